@@ -22,7 +22,7 @@ fn main_from_docs() {
 ### SimpleGet
 GET {{HOST}}/get HTTP/1.1"#;
 
-    let RestFormat { requests, variables, flavor } = RestFormat::parse(
+    let RestFormat { requests, variables, flavor, .. } = RestFormat::parse(
         rest_data,
         // Normally, the flavor is determined by the file extension.
         RestFlavor::Jetbrains
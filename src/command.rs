@@ -0,0 +1,123 @@
+//! Typed request commands: `# @no-log`, `# @timeout 30 s`
+//! Commands are stored as written in `RestRequest::commands`,
+//! use `RestRequest::parsed_commands` to get them as `Command`s
+
+use std::time::Duration;
+
+use crate::RestRequest;
+
+/// A single `# @command` of a request
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command {
+    /// `# @no-log`
+    NoLog,
+    /// `# @no-redirect`
+    NoRedirect,
+    /// `# @no-cookie-jar`
+    NoCookieJar,
+    /// `# @timeout 30 s`
+    Timeout(Duration),
+    /// `# @connection-timeout 2 m`
+    ConnectionTimeout(Duration),
+    /// `# @name GetUser`, normally parsed into `RestRequest::name` instead
+    Name(String),
+    /// Any other command, or a known command with parameters that don't parse
+    Other {
+        name: String,
+        params: Option<String>,
+    },
+}
+
+impl Command {
+    /// Parse a command from its name and parameters
+    pub fn parse(name: &str, params: Option<&str>) -> Self {
+        let params_trimmed = params.map(str::trim).filter(|params| !params.is_empty());
+        let command = match (name, params_trimmed) {
+            ("no-log", None) => Some(Self::NoLog),
+            ("no-redirect", None) => Some(Self::NoRedirect),
+            ("no-cookie-jar", None) => Some(Self::NoCookieJar),
+            ("timeout", Some(value)) => parse_duration(value).map(Self::Timeout),
+            ("connection-timeout", Some(value)) => parse_duration(value).map(Self::ConnectionTimeout),
+            ("name", Some(value)) => Some(Self::Name(value.into())),
+            _ => None,
+        };
+        command.unwrap_or(Self::Other {
+            name: name.into(),
+            params: params.map(String::from),
+        })
+    }
+}
+
+/// A duration in seconds or with a unit: `300`, `30 s`, `2 m`, `500 ms`
+/// Units are `ms`, `s`, `m` and `h`
+fn parse_duration(input: &str) -> Option<Duration> {
+    let split_at = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split_at);
+    let amount: u64 = amount.parse().ok()?;
+    match unit.trim() {
+        "" | "s" => Some(Duration::from_secs(amount)),
+        "ms" => Some(Duration::from_millis(amount)),
+        "m" => Some(Duration::from_secs(amount * 60)),
+        "h" => Some(Duration::from_secs(amount * 60 * 60)),
+        _ => None,
+    }
+}
+
+impl RestRequest {
+    /// The request's commands as `Command`s, in order
+    pub fn parsed_commands(&self) -> Vec<Command> {
+        self.commands
+            .iter()
+            .map(|(name, params)| Command::parse(name, params.as_deref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RestFlavor, RestFormat};
+    use indoc::indoc;
+
+    #[test]
+    fn parsed_commands_test() {
+        let text = indoc! {r#"
+            ### Commands @no-log
+            # @no-redirect
+            # @no-cookie-jar
+            # @timeout 300
+            # @connection-timeout 2 m
+            # @retries 3
+            # @timeout soon
+            GET https://example.com HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests[0].parsed_commands(), vec![
+            Command::NoLog,
+            Command::NoRedirect,
+            Command::NoCookieJar,
+            // The last `timeout` wins and it doesn't parse
+            Command::Other { name: "timeout".into(), params: Some("soon".into()) },
+            Command::ConnectionTimeout(Duration::from_secs(120)),
+            Command::Other { name: "retries".into(), params: Some("3".into()) },
+        ]);
+    }
+
+    #[test]
+    fn parse_duration_test() {
+        assert_eq!(parse_duration("300"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("30 s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2 m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1 h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("2 d"), None);
+        assert_eq!(parse_duration("m"), None);
+
+        assert_eq!(Command::parse("name", Some("GetUser")), Command::Name("GetUser".into()));
+        assert_eq!(
+            Command::parse("no-log", Some("please")),
+            Command::Other { name: "no-log".into(), params: Some("please".into()) },
+        );
+    }
+}
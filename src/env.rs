@@ -0,0 +1,106 @@
+//! Parses Jetbrains `http-client.env.json` environment files
+//! Each top level key is an environment (`dev`, `prod`, etc.)
+//! containing the variables for that environment
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use indexmap::IndexMap;
+use serde_json::Value;
+
+use crate::template::Template;
+use crate::RestVariables;
+
+/// Environment names mapped to their variables
+pub type RestEnvironments = IndexMap<String, RestVariables>;
+
+/// Parse the text of an environment file into environments
+pub fn parse_environments(text: &str) -> anyhow::Result<RestEnvironments> {
+    let raw: IndexMap<String, IndexMap<String, Value>> = serde_json::from_str(text)
+        .context("Invalid environment file")?;
+
+    let environments = raw
+        .into_iter()
+        .map(|(env_name, values)| {
+            let variables = values
+                .into_iter()
+                .map(|(name, value)| (name, value_to_template(value)))
+                .collect();
+            (env_name, variables)
+        })
+        .collect();
+
+    Ok(environments)
+}
+
+/// The name of the environment file Jetbrains looks for next to `.http` files
+pub const ENV_FILE_NAME: &str = "http-client.env.json";
+
+/// Load a single environment from the `http-client.env.json` in `dir`
+/// A missing environment file is not an error and loads no variables
+pub fn load_environment(dir: impl AsRef<Path>, env_name: &str) -> anyhow::Result<RestVariables> {
+    let path = dir.as_ref().join(ENV_FILE_NAME);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(RestVariables::new()),
+        Err(err) => return Err(err).context(format!("Error reading environment file {path:?}")),
+    };
+
+    let mut environments = parse_environments(&text)
+        .context(format!("Error parsing environment file {path:?}"))?;
+    environments.swap_remove(env_name).ok_or_else(|| {
+        let names: Vec<_> = environments.keys().map(String::as_str).collect();
+        anyhow!("Environment {env_name:?} not found in {path:?}, expected one of: {}", names.join(", "))
+    })
+}
+
+/// Env values may be numbers or booleans, not just strings
+/// Stringify them so they can be used like any other variable
+fn value_to_template(value: Value) -> Template {
+    match value {
+        Value::String(text) => Template::new(&text),
+        Value::Null => Template::new(""),
+        other => Template::new(&other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn parse_environments_test() {
+        let text = indoc! {r#"
+            {
+                "dev": {
+                    "host": "localhost",
+                    "port": 8080,
+                    "ratio": 0.5,
+                    "secure": false,
+                    "url": "http://{{host}}:{{port}}"
+                },
+                "prod": {
+                    "host": "example.com",
+                    "secure": true
+                }
+            }
+        "#};
+        let environments = parse_environments(text).unwrap();
+        assert_eq!(environments.keys().collect::<Vec<_>>(), vec!["dev", "prod"]);
+
+        let dev = environments.get("dev").unwrap();
+        assert_eq!(dev.get("host").unwrap().raw, "localhost");
+        assert_eq!(dev.get("port").unwrap().raw, "8080");
+        assert_eq!(dev.get("ratio").unwrap().raw, "0.5");
+        assert_eq!(dev.get("secure").unwrap().raw, "false");
+        assert_eq!(dev.get("url").unwrap().variable_names(), vec!["host", "port"]);
+
+        let prod = environments.get("prod").unwrap();
+        assert_eq!(prod.get("secure").unwrap().raw, "true");
+
+        assert!(parse_environments("[1, 2]").is_err());
+    }
+}
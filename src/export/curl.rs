@@ -0,0 +1,341 @@
+//! Export a request as a `curl` command or a whole file as a bash script of them
+//! Every argument is single quoted so it can be pasted into a POSIX shell
+
+use url::form_urlencoded;
+
+use crate::headers::{Authorization, AUTHORIZATION_HEADER};
+use crate::parser::{MultipartContent, MultipartPart};
+use crate::template::Template;
+use crate::{Body, RestFormat, RestRequest, RestVariables};
+
+const CONTENT_LENGTH: &str = "Content-Length";
+const CONTENT_TYPE: &str = "Content-Type";
+const LINE_CONTINUATION: &str = " \\\n  ";
+/// Marks where a file variable is used so it can be swapped for a shell variable
+const SHELL_VARIABLE_MARKER: char = '\u{1}';
+
+impl RestFormat {
+    /// Render the whole file as a bash script
+    /// The file variables are assigned once as shell variables and the requests use them,
+    /// each request is a `curl` command under a comment with its name
+    /// Variables declared inside a request are rendered into its command
+    pub fn to_curl_script(&self) -> String {
+        let mut script = String::from("#!/usr/bin/env bash\n");
+
+        let mut shell_vars = RestVariables::new();
+        let mut shell_names = vec![];
+        if !self.variables.is_empty() {
+            script += "\n";
+        }
+        for (name, value) in &self.variables {
+            let shell_name = shell_variable_name(name, &shell_names);
+            shell_names.push(shell_name.clone());
+            script += &format!("{shell_name}={}\n", shell_quote(&value.render(&self.variables)));
+            let marker = format!("{SHELL_VARIABLE_MARKER}{shell_name}{SHELL_VARIABLE_MARKER}");
+            shell_vars.insert(name.clone(), Template::new(&marker));
+        }
+
+        for (index, request) in self.requests.iter().enumerate() {
+            let mut vars = shell_vars.clone();
+            vars.extend(request.variables.clone());
+            let name = request.name.clone().unwrap_or(format!("Request {}", index + 1));
+            let command = use_shell_variables(&request.to_curl(&vars));
+            script += &format!("\n# {name}\n{command}\n");
+        }
+        script
+    }
+}
+
+impl RestRequest {
+    /// Render the request as a `curl` command with the variables filled in
+    /// The query is percent-encoded and the fragment is kept
+    /// Basic auth becomes `-u user:pass` and other authorization an `Authorization` header
+    /// A body loaded from a file is sent with `--data-binary @file` (variables in it aren't rendered)
+    /// and a response saved to a file becomes `-o file`
+    pub fn to_curl(&self, vars: &RestVariables) -> String {
+        let render = |value: &str| Template::new(value).render(vars);
+
+        let mut url = self.url.render(vars);
+        let query = self.query
+            .iter()
+            .map(|(key, value)| format!("{}={}", encode_query(key), encode_query(&value.render(vars))))
+            .collect::<Vec<String>>()
+            .join("&");
+        if !query.is_empty() {
+            url = format!("{url}?{query}");
+        }
+        if let Some(fragment) = &self.fragment {
+            url = format!("{url}#{}", fragment.render(vars));
+        }
+
+        let mut args = vec![
+            format!("-X {}", self.method.render(vars)),
+            shell_quote(&url),
+        ];
+
+        // curl picks its own boundary for `-F`
+        let multipart = matches!(self.body, Some(Body::Multipart { .. }));
+        for (name, value) in &self.headers {
+            let skip_content_type = multipart && name.eq_ignore_ascii_case(CONTENT_TYPE);
+            if !name.eq_ignore_ascii_case(CONTENT_LENGTH) && !skip_content_type {
+                args.push(format!("-H {}", shell_quote(&format!("{name}: {}", value.render(vars)))));
+            }
+        }
+
+        match &self.authorization {
+            Some(Authorization::Basic { username, password }) => {
+                let password = password.as_deref().map(render).unwrap_or_default();
+                args.push(format!("-u {}", shell_quote(&format!("{}:{password}", render(username)))));
+            }
+            Some(authorization) => {
+                let value = render(&authorization.to_header());
+                if !value.is_empty() {
+                    args.push(format!("-H {}", shell_quote(&format!("{AUTHORIZATION_HEADER}: {value}"))));
+                }
+            }
+            None => {}
+        }
+
+        match &self.body {
+            Some(Body::Text(text) | Body::Ndjson(text)) => args.push(data_arg(&text.render(vars))),
+            Some(body @ Body::GraphQL { .. }) => {
+                args.push(data_arg(&body.body_text(vars).unwrap_or_default()))
+            }
+            Some(Body::Multipart { parts, .. }) => {
+                args.extend(parts.iter().map(|part| form_arg(part, vars)));
+            }
+            Some(Body::LoadFromFile { filepath, .. }) => {
+                args.push(format!("--data-binary {}", shell_quote(&format!("@{}", filepath.render(vars)))));
+            }
+            Some(Body::SaveToFile { text, filepath }) => {
+                let text = text.render(vars);
+                if !text.is_empty() {
+                    args.push(data_arg(&text));
+                }
+                args.push(format!("-o {}", shell_quote(&filepath.render(vars))));
+            }
+            None => {}
+        }
+
+        format!("curl {}", args.join(LINE_CONTINUATION))
+    }
+}
+
+/// `--data` reads a file when the value starts with `@`, `--data-raw` never does
+fn data_arg(body: &str) -> String {
+    let body = body.replace("\r\n", "\n");
+    match body.starts_with('@') {
+        true => format!("--data-raw {}", shell_quote(&body)),
+        false => format!("--data {}", shell_quote(&body)),
+    }
+}
+
+/// Percent-encode a query key or value like `RestRequest::rendered_url`
+/// Shell variable markers are kept so the value still comes from the variable
+fn encode_query(value: &str) -> String {
+    value
+        .split(SHELL_VARIABLE_MARKER)
+        .enumerate()
+        .map(|(index, part)| match index % 2 {
+            0 => form_urlencoded::byte_serialize(part.as_bytes()).collect(),
+            _ => format!("{SHELL_VARIABLE_MARKER}{part}{SHELL_VARIABLE_MARKER}"),
+        })
+        .collect()
+}
+
+/// Wrap a value in single quotes, nothing inside them is special to the shell
+/// A single quote is closed, escaped and reopened: `it's` -> `'it'\''s'`
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Swap the markers for shell variables, every marker is inside a single quoted argument
+/// `'https://\u{1}HOST\u{1}/a'` -> `'https://'"${HOST}"'/a'`
+fn use_shell_variables(command: &str) -> String {
+    let mut parts = command.split(SHELL_VARIABLE_MARKER);
+    let mut replaced = parts.next().unwrap_or_default().to_string();
+    while let (Some(name), Some(rest)) = (parts.next(), parts.next()) {
+        replaced += &format!("'\"${{{name}}}\"'{rest}");
+    }
+    replaced
+}
+
+/// Variables like `api-key` aren't valid shell names: `api_key`
+/// A name that's already `taken` gets a number: `api-key` and `api_key` -> `api_key` and `api_key_2`
+fn shell_variable_name(name: &str, taken: &[String]) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let name = match name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        true => format!("_{name}"),
+        false => name,
+    };
+    let mut unique = name.clone();
+    let mut count = 1;
+    while taken.contains(&unique) {
+        count += 1;
+        unique = format!("{name}_{count}");
+    }
+    unique
+}
+
+/// A multipart part as a form argument
+/// Inline content uses `--form-string` so a leading `@` or `<` isn't read as a file
+fn form_arg(part: &MultipartPart, vars: &RestVariables) -> String {
+    match &part.content {
+        MultipartContent::Text(text) => {
+            format!("--form-string {}", shell_quote(&format!("{}={}", part.name, text.render(vars))))
+        }
+        MultipartContent::LoadFromFile { filepath, .. } => {
+            let mut value = format!("{}=@{}", part.name, filepath.render(vars));
+            if let Some(filename) = &part.filename {
+                value += &format!(";filename={filename}");
+            }
+            if let Some(content_type) = part.content_type() {
+                value += &format!(";type={}", content_type.render(vars));
+            }
+            format!("-F {}", shell_quote(&value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RestFlavor, RestFormat};
+    use indoc::indoc;
+
+    #[test]
+    fn to_curl_test() {
+        let text = indoc! {r#"
+            @HOST = https://example.com
+            @token = abc123
+
+            ### Create
+            POST {{HOST}}/users?page=1&tags[]=a&q=a%20b#top HTTP/1.1
+            Content-Type: application/json
+            Authorization: Bearer {{token}}
+
+            {"name": "Ben's \"laptop\""}
+
+            >> ./created.json
+
+            ### Basic
+            GET {{HOST}}/basic HTTP/1.1
+            Authorization: Basic dXNlcjpwYXNz
+
+            ### Upload
+            PUT {{HOST}}/upload HTTP/1.1
+
+            < ./data.bin
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let vars = &format.variables;
+
+        assert_eq!(format.requests[0].to_curl(vars), indoc! {r#"
+            curl -X POST \
+              'https://example.com/users?page=1&tags%5B%5D=a&q=a+b#top' \
+              -H 'Content-Type: application/json' \
+              -H 'Authorization: Bearer abc123' \
+              --data '{"name": "Ben'\''s \"laptop\""}' \
+              -o './created.json'"#
+        });
+        assert_eq!(format.requests[1].to_curl(vars), indoc! {r#"
+            curl -X GET \
+              'https://example.com/basic' \
+              -u 'user:pass'"#
+        });
+        assert!(format.requests[2].to_curl(vars).ends_with("--data-binary '@./data.bin'"));
+
+        // The command can be imported again
+        let imported = RestRequest::from_curl(&format.requests[1].to_curl(vars)).unwrap();
+        assert_eq!(imported.url.raw, "https://example.com/basic");
+        assert_eq!(imported.authorization, format.requests[1].authorization);
+    }
+
+    #[test]
+    fn to_curl_multipart_test() {
+        let text = indoc! {r#"
+            POST https://example.com/upload HTTP/1.1
+            Content-Type: multipart/form-data; boundary=b
+
+            --b
+            Content-Disposition: form-data; name="title"
+
+            @home
+            --b
+            Content-Disposition: form-data; name="data"; filename="data.json"
+            Content-Type: application/json
+
+            < ./data.json
+            --b--
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Generic).unwrap();
+        assert_eq!(format.requests[0].to_curl(&format.variables), indoc! {r#"
+            curl -X POST \
+              'https://example.com/upload' \
+              --form-string 'title=@home' \
+              -F 'data=@./data.json;filename=data.json;type=application/json'"#
+        });
+    }
+
+    #[test]
+    fn to_curl_script_test() {
+        let text = indoc! {r#"
+            @HOST = https://{{domain}}
+            @domain = example.com
+            @api-key = it's secret
+
+            ### List
+            GET {{HOST}}/users?key={{api-key}} HTTP/1.1
+
+            ###
+            @id = 42
+            DELETE {{HOST}}/users/{{id}} HTTP/1.1
+            Authorization: Bearer {{api-key}}
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Generic).unwrap();
+        let script = format.to_curl_script();
+        assert_eq!(script, indoc! {r#"
+            #!/usr/bin/env bash
+
+            HOST='https://example.com'
+            domain='example.com'
+            api_key='it'\''s secret'
+            id='42'
+
+            # List
+            curl -X GET \
+              ''"${HOST}"'/users?key='"${api_key}"''
+
+            # Request 2
+            curl -X DELETE \
+              ''"${HOST}"'/users/'"${id}"'' \
+              -H 'Authorization: Bearer '"${api_key}"''
+        "#});
+        assert_eq!(script.lines().filter(|line| line.starts_with("curl ")).count(), 2);
+    }
+
+    #[test]
+    fn shell_variable_name_test() {
+        assert_eq!(shell_variable_name("api-key", &[]), "api_key");
+        assert_eq!(shell_variable_name("1st", &[]), "_1st");
+
+        let text = indoc! {r#"
+            @api-key = dash
+            @api_key = underscore
+
+            GET https://example.com/?a={{api-key}}&b={{api_key}} HTTP/1.1
+        "#};
+        let script = RestFormat::parse(text, RestFlavor::Generic).unwrap().to_curl_script();
+        assert!(script.contains("api_key='dash'\napi_key_2='underscore'\n"));
+        assert!(script.contains("?a='\"${api_key}\"'&b='\"${api_key_2}\"''"));
+    }
+
+    #[test]
+    fn data_arg_test() {
+        assert_eq!(data_arg("a=1\r\nb=2"), "--data 'a=1\nb=2'");
+        assert_eq!(data_arg("@handle"), "--data-raw '@handle'");
+    }
+}
@@ -0,0 +1,160 @@
+//! Export a request as a JavaScript `fetch()` call for the browser
+
+use crate::headers::ContentType;
+use crate::template::Template;
+use crate::{Body, RestRequest, RestVariables};
+
+const CONTENT_LENGTH: &str = "Content-Length";
+const CONTENT_TYPE: &str = "Content-Type";
+
+impl RestRequest {
+    /// Render the request as a `fetch()` call with the variables filled in
+    /// The URL is `rendered_url` with its percent-encoded query
+    /// A JSON body is passed to `JSON.stringify`, any other body is a string
+    /// Bodies that need a file can't be inlined so they're left as a comment
+    pub fn to_fetch_js(&self, vars: &RestVariables) -> String {
+        let url = self.rendered_url(vars);
+
+        let mut headers: Vec<(String, String)> = self.headers
+            .iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case(CONTENT_LENGTH))
+            .map(|(name, value)| (name.clone(), value.render(vars)))
+            .collect();
+        if let Some((name, value)) = self.authorization_header() {
+            headers.push((name, Template::new(&value).render(vars)));
+        }
+        let is_json = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(CONTENT_TYPE))
+            .map(|(_, value)| ContentType::parse(value))
+            .is_some_and(|content_type| {
+                content_type.matches("application/json") || content_type.essence.ends_with("+json")
+            });
+
+        let mut options = vec![format!("method: {}", js_string(&self.method.render(vars)))];
+        if !headers.is_empty() {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| format!("    {}: {}", js_string(name), js_string(value)))
+                .collect::<Vec<String>>()
+                .join(",\n");
+            options.push(format!("headers: {{\n{headers}\n  }}"));
+        }
+
+        let mut comment = None;
+        match self.body.as_ref().map(|body| (body, body.body_text(vars))) {
+            Some((_, Some(text))) if text.trim().is_empty() => {}
+            Some((_, Some(text))) if is_json => {
+                options.push(format!("body: JSON.stringify({})", text.trim()))
+            }
+            Some((_, Some(text))) => options.push(format!("body: {}", js_string(&text))),
+            Some((Body::LoadFromFile { filepath, .. }, None)) => {
+                comment = Some(format!("// The body is loaded from {}\n", filepath.render(vars)))
+            }
+            Some((_, None)) => comment = Some("// The body loads parts from files\n".to_string()),
+            None => {}
+        }
+
+        let options = options
+            .iter()
+            .map(|option| format!("  {option}"))
+            .collect::<Vec<String>>()
+            .join(",\n");
+        format!("{}fetch({}, {{\n{options}\n}});", comment.unwrap_or_default(), js_string(&url))
+    }
+}
+
+/// Quote a value as a double quoted JavaScript string
+fn js_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            // Line terminators and control characters can't appear in a string literal
+            '\u{2028}' | '\u{2029}' => quoted += &format!("\\u{:04x}", c as u32),
+            c if c.is_control() => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RestFlavor, RestFormat};
+    use indoc::indoc;
+
+    #[test]
+    fn to_fetch_js_test() {
+        let text = indoc! {r#"
+            @HOST = https://example.com
+            @token = abc123
+
+            ### List
+            @term = a&b c
+            GET {{HOST}}/users?page=1&tags[]=a&q={{term}} HTTP/1.1
+            Accept: application/json
+
+            ### Create
+            POST {{HOST}}/users HTTP/1.1
+            Content-Type: application/json
+            Content-Length: 999
+            Authorization: Bearer {{token}}
+
+            {"name": "Ben"}
+
+            ### Note
+            PUT {{HOST}}/notes/1 HTTP/1.1
+            Content-Type: text/plain
+
+            Say "hi"
+            twice
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Generic).unwrap();
+        let vars = &format.variables;
+
+        assert_eq!(format.requests[0].to_fetch_js(vars), indoc! {r#"
+            fetch("https://example.com/users?page=1&tags%5B%5D=a&q=a%26b+c", {
+              method: "GET",
+              headers: {
+                "Accept": "application/json"
+              }
+            });"#
+        });
+        assert_eq!(format.requests[1].to_fetch_js(vars), indoc! {r#"
+            fetch("https://example.com/users", {
+              method: "POST",
+              headers: {
+                "Content-Type": "application/json",
+                "Authorization": "Bearer abc123"
+              },
+              body: JSON.stringify({"name": "Ben"})
+            });"#
+        });
+        assert!(format.requests[2].to_fetch_js(vars).contains(r#"body: "Say \"hi\"\r\ntwice""#));
+    }
+
+    #[test]
+    fn to_fetch_js_file_body_test() {
+        let text = "POST https://example.com/upload HTTP/1.1\n\n< ./data.bin";
+        let format = RestFormat::parse(text, RestFlavor::Generic).unwrap();
+        assert_eq!(format.requests[0].to_fetch_js(&format.variables), indoc! {r#"
+            // The body is loaded from ./data.bin
+            fetch("https://example.com/upload", {
+              method: "POST"
+            });"#
+        });
+    }
+
+    #[test]
+    fn js_string_test() {
+        assert_eq!(js_string(r#"a "b" \ c"#), r#""a \"b\" \\ c""#);
+        assert_eq!(js_string("line\u{2028}\u{7}"), r#""line\u2028\u0007""#);
+    }
+}
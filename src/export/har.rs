@@ -0,0 +1,207 @@
+//! Export to a HAR (HTTP Archive) log
+//! Only the requests are known, so each entry has an empty response
+
+use serde_json::{json, Value};
+
+use crate::parser::{MultipartContent, MultipartPart};
+use crate::template::Template;
+use crate::{Body, RestFormat, RestRequest, RestVariables};
+
+const HAR_VERSION: &str = "1.2";
+const HTTP_VERSION: &str = "HTTP/1.1";
+
+impl RestFormat {
+    /// Export the requests as a HAR log with every template rendered
+    /// `vars` fill in variables the file doesn't define, the file and request variables take precedence
+    pub fn to_har(&self, vars: &RestVariables) -> Value {
+        let started = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let entries: Vec<Value> = self
+            .iter_with_variables()
+            .map(|(request, variables)| {
+                let mut context = vars.clone();
+                context.extend(variables.into_owned());
+                json!({
+                    "startedDateTime": started,
+                    "time": 0,
+                    "request": har_request(request, &context),
+                    "response": empty_response(),
+                    "cache": {},
+                    "timings": { "send": 0, "wait": 0, "receive": 0 },
+                })
+            })
+            .collect();
+
+        json!({
+            "log": {
+                "version": HAR_VERSION,
+                "creator": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "entries": entries,
+            }
+        })
+    }
+}
+
+fn har_request(request: &RestRequest, vars: &RestVariables) -> Value {
+    let query: Vec<(String, String)> = request.query
+        .iter()
+        .map(|(name, value)| (name.clone(), value.render(vars)))
+        .collect();
+
+    // A HAR url has the percent-encoded query and no fragment
+    let rendered_url = request.rendered_url(vars);
+    let url = rendered_url.split('#').next().unwrap_or_default();
+
+    let mut headers: Vec<(String, String)> = request.headers
+        .iter()
+        .map(|(name, value)| (name.clone(), value.render(vars)))
+        .collect();
+    if let Some((name, value)) = request.authorization_header() {
+        headers.push((name, Template::new(&value).render(vars)));
+    }
+    let mime_type = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        .map(|(_, value)| value.clone())
+        .unwrap_or_default();
+
+    let post_data = match &request.body {
+        Some(Body::Text(text) | Body::Ndjson(text) | Body::SaveToFile { text, .. }) => Some(json!({
+            "mimeType": mime_type,
+            "text": text.render(vars),
+        })),
+        Some(body @ Body::GraphQL { .. }) => Some(json!({
+            "mimeType": mime_type,
+            "text": body.body_text(vars).unwrap_or_default(),
+        })),
+        Some(body @ Body::Multipart { parts, .. }) => {
+            let params: Vec<Value> = parts.iter().map(|part| har_param(part, vars)).collect();
+            Some(json!({
+                "mimeType": mime_type,
+                "params": params,
+                "text": body.body_text(vars).unwrap_or_default(),
+            }))
+        }
+        // The file isn't read, it's noted in the comment
+        Some(Body::LoadFromFile { filepath, .. }) => Some(json!({
+            "mimeType": mime_type,
+            "text": "",
+            "comment": format!("Loaded from {}", filepath.render(vars)),
+        })),
+        None => None,
+    };
+    let body_size = post_data
+        .as_ref()
+        .and_then(|post_data| post_data["text"].as_str())
+        .map(str::len)
+        .unwrap_or(0);
+
+    let name_values = |pairs: &[(String, String)]| -> Vec<Value> {
+        pairs
+            .iter()
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect()
+    };
+
+    let mut har = json!({
+        "method": request.method.render(vars),
+        "url": url,
+        "httpVersion": request.http_version
+            .as_ref()
+            .map_or(HTTP_VERSION.to_string(), |version| format!("HTTP/{version}")),
+        "cookies": [],
+        "headers": name_values(&headers),
+        "queryString": name_values(&query),
+        "headersSize": -1,
+        "bodySize": body_size,
+    });
+    if let Some(post_data) = post_data {
+        har["postData"] = post_data;
+    }
+    if let Some(name) = &request.name {
+        har["comment"] = json!(name);
+    }
+    har
+}
+
+fn empty_response() -> Value {
+    json!({
+        "status": 0,
+        "statusText": "",
+        "httpVersion": HTTP_VERSION,
+        "cookies": [],
+        "headers": [],
+        "content": { "size": 0, "mimeType": "" },
+        "redirectURL": "",
+        "headersSize": -1,
+        "bodySize": -1,
+    })
+}
+
+/// A multipart part as a HAR param, files keep their path as the `fileName`
+fn har_param(part: &MultipartPart, vars: &RestVariables) -> Value {
+    let mut param = json!({ "name": part.name });
+    match &part.content {
+        MultipartContent::Text(text) => param["value"] = json!(text.render(vars)),
+        MultipartContent::LoadFromFile { filepath, .. } => {
+            let filename = part.filename.clone().unwrap_or(filepath.render(vars));
+            param["fileName"] = json!(filename);
+        }
+    }
+    if let Some(content_type) = part.content_type() {
+        param["contentType"] = json!(content_type.render(vars));
+    }
+    param
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_har_test() {
+        let format = RestFormat::parse_file("test_data/http_bin.http").unwrap();
+        let vars = RestVariables::from_iter([("HOST".to_string(), Template::new("https://ignored.com"))]);
+        let har = format.to_har(&vars);
+
+        assert_eq!(har["log"]["version"], "1.2");
+        assert_eq!(har["log"]["creator"]["name"], "rest_parser");
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), format.requests.len());
+        for entry in entries {
+            assert!(entry["startedDateTime"].is_string());
+            assert!(entry["response"]["status"].is_number());
+            let request = &entry["request"];
+            for field in ["method", "url", "httpVersion"] {
+                assert!(request[field].is_string(), "{field} is missing");
+            }
+            for field in ["headers", "queryString", "cookies"] {
+                assert!(request[field].is_array(), "{field} is missing");
+            }
+        }
+
+        let simple_get = &entries[0]["request"];
+        assert_eq!(simple_get["method"], "GET");
+        // The file variable wins
+        assert_eq!(simple_get["url"], "http://httpbin.org/get");
+        assert!(simple_get.get("postData").is_none());
+        assert_eq!(simple_get["comment"], "SimpleGet");
+
+        let json_post = &entries[1]["request"];
+        assert_eq!(json_post["url"], "http://httpbin.org/post?hello=123");
+        assert_eq!(json_post["queryString"], json!([{ "name": "hello", "value": "123" }]));
+        assert_eq!(json_post["postData"]["mimeType"], "application/json");
+        assert!(json_post["postData"]["text"].as_str().unwrap().contains("\"name\": \"Joe Smith\""));
+        let headers = json_post["headers"].as_array().unwrap();
+        assert!(headers.contains(&json!({ "name": "Authorization", "value": "Basic Zm9vOmJhcg==" })));
+
+        // The url has the percent-encoded query, `queryString` the decoded values
+        let format = RestFormat::parse("GET https://example.com/a?q={{term}}&tags[]=x#top HTTP/1.1", crate::RestFlavor::Generic).unwrap();
+        let vars = RestVariables::from_iter([("term".to_string(), Template::new("a b&c"))]);
+        let request = &format.to_har(&vars)["log"]["entries"][0]["request"];
+        assert_eq!(request["url"], "https://example.com/a?q=a+b%26c&tags%5B%5D=x");
+        assert_eq!(request["queryString"][0], json!({ "name": "q", "value": "a b&c" }));
+    }
+}
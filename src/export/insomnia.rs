@@ -0,0 +1,215 @@
+//! Export to the Insomnia v4 format
+//! Requests become request resources in a workspace
+//! and the file variables become the base environment
+
+use serde_json::{json, Value};
+
+use crate::headers::Authorization;
+use crate::parser::{graphql_text, MultipartContent};
+use crate::template::{Template, TemplatePart};
+use crate::{Body, RestFormat, RestRequest, RestVariables};
+
+const WORKSPACE_ID: &str = "wrk_rest_parser";
+const ENVIRONMENT_ID: &str = "env_rest_parser";
+
+impl RestFormat {
+    /// Export the requests as an Insomnia v4 export
+    pub fn to_insomnia(&self) -> Value {
+        let environment: serde_json::Map<String, Value> = self.variables
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::String(insomnia_template(value))))
+            .collect();
+
+        let mut resources = vec![
+            json!({
+                "_id": WORKSPACE_ID,
+                "_type": "workspace",
+                "name": "rest_parser",
+            }),
+            json!({
+                "_id": ENVIRONMENT_ID,
+                "_type": "environment",
+                "parentId": WORKSPACE_ID,
+                "name": "Base Environment",
+                "data": environment,
+            }),
+        ];
+        resources.extend(
+            self.requests
+                .iter()
+                .enumerate()
+                .map(|(index, request)| insomnia_request(index, request, &request.variables)),
+        );
+
+        json!({
+            "_type": "export",
+            "__export_format": 4,
+            "__export_source": "rest_parser",
+            "resources": resources,
+        })
+    }
+}
+
+fn insomnia_request(index: usize, request: &RestRequest, variables: &RestVariables) -> Value {
+    // Insomnia has no request scoped variables, render them into the request
+    let mut request = request.clone();
+    for template in request.templates_mut() {
+        *template = template.render_partial(variables);
+    }
+
+    let name = request.name.clone().unwrap_or(format!("Request {}", index + 1));
+    let headers: Vec<Value> = request.headers
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": insomnia_template(value) }))
+        .collect();
+    let parameters: Vec<Value> = request.query
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": insomnia_template(value) }))
+        .collect();
+    let mime_type = request.headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        .map(|(_, value)| value.raw.clone())
+        .unwrap_or_default();
+
+    let body = match &request.body {
+        Some(Body::Text(text) | Body::Ndjson(text) | Body::SaveToFile { text, .. }) => {
+            json!({ "mimeType": mime_type, "text": insomnia_template(text) })
+        }
+        Some(Body::GraphQL { query, variables }) => {
+            let variables = variables.as_ref().map(insomnia_template);
+            json!({ "mimeType": mime_type, "text": graphql_text(&insomnia_template(query), variables.as_deref()) })
+        }
+        Some(Body::Multipart { parts, .. }) => {
+            let params: Vec<Value> = parts
+                .iter()
+                .map(|part| match &part.content {
+                    MultipartContent::Text(text) => {
+                        json!({ "name": part.name, "value": insomnia_template(text) })
+                    }
+                    MultipartContent::LoadFromFile { filepath, .. } => {
+                        json!({ "name": part.name, "type": "file", "fileName": insomnia_template(filepath) })
+                    }
+                })
+                .collect();
+            json!({ "mimeType": mime_type, "params": params })
+        }
+        Some(Body::LoadFromFile { filepath, .. }) => {
+            json!({ "mimeType": mime_type, "fileName": insomnia_template(filepath) })
+        }
+        None => json!({}),
+    };
+
+    let authentication = match &request.authorization {
+        Some(Authorization::Bearer(token)) => json!({
+            "type": "bearer",
+            "token": insomnia_template(&Template::new(token)),
+        }),
+        Some(Authorization::Basic { username, password }) => json!({
+            "type": "basic",
+            "username": username,
+            "password": password.clone().unwrap_or_default(),
+        }),
+        Some(Authorization::OAuth2 { token, config }) => {
+            let field = |value: &Option<Template>| {
+                value.as_ref().map(insomnia_template).unwrap_or_default()
+            };
+            json!({
+                "type": "oauth2",
+                "accessToken": field(token),
+                "accessTokenUrl": field(&config.token_url),
+                "clientId": field(&config.client_id),
+                "clientSecret": field(&config.client_secret),
+                "scope": field(&config.scope),
+                "grantType": field(&config.grant_type),
+            })
+        }
+        // A raw header, a precomputed digest, another scheme or several schemes can't be mapped onto an authentication type
+        Some(
+            Authorization::Raw(_)
+            | Authorization::Digest { .. }
+            | Authorization::Other { .. }
+            | Authorization::Multiple(_),
+        )
+        | None => {
+            json!({})
+        }
+    };
+
+    let mut headers = headers;
+    match &request.authorization {
+        Some(Authorization::Raw(value)) => {
+            headers.push(json!({ "name": "Authorization", "value": insomnia_template(value) }));
+        }
+        Some(auth @ (Authorization::Digest { .. } | Authorization::Other { .. } | Authorization::Multiple(_))) => {
+            let value = insomnia_template(&Template::new(&auth.to_header()));
+            headers.push(json!({ "name": "Authorization", "value": value }));
+        }
+        _ => {}
+    }
+
+    json!({
+        "_id": format!("req_rest_parser_{}", index + 1),
+        "_type": "request",
+        "parentId": WORKSPACE_ID,
+        "name": name,
+        "method": request.method.raw,
+        "url": insomnia_template(&request.url),
+        "parameters": parameters,
+        "headers": headers,
+        "body": body,
+        "authentication": authentication,
+    })
+}
+
+/// Insomnia reads environment variables with `{{ _.name }}`
+fn insomnia_template(template: &Template) -> String {
+    template.parts
+        .iter()
+        .map(|part| match part {
+            TemplatePart::Text(text) => text.clone(),
+            TemplatePart::Variable { name, .. } => format!("{{{{ _.{name} }}}}"),
+            other @ (TemplatePart::System { .. } | TemplatePart::Response { .. }) => other.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_insomnia_test() {
+        let format = RestFormat::parse_file("test_data/jetbrains.http").unwrap();
+        let export = format.to_insomnia();
+        assert_eq!(export["__export_format"], 4);
+
+        let resources = export["resources"].as_array().unwrap();
+        // The workspace, the environment and six requests
+        assert_eq!(resources.len(), 8);
+        assert_eq!(resources[1]["data"]["HOST"], "http://httpbin.org");
+
+        let first = &resources[2];
+        assert_eq!(first["_type"], "request");
+        assert_eq!(first["method"], "GET");
+        assert_eq!(first["name"], "SimpleGet");
+        assert_eq!(first["url"], "{{ _.HOST }}/get");
+
+        let json_post = &resources[3];
+        assert_eq!(json_post["authentication"]["type"], "basic");
+        assert_eq!(json_post["authentication"]["username"], "foo");
+        assert_eq!(json_post["body"]["mimeType"], "application/json");
+
+        // File variables are environment variables
+        let with_query = &resources[5];
+        assert_eq!(with_query["parameters"][1]["value"], "{{ _.sort_by }}");
+
+        // Request variables are rendered into the request
+        let options = crate::ParseOptions { request_variables: true, ..Default::default() };
+        let format = RestFormat::parse_file_with_options("test_data/jetbrains.http", &options).unwrap();
+        let export = format.to_insomnia();
+        let with_query = &export["resources"][5];
+        assert_eq!(with_query["url"], "{{ _.HOST }}/get");
+        assert_eq!(with_query["parameters"][1]["value"], "products");
+    }
+}
@@ -0,0 +1,8 @@
+//! Converting a `RestFormat` into the formats of other HTTP clients
+
+pub mod curl;
+pub mod fetch;
+#[cfg(feature = "har")]
+pub mod har;
+#[cfg(feature = "insomnia")]
+pub mod insomnia;
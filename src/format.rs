@@ -1,19 +1,27 @@
+use std::borrow::Cow;
+use std::fmt;
 use std::str::FromStr;
 use std::io::Read;
-use std::fs::File;
-use std::path::Path;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use indexmap::IndexMap;
+use url::Url;
 
-use crate::template::Template;
+use crate::template::{Template, TemplatePart};
 use crate::RestVariables;
 
-use super::lexer::{Line, parse_lines};
-use super::parser::{RestRequest, RestFlavor, REQUEST_NEWLINE};
+use super::lexer::{Line, parse_numbered_lines};
+use super::system::{dotenv_variable_name, load_dotenv};
+use super::parser::{
+    encoding_for_label, ParseOptions, RequestBlock, RestRequest, RestFlavor, HTTP_VERSION_COMMAND,
+    REQUEST_NEWLINE,
+};
 
 /// A basic representaion of the REST format
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RestFormat {
     /// A list of recipes
     pub requests: Vec<RestRequest>,
@@ -21,89 +29,1835 @@ pub struct RestFormat {
     pub variables: IndexMap<String, Template>,
     /// The specific flavor of REST format (VSCode, Jetbrains, etc.)
     pub flavor: RestFlavor,
+    /// Commands before the first request that apply to every request: `# @no-log`
+    /// They're merged into each request's `commands`, a request's own value wins
+    pub commands: IndexMap<String, Option<String>>,
+    /// The directory of the parsed file, relative paths like `.env` are found here
+    /// `None` when the format wasn't parsed from a file
+    pub base_dir: Option<PathBuf>,
+}
+
+/// The directory a format was read from isn't part of its contents
+impl PartialEq for RestFormat {
+    fn eq(&self, other: &Self) -> bool {
+        self.requests == other.requests
+            && self.variables == other.variables
+            && self.flavor == other.flavor
+            && self.commands == other.commands
+    }
+}
+
+/// Commands that apply to every request when they're placed before the first request
+const FILE_SCOPE_COMMANDS: &[&str] = &[
+    "no-log", "no-redirect", "no-cookie-jar", "timeout", "connection-timeout",
+];
+
+/// The differences between two formats, see `RestFormat::diff`
+/// Requests are matched by name, unnamed requests by their position (`Request N`)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FormatDiff {
+    /// Requests only in the new format
+    pub added_requests: Vec<String>,
+    /// Requests only in the old format
+    pub removed_requests: Vec<String>,
+    /// Requests in both formats mapped to the names of the fields that changed
+    pub changed_requests: IndexMap<String, Vec<&'static str>>,
+    pub added_variables: Vec<String>,
+    pub removed_variables: Vec<String>,
+    pub changed_variables: Vec<String>,
+}
+
+/// A named group of requests between `# region Name` and `# endregion`
+/// Regions don't nest, a new `# region` ends the current one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section<'a> {
+    pub name: &'a str,
+    pub requests: Vec<&'a RestRequest>,
+}
+
+/// A request block skipped by `RestFormat::parse_lenient`
+#[derive(Debug)]
+pub struct ParseError {
+    /// The 1-based line of the block's request line, or where the block starts
+    pub line: usize,
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {:#}", self.line, self.error)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FormatDiff {
+    /// There are no differences
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 impl RestFormat {
     pub fn parse_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::parse_file_with_options(path, &ParseOptions::default())
+    }
+
+    /// Parse a file, decoding it with `options.file_encoding` when set
+    /// The file's directory is kept as `base_dir`
+    pub fn parse_file_with_options(
+        path: impl AsRef<Path>,
+        options: &ParseOptions,
+    ) -> anyhow::Result<Self> {
         let flavor = RestFlavor::from_path(&path); 
         let path = path.as_ref();
 
         let mut file = File::open(path)
             .context(format!("Error opening REST file {path:?}"))?;
 
-        let mut text = String::new();
-        file.read_to_string(&mut text)
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)
             .context(format!("Error reading REST file {path:?}"))?;
 
-        Self::parse(&text, flavor)
+        let text = match &options.file_encoding {
+            Some(label) => {
+                let encoding = encoding_for_label(label).context("Unknown file encoding")?;
+                let (text, _, had_errors) = encoding.decode(&bytes);
+                if had_errors {
+                    return Err(anyhow!("REST file {path:?} is not valid {}", encoding.name()));
+                }
+                text.into_owned()
+            }
+            None => String::from_utf8(bytes)
+                .context(format!("REST file {path:?} is not valid UTF-8"))?,
+        };
+
+        let format = Self::parse_with_options(&text, flavor, options)?;
+        let base_dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Ok(Self { base_dir: Some(base_dir), ..format })
+    }
+
+    /// The values of the `.env` file in `base_dir` as variables for rendering `{{$dotenv KEY}}`
+    /// Extend the context with them before rendering, keys missing from the file render as empty
+    /// The file is only read when a template uses `$dotenv`, a missing file is not an error
+    pub fn dotenv_variables(&self) -> anyhow::Result<RestVariables> {
+        let uses_dotenv = self.variables.values()
+            .chain(self.requests.iter().flat_map(|request| request.variables.values()))
+            .chain(self.requests.iter().flat_map(RestRequest::templates))
+            .any(Template::uses_dotenv);
+        // Bearer, Basic and Digest credentials are kept as text
+        let authorization_uses_dotenv = self.requests
+            .iter()
+            .filter_map(RestRequest::authorization_header)
+            .any(|(_, value)| Template::new(&value).uses_dotenv());
+
+        let values = match (&self.base_dir, uses_dotenv || authorization_uses_dotenv) {
+            (Some(base_dir), true) => load_dotenv(base_dir)?.unwrap_or_default(),
+            _ => IndexMap::new(),
+        };
+        // The values are literal text, braces in them aren't variables
+        Ok(values
+            .into_iter()
+            .map(|(key, value)| {
+                let literal = TemplatePart::Text(value).to_string();
+                (dotenv_variable_name(&key), Template::new(&literal))
+            })
+            .collect())
+    }
+
+    /// Parse a file and merge in the variables of `env_name` from the sibling `http-client.env.json`
+    /// Precedence in the returned `variables`: file `@var` definitions override environment values
+    /// A missing environment file is ignored
+    #[cfg(feature = "json")]
+    pub fn parse_file_with_env(path: impl AsRef<Path>, env_name: &str) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let format = Self::parse_file(path)?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut variables = crate::env::load_environment(dir, env_name)?;
+        variables.extend(format.variables);
+
+        Ok(Self { variables, ..format })
+    }
+
+    /// Parse every `.http` and `.rest` file in a directory, sorted by path
+    /// Errors reading the directory fail the whole call,
+    /// errors in a single file are returned next to its path
+    pub fn parse_all_in_dir(
+        dir: impl AsRef<Path>,
+        recursive: bool,
+    ) -> anyhow::Result<Vec<(PathBuf, anyhow::Result<Self>)>> {
+        let mut paths = vec![];
+        collect_rest_files(dir.as_ref(), recursive, &mut paths)?;
+        paths.sort();
+
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let format = Self::parse_file(&path);
+                (path, format)
+            })
+            .collect())
     }
 
     pub fn parse(text: &str, flavor: RestFlavor) -> anyhow::Result<Self> {
-        let (lines, variables) = parse_lines(text)?;
-        Self::from_lines(lines, variables, flavor)
+        Self::parse_with_options(text, flavor, &ParseOptions::default())
+    }
+
+    pub fn parse_with_options(
+        text: &str,
+        flavor: RestFlavor,
+        options: &ParseOptions,
+    ) -> anyhow::Result<Self> {
+        let (lines, variables) = parse_numbered_lines(text)?;
+        Self::from_lines(lines, variables, flavor, options, None)
+    }
+
+    /// Parse as much as possible, skipping request blocks that fail to parse
+    /// Returns the requests that parsed and an error for each skipped block
+    pub fn parse_lenient(text: &str, flavor: RestFlavor) -> (Self, Vec<ParseError>) {
+        let mut errors = vec![];
+        let format = parse_numbered_lines(text).and_then(|(lines, variables)| {
+            Self::from_lines(lines, variables, flavor, &ParseOptions::default(), Some(&mut errors))
+        });
+        match format {
+            Ok(format) => (format, errors),
+            Err(error) => (Self { flavor, ..Self::default() }, vec![ParseError { line: 1, error }]),
+        }
+    }
+
+    /// Parse the fenced `http` and `rest` code blocks embedded in Markdown
+    /// Prose and other code blocks are ignored
+    /// Each request records the index of the block it came from
+    pub fn parse_markdown(markdown: &str) -> anyhow::Result<Self> {
+        let mut format = Self::default();
+        for (index, block) in markdown_code_blocks(markdown).into_iter().enumerate() {
+            let Self { requests, variables, commands, .. } = Self::parse(&block, RestFlavor::Generic)
+                .context(format!("Error parsing Markdown code block {index}"))?;
+
+            format.variables.extend(variables);
+            format.commands.extend(commands);
+            format.requests.extend(requests.into_iter().map(|request| RestRequest {
+                markdown_block: Some(index),
+                ..request
+            }));
+        }
+        Ok(format)
     }
 
     /// Take each parsed line (like a lex token) and
     /// convert it to the REST format
+    /// With `errors` a request block that fails to parse is skipped and its error collected
     fn from_lines(
-        lines: Vec<Line>,
-        variables: RestVariables, 
+        lines: Vec<(usize, Line)>,
+        mut variables: RestVariables, 
         flavor: RestFlavor,
+        options: &ParseOptions,
+        mut errors: Option<&mut Vec<ParseError>>,
     ) -> anyhow::Result<Self> {
         let mut requests: Vec<RestRequest> = vec![];
-        let mut current_name: Option<String> = None;
-        let mut current_request: String = "".into();
-        let mut current_commands: IndexMap<String, Option<String>> = IndexMap::new();
+        let mut finish_block = |block: RequestBlock, line: usize, block_error: Option<anyhow::Error>| {
+            let request = match block_error {
+                Some(error) => Err(error),
+                None => RestRequest::from_raw_request(block, flavor, options),
+            };
+            match (request, errors.as_deref_mut()) {
+                (Ok(request), _) => requests.push(request),
+                (Err(error), Some(errors)) => errors.push(ParseError { line, error }),
+                (Err(error), None) => return Err(error),
+            }
+            Ok(())
+        };
+        let mut current = RequestBlock::default();
+        // Where the current block starts, its request line once it's seen
+        let mut block_line = 1;
+        let mut block_error: Option<anyhow::Error> = None;
+        let mut region: Option<String> = None;
+        // Lines before the first seperator or request line can set file-wide defaults
+        let mut in_file_header = true;
+        let mut default_http_version: Option<String> = None;
+        let mut commands: IndexMap<String, Option<String>> = IndexMap::new();
        
-        for line in lines {
+        for (number, line) in lines {
             match line {
                 Line::Seperator(name_opt) => {
-                    if current_request.trim() != "" {
-                        let request = RestRequest::from_raw_request(
-                            current_name,
-                            current_commands.clone(),
-                            &current_request,
-                        )?;
-                        requests.push(request);
+                    in_file_header = false;
+                    let block = std::mem::take(&mut current);
+                    if block.raw_request.trim() != "" {
+                        finish_block(block, block_line, block_error.take())?;
                     }
+                    block_line = number;
+                    block_error = None;
 
-                    current_name = None;
-                    current_request = "".into();
-                    current_commands = IndexMap::new();
-
-                    if let Some(name) = name_opt {
-                        current_name = Some(name);
+                    current.name = name_opt;
+                    current.region = region.clone();
+                }
+                Line::Region(name) => {
+                    region = name;
+                    // A region before the request line applies to the current request
+                    if current.raw_request.trim().is_empty() {
+                        current.region = region.clone();
                     }
                 }
                 Line::Name(name) => {
-                    current_name = Some(name);
+                    // The last name wins unless parsing strictly
+                    match &current.name {
+                        Some(existing) if options.strict && *existing != name => {
+                            block_error = Some(anyhow!(
+                                "Request has conflicting names '{existing}' and '{name}'"
+                            ));
+                        }
+                        _ => current.name = Some(name),
+                    }
+                },
+                Line::Command { name, params } if in_file_header && name == HTTP_VERSION_COMMAND => {
+                    default_http_version = params.map(|version| version.trim().to_string());
+                },
+                Line::Command { name, params } if in_file_header && FILE_SCOPE_COMMANDS.contains(&name.as_str()) => {
+                    commands.insert(name, params);
                 },
                 Line::Command { name, params } => {
-                    current_commands.insert(name, params); 
+                    current.commands.insert(name, params); 
+                },
+                Line::Variable { name, value } if options.request_variables => {
+                    current.variables.insert(name, value);
+                },
+                Line::Variable { name, value } => {
+                    variables.insert(name, value);
+                },
+                Line::PreRequestScript(script) => {
+                    current.pre_request_script = Some(script);
                 },
                 Line::Request(req) => {
-                    current_request.push_str(&req);
-                    current_request.push_str(REQUEST_NEWLINE);
+                    if current.raw_request.trim().is_empty() && !req.trim().is_empty() {
+                        block_line = number;
+                    }
+                    in_file_header &= req.trim().is_empty();
+                    current.raw_request.push_str(&req);
+                    current.raw_request.push_str(REQUEST_NEWLINE);
                 }
             }
         }
 
-        let request = RestRequest::from_raw_request(
-            current_name,
-            current_commands,
-            &current_request,
-        )?;
-        requests.push(request);
+        finish_block(current, block_line, block_error)?;
 
-        Ok(Self { requests, variables, flavor })
+        if let Some(version) = default_http_version {
+            for request in &mut requests {
+                request.http_version.get_or_insert(version.clone());
+            }
+        }
+
+        for request in &mut requests {
+            let mut merged = commands.clone();
+            merged.extend(std::mem::take(&mut request.commands));
+            request.commands = merged;
+        }
+
+        #[cfg(feature = "system-vars")]
+        if options.resolve_system_variables {
+            let definitions = variables
+                .values_mut()
+                .chain(requests.iter_mut().flat_map(|request| request.variables.values_mut()));
+            for template in definitions {
+                *template = template.resolve_system_variables();
+            }
+        }
+
+        if options.auto_name {
+            for (index, request) in requests.iter_mut().enumerate() {
+                request.name.get_or_insert(format!("Request {}", index + 1));
+            }
+        }
+
+        Ok(Self { requests, variables, flavor, commands, base_dir: None })
+    }
+
+    /// The requests grouped by their `# region`, in order
+    /// Requests outside of a region aren't in any section
+    pub fn sections(&self) -> Vec<Section<'_>> {
+        let mut sections: Vec<Section> = vec![];
+        let mut previous: Option<&str> = None;
+        for request in &self.requests {
+            let region = request.region.as_deref();
+            match (region, sections.last_mut()) {
+                (Some(name), Some(section)) if previous == Some(name) => {
+                    section.requests.push(request)
+                }
+                (Some(name), _) => sections.push(Section { name, requests: vec![request] }),
+                (None, _) => {}
+            }
+            previous = region;
+        }
+        sections
+    }
+
+    /// Override the flavor after parsing, for example when parsing from a string
+    /// The flavor decides the content type assumed for a body without one, see `RestRequest::body_content_type`
+    pub fn with_flavor(mut self, flavor: RestFlavor) -> Self {
+        self.flavor = flavor;
+        self
+    }
+
+    /// Render the variables into every request's templates and clear them.
+    /// Each request uses its own variables over the file-level variables.
+    /// Undefined variables are kept so the requests are self-contained.
+    pub fn apply_variables(&mut self) {
+        let file_variables = std::mem::take(&mut self.variables);
+        for request in &mut self.requests {
+            let mut context = file_variables.clone();
+            context.extend(std::mem::take(&mut request.variables));
+            let context = resolve_partially(&context);
+
+            for template in request.templates_mut() {
+                *template = template.render_partial(&context);
+            }
+        }
+    }
+
+    /// Point every request at `old` to `new` instead
+    /// `http://localhost/users` -> `https://prod.example.com/users`
+    /// A templated host is only rewritten when it renders to `old`
+    pub fn replace_host(&mut self, old: &str, new: &str) {
+        let old = old.trim_end_matches('/');
+        let new = new.trim_end_matches('/');
+        for request in &mut self.requests {
+            let mut context = self.variables.clone();
+            context.extend(request.variables.clone());
+
+            if let Some(url) = replace_url_host(&request.url, &context, old, new) {
+                request.url = url;
+            }
+        }
+    }
+
+    /// Add `Origin` and `Referer` headers derived from a base URL
+    /// Only requests missing the header get it, existing values are kept
+    /// `https://example.com/app/` -> `Origin: https://example.com`, `Referer: https://example.com/app/`
+    pub fn fill_origin_headers(&mut self, base: &str) {
+        let origin = match Url::parse(base) {
+            Ok(url) if url.has_host() => url.origin().ascii_serialization(),
+            _ => base.trim_end_matches('/').to_string(),
+        };
+
+        for request in &mut self.requests {
+            if request.header_index("Origin").is_none() {
+                request.set_header("Origin", Template::new(&origin));
+            }
+            if request.header_index("Referer").is_none() {
+                request.set_header("Referer", Template::new(base));
+            }
+        }
+    }
+
+    /// Count how often each declared variable is referenced.
+    /// Every reference counts once, for the definition it resolves to: a request's own
+    /// variables (see `ParseOptions::request_variables`) shadow the file variables and
+    /// are keyed with the request: `Login.TOKEN`. Unused variables have a count of 0.
+    pub fn variable_usage(&self) -> IndexMap<String, usize> {
+        let mut usage: IndexMap<String, usize> = self.variables
+            .keys()
+            .map(|name| (name.clone(), 0))
+            .collect();
+        for name in self.variables.values().flat_map(Template::variable_names) {
+            if let Some(count) = usage.get_mut(name) {
+                *count += 1;
+            }
+        }
+
+        for (key, request) in keyed_requests(&self.requests) {
+            let own: Vec<(&String, &Template)> = request.variables.iter().collect();
+            let own_key = |name: &str| format!("{key}.{name}");
+            for (name, _) in &own {
+                usage.insert(own_key(name), 0);
+            }
+
+            // A definition referencing its own name means the file variable: `@HOST = {{HOST}}/v2`
+            let references = own
+                .iter()
+                .flat_map(|(definition, value)| {
+                    value.variable_names().into_iter().map(move |name| (Some(*definition), name))
+                })
+                .chain(
+                    request.templates()
+                        .into_iter()
+                        .flat_map(Template::variable_names)
+                        .map(|name| (None, name)),
+                );
+            for (definition, name) in references {
+                let shadowed = own.iter().any(|(own_name, _)| *own_name == name)
+                    && definition.map(String::as_str) != Some(name);
+                let resolved = match shadowed {
+                    true => own_key(name),
+                    false => name.to_string(),
+                };
+                if let Some(count) = usage.get_mut(&resolved) {
+                    *count += 1;
+                }
+            }
+        }
+        usage
+    }
+
+    /// The variables referenced but not defined in the file, in order of first use.
+    /// These have to come from the environment.
+    /// Variables declared or set by a pre-request script only count inside their own request,
+    /// references with a default value like `{{port:8080}}` aren't required.
+    pub fn required_variables(&self) -> Vec<String> {
+        let mut required: Vec<String> = vec![];
+        let mut collect = |templates: Vec<&Template>, defined: &dyn Fn(&str) -> bool| {
+            for template in templates {
+                for (name, default) in template.variables_with_defaults() {
+                    if default.is_none() && !defined(&name) && !required.contains(&name) {
+                        required.push(name);
+                    }
+                }
+            }
+        };
+
+        let file_defined = |name: &str| self.variables.contains_key(name);
+        collect(self.variables.values().collect(), &file_defined);
+
+        for request in &self.requests {
+            let defined = |name: &str| {
+                file_defined(name)
+                    || request.variables.contains_key(name)
+                    || request.script_variables.contains_key(name)
+            };
+            let mut templates: Vec<&Template> = request.variables.values().collect();
+            templates.extend(request.templates());
+            collect(templates, &defined);
+        }
+        required
+    }
+
+    /// Find a request by its name
+    pub fn get_request(&self, name: &str) -> Option<&RestRequest> {
+        self.requests
+            .iter()
+            .find(|request| request.name.as_deref() == Some(name))
+    }
+
+    /// Iterate over the requests that are not annotated with `# @disabled`
+    pub fn enabled_requests(&self) -> impl Iterator<Item = &RestRequest> {
+        self.requests.iter().filter(|request| !request.disabled)
+    }
+
+    /// Compare with a newer version of the format
+    pub fn diff(&self, other: &RestFormat) -> FormatDiff {
+        let old = keyed_requests(&self.requests);
+        let new = keyed_requests(&other.requests);
+
+        let mut diff = FormatDiff::default();
+        for (key, old_request) in &old {
+            match new.get(key) {
+                Some(new_request) => {
+                    let changes = request_changes(old_request, new_request);
+                    if !changes.is_empty() {
+                        diff.changed_requests.insert(key.clone(), changes);
+                    }
+                }
+                None => diff.removed_requests.push(key.clone()),
+            }
+        }
+        diff.added_requests = new.keys()
+            .filter(|key| !old.contains_key(*key))
+            .cloned()
+            .collect();
+
+        for (name, value) in &self.variables {
+            match other.variables.get(name) {
+                Some(new_value) if new_value != value => diff.changed_variables.push(name.clone()),
+                Some(_) => {}
+                None => diff.removed_variables.push(name.clone()),
+            }
+        }
+        diff.added_variables = other.variables.keys()
+            .filter(|name| !self.variables.contains_key(*name))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    /// Render each request's URL and check that it parses
+    /// Returns the index of each request with an invalid URL and the reason
+    /// Relative URLs and URLs with undefined variables can't be checked and are skipped
+    pub fn validate_urls(&self) -> Vec<(usize, String)> {
+        self.iter_with_variables()
+            .enumerate()
+            .filter_map(|(index, (request, variables))| {
+                let url = request.url.try_render(&variables).ok()?;
+                match Url::parse(&url) {
+                    Ok(_) | Err(url::ParseError::RelativeUrlWithoutBase) => None,
+                    Err(error) => Some((index, format!("{url}: {error}"))),
+                }
+            })
+            .collect()
+    }
+
+    /// Check that every template in every request renders with the file's variables
+    /// Errors with the undefined variables of each request, see `Template::render_strict`
+    /// Variables set by a pre-request script count as defined in their request
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = vec![];
+        for (index, (request, variables)) in self.iter_with_variables().enumerate() {
+            let mut variables = variables.into_owned();
+            variables.extend(request.script_variables.clone());
+            // Bearer, Basic and Digest credentials are kept as text, check them as the header
+            let authorization = request.authorization_header().map(|(_, value)| Template::new(&value));
+            let mut missing: Vec<String> = vec![];
+            for template in request.templates().into_iter().chain(authorization.as_ref()) {
+                for name in template.try_render(&variables).err().unwrap_or_default() {
+                    if !missing.contains(&name) {
+                        missing.push(name);
+                    }
+                }
+            }
+            if !missing.is_empty() {
+                let request = request.name.clone().unwrap_or(format!("Request {}", index + 1));
+                problems.push(format!("{request}: {}", missing.join(", ")));
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!("Undefined variables in {}", problems.join("; "))),
+        }
+    }
+
+    /// Iterate over each request paired with its effective variables.
+    /// The context is the file-level variables, overridden by the request's own variables
+    /// when it declares some with `ParseOptions::request_variables`.
+    /// It's merged while iterating so changes to `variables` after parsing are used.
+    pub fn iter_with_variables(
+        &self,
+    ) -> impl Iterator<Item = (&RestRequest, Cow<'_, RestVariables>)> {
+        self.requests.iter().map(|request| match request.variables.is_empty() {
+            true => (request, Cow::Borrowed(&self.variables)),
+            false => {
+                let mut context = self.variables.clone();
+                context.extend(request.variables.clone());
+                (request, Cow::Owned(context))
+            }
+        })
+    }
+}
+
+fn collect_rest_files(dir: &Path, recursive: bool, paths: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let entries = fs::read_dir(dir)
+        .context(format!("Error reading directory {dir:?}"))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                collect_rest_files(&path, recursive, paths)?;
+            }
+            continue;
+        }
+
+        let is_rest_file = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("http" | "rest")
+        );
+        if is_rest_file {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Key requests by name, falling back to their position for unnamed requests
+fn keyed_requests(requests: &[RestRequest]) -> IndexMap<String, &RestRequest> {
+    requests
+        .iter()
+        .enumerate()
+        .map(|(index, request)| {
+            let key = request.name.clone().unwrap_or(format!("Request {}", index + 1));
+            (key, request)
+        })
+        .collect()
+}
+
+/// The names of the fields that differ between two versions of a request
+/// Destructured so a new field can't be forgotten here
+fn request_changes(old: &RestRequest, new: &RestRequest) -> Vec<&'static str> {
+    let RestRequest {
+        name,
+        url,
+        query,
+        fragment,
+        body,
+        method,
+        http_version,
+        headers,
+        authorization,
+        commands,
+        variables,
+        disabled,
+        body_language,
+        settings,
+        pre_request_script,
+        script_variables,
+        response_handler,
+        markdown_block,
+        region,
+    } = old;
+    let fields = vec![
+        ("name", *name != new.name),
+        ("method", *method != new.method),
+        ("http_version", *http_version != new.http_version),
+        ("url", *url != new.url),
+        ("query", *query != new.query),
+        ("fragment", *fragment != new.fragment),
+        ("headers", *headers != new.headers),
+        ("authorization", *authorization != new.authorization),
+        ("body", *body != new.body),
+        ("body_language", *body_language != new.body_language),
+        ("settings", *settings != new.settings),
+        ("commands", *commands != new.commands),
+        ("variables", *variables != new.variables),
+        ("disabled", *disabled != new.disabled),
+        ("pre_request_script", *pre_request_script != new.pre_request_script),
+        ("script_variables", *script_variables != new.script_variables),
+        ("response_handler", *response_handler != new.response_handler),
+        ("markdown_block", *markdown_block != new.markdown_block),
+        ("region", *region != new.region),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect()
+}
+
+/// Render the start of a URL until it is long enough to compare with `old`
+/// If it matches, replace it with `new` and keep the rest of the template
+fn replace_url_host(
+    url: &Template,
+    context: &RestVariables,
+    old: &str,
+    new: &str,
+) -> Option<Template> {
+    let mut prefix = "".to_string();
+    let mut rest = url.parts.iter();
+    while prefix.len() <= old.len() {
+        match rest.next() {
+            Some(TemplatePart::Text(text)) => prefix += text,
+            Some(TemplatePart::Variable { name, .. }) => prefix += &context.get(name)?.render(context),
+            // A dynamic value never matches a fixed host
+            Some(TemplatePart::System { .. } | TemplatePart::Response { .. }) => return None,
+            None => break,
+        }
+    }
+
+    let remainder = prefix.strip_prefix(old)?;
+    // `http://localhost` should not match `http://localhost:8080` or `http://localhost.com`
+    let at_boundary = remainder.is_empty()
+        || remainder.starts_with(['/', '?', '#']);
+    if !at_boundary {
+        return None;
+    }
+
+    let rest_raw: String = rest.map(TemplatePart::to_string).collect();
+    Some(Template::new(&format!("{new}{remainder}{rest_raw}")))
+}
+
+const MARKDOWN_LANGUAGES: &[&str] = &["http", "rest"];
+
+/// Extract the contents of fenced code blocks tagged `http` or `rest`
+fn markdown_code_blocks(markdown: &str) -> Vec<String> {
+    let mut blocks: Vec<String> = vec![];
+    // The fence that opened the current block and its contents
+    let mut current: Option<(&str, bool, Vec<&str>)> = None;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        let fence = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f));
+
+        match (&mut current, fence) {
+            (None, Some(fence)) => {
+                let language = trimmed.trim_start_matches(fence).trim().to_lowercase();
+                let wanted = MARKDOWN_LANGUAGES.contains(&language.as_str());
+                current = Some((fence, wanted, vec![]));
+            }
+            (Some((open, wanted, contents)), Some(fence)) if *open == fence => {
+                if *wanted {
+                    blocks.push(contents.join("\n"));
+                }
+                current = None;
+            }
+            (Some((_, _, contents)), _) => contents.push(line),
+            (None, None) => {}
+        }
+    }
+    blocks
+}
+
+/// Render variables that reference other variables
+/// Stops after one pass per variable so cycles can't loop forever
+fn resolve_partially(variables: &RestVariables) -> RestVariables {
+    let mut resolved = variables.clone();
+    for _ in 0..variables.len() {
+        let next: RestVariables = resolved
+            .iter()
+            .map(|(name, value)| (name.clone(), value.render_partial(&resolved)))
+            .collect();
+        if next == resolved {
+            break;
+        }
+        resolved = next;
+    }
+    resolved
+}
+
+/// Write the format back in the `.http` format
+/// The file variables come first, then every request after a `###` seperator
+impl fmt::Display for RestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, params) in &self.commands {
+            match params {
+                Some(params) => writeln!(f, "# @{name} {params}")?,
+                None => writeln!(f, "# @{name}")?,
+            }
+        }
+        for (name, value) in &self.variables {
+            writeln!(f, "@{name} = {value}")?;
+        }
+        let mut region: Option<&str> = None;
+        for request in &self.requests {
+            if request.region.as_deref() != region {
+                if region.is_some() {
+                    write!(f, "\n# endregion\n")?;
+                }
+                region = request.region.as_deref();
+                if let Some(name) = region {
+                    write!(f, "\n# region {name}\n")?;
+                }
+            }
+            write!(f, "\n###\n{request}")?;
+        }
+        if region.is_some() {
+            write!(f, "\n# endregion\n")?;
+        }
+        Ok(())
     }
 }
 
 impl FromStr for RestFormat {
     type Err = anyhow::Error;
     fn from_str(text: &str) -> Result<Self, Self::Err> {
-        let (lines, variables) = parse_lines(text)?;
+        let (lines, variables) = parse_numbered_lines(text)?;
         // TODO: Figure out flavor
-        Self::from_lines(lines, variables, RestFlavor::Vscode)
+        Self::from_lines(lines, variables, RestFlavor::Vscode, &ParseOptions::default(), None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn iter_with_variables_test() {
+        let text = indoc! {r#"
+            @HOST = https://httpbin.org
+
+            ### First
+            @ENDPOINT = post
+            POST {{HOST}}/{{ENDPOINT}} HTTP/1.1
+
+            ### Second
+            GET {{HOST}}/get HTTP/1.1
+        "#};
+        let options = ParseOptions { request_variables: true, ..Default::default() };
+        let format = RestFormat::parse_with_options(text, RestFlavor::Jetbrains, &options).unwrap();
+        assert_eq!(format.variables.len(), 1);
+
+        let contexts: Vec<(&RestRequest, Cow<RestVariables>)> =
+            format.iter_with_variables().collect();
+        assert_eq!(contexts.len(), 2);
+
+        let (first, first_vars) = &contexts[0];
+        assert_eq!(first.name, Some("First".into()));
+        assert_eq!(first_vars.get("ENDPOINT").unwrap().raw, "post");
+        assert_eq!(first.url.render(first_vars), "https://httpbin.org/post");
+
+        let (second, second_vars) = &contexts[1];
+        assert_eq!(second.name, Some("Second".into()));
+        assert!(second_vars.contains_key("HOST"));
+        assert!(!second_vars.contains_key("ENDPOINT"));
+        assert!(matches!(second_vars, Cow::Borrowed(vars) if std::ptr::eq(*vars, &format.variables)));
+
+        // Only the request's own variables are written in its block
+        let written = format.to_string();
+        assert_eq!(written.matches("@HOST").count(), 1);
+        assert_eq!(RestFormat::parse_with_options(&written, RestFlavor::Jetbrains, &options).unwrap(), format);
+
+        // A file variable changed after parsing is used by the requests with their own variables
+        let mut changed = format.clone();
+        changed.variables.insert("HOST".into(), Template::new("https://b.com"));
+        let (first, first_vars) = changed.iter_with_variables().next().unwrap();
+        assert_eq!(first.url.render(&first_vars), "https://b.com/post");
+        assert_eq!(changed.to_string().matches("@HOST").count(), 1);
+
+        // A request redefining a file variable with the same value keeps its definition
+        let redefined = "@HOST = https://a.com\n\n###\n@HOST = https://a.com\nGET {{HOST}}/a HTTP/1.1\n";
+        let redefined = RestFormat::parse_with_options(redefined, RestFlavor::Jetbrains, &options).unwrap();
+        assert_eq!(redefined.requests[0].variables.keys().collect::<Vec<_>>(), vec!["HOST"]);
+        assert_eq!(redefined.to_string().matches("@HOST").count(), 2);
+
+        // By default every definition applies to the whole file
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.variables.keys().collect::<Vec<_>>(), vec!["HOST", "ENDPOINT"]);
+        assert!(format.iter_with_variables().all(|(_, vars)| matches!(vars, Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn file_variables_in_request_blocks_test() {
+        // `@FIRST`, `@LAST` and `@FULL` are declared inside `JsonPost` but used by the next request
+        let format = RestFormat::parse_file("test_data/http_bin.http").unwrap();
+        assert!(format.requests.iter().all(|request| request.variables.is_empty()));
+        let body = format.requests[2].body.as_ref().unwrap().body_text(&format.variables).unwrap();
+        assert_eq!(body, "first=Joe&last=Smith&full=Joe Smith");
+    }
+
+    #[test]
+    fn variable_usage_test() {
+        let text = indoc! {r#"
+            @HOST = https://httpbin.org
+            @TOKEN = abc
+            @UNUSED = nothing
+
+            ### First
+            @ENDPOINT = post
+            POST {{HOST}}/{{ENDPOINT}} HTTP/1.1
+            X-Token: {{TOKEN}}
+
+            ### Second
+            GET {{HOST}}/get?missing={{NOT_DECLARED}} HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let usage = format.variable_usage();
+
+        assert_eq!(usage.get("HOST"), Some(&2));
+        assert_eq!(usage.get("TOKEN"), Some(&1));
+        assert_eq!(usage.get("ENDPOINT"), Some(&1));
+        assert_eq!(usage.get("UNUSED"), Some(&0));
+        assert_eq!(usage.get("NOT_DECLARED"), None);
+
+        // A request's own definition shadows the file variable
+        let text = indoc! {r#"
+            @HOST = https://example.com
+            @TOKEN = abc
+
+            ### Login
+            @TOKEN = {{TOKEN}}-login
+            POST {{HOST}}/login HTTP/1.1
+            X-Token: {{TOKEN}}
+            X-Again: {{TOKEN}}
+
+            ### Other
+            GET {{HOST}}/other HTTP/1.1
+        "#};
+        let options = ParseOptions { request_variables: true, ..Default::default() };
+        let format = RestFormat::parse_with_options(text, RestFlavor::Jetbrains, &options).unwrap();
+        let usage = format.variable_usage();
+        assert_eq!(usage.get("HOST"), Some(&2));
+        assert_eq!(usage.get("TOKEN"), Some(&1));
+        assert_eq!(usage.get("Login.TOKEN"), Some(&2));
+        assert_eq!(usage.len(), 3);
+    }
+
+    #[test]
+    fn required_variables_test() {
+        let text = indoc! {r#"
+            @HOST = https://{{DOMAIN}}
+            @TOKEN = abc
+
+            ### First
+            @ENDPOINT = post
+            POST {{HOST}}/{{ENDPOINT}}?port={{PORT:8080}} HTTP/1.1
+            X-Token: {{TOKEN}}
+            X-Api-Key: {{API_KEY}}
+
+            ### Second
+            < {% request.variables.set("page", "2") %}
+            GET {{HOST}}/{{ENDPOINT}}?page={{page}}&id={{$guid}} HTTP/1.1
+            X-Api-Key: {{API_KEY}}
+            X-Login: {{login.response.body.$.token}}
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.required_variables(), vec!["DOMAIN", "API_KEY"]);
+
+        // `ENDPOINT` is only declared inside the first request
+        let options = ParseOptions { request_variables: true, ..Default::default() };
+        let format = RestFormat::parse_with_options(text, RestFlavor::Jetbrains, &options).unwrap();
+        assert_eq!(format.required_variables(), vec!["DOMAIN", "API_KEY", "ENDPOINT"]);
+
+        let format = RestFormat::parse_file("test_data/jetbrains.http").unwrap();
+        assert!(format.required_variables().is_empty());
+    }
+
+    #[test]
+    fn apply_variables_test() {
+        let text = indoc! {r#"
+            @HOST = https://httpbin.org
+            @FIRST = Joe
+            @FULL = {{FIRST}} Smith
+
+            ### First
+            @ENDPOINT = post
+            POST {{HOST}}/{{ENDPOINT}}?name={{FULL}} HTTP/1.1
+            X-Token: {{TOKEN}}
+
+            ### Second
+            GET {{HOST}}/get HTTP/1.1
+        "#};
+        let mut format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        format.apply_variables();
+
+        assert!(format.variables.is_empty());
+        assert!(format.requests.iter().all(|r| r.variables.is_empty()));
+
+        let first = &format.requests[0];
+        assert_eq!(first.url.raw, "https://httpbin.org/post");
+        assert_eq!(first.query.get("name").unwrap().raw, "Joe Smith");
+        // Undefined variables are kept
+        assert_eq!(first.headers.get("X-Token").unwrap().raw, "{{TOKEN}}");
+
+        let second = &format.requests[1];
+        assert_eq!(second.url.raw, "https://httpbin.org/get");
+    }
+
+    #[test]
+    fn parse_markdown_test() {
+        let markdown = indoc! {r#"
+            # My API
+
+            Some prose about the API with a ### that isn't a seperator.
+
+            ```http
+            @HOST = https://httpbin.org
+            ### GetThing
+            GET {{HOST}}/get HTTP/1.1
+            ```
+
+            A JSON example that should be ignored:
+
+            ```json
+            {"a": 1}
+            ```
+
+            ```rest
+            ### PostThing
+            POST {{HOST}}/post HTTP/1.1
+
+            ### PutThing
+            PUT {{HOST}}/put HTTP/1.1
+            ```
+        "#};
+        let format = RestFormat::parse_markdown(markdown).unwrap();
+        assert_eq!(format.variables.get("HOST").unwrap().raw, "https://httpbin.org");
+
+        let names_and_blocks: Vec<(Option<String>, Option<usize>)> = format
+            .requests
+            .iter()
+            .map(|r| (r.name.clone(), r.markdown_block))
+            .collect();
+        assert_eq!(names_and_blocks, vec![
+            (Some("GetThing".into()), Some(0)),
+            (Some("PostThing".into()), Some(1)),
+            (Some("PutThing".into()), Some(1)),
+        ]);
+    }
+
+    #[test]
+    fn multiple_names_test() {
+        let text = indoc! {r#"
+            ###
+            # @name First
+            # @name Second
+            GET https://example.com HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests[0].name, Some("Second".into()));
+
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        let err = RestFormat::parse_with_options(text, RestFlavor::Jetbrains, &strict).unwrap_err();
+        assert!(err.to_string().contains("conflicting names"));
+
+        // Repeating the same name is not a conflict
+        let text = "### Same\n# @name Same\nGET https://example.com HTTP/1.1";
+        let format = RestFormat::parse_with_options(text, RestFlavor::Jetbrains, &strict).unwrap();
+        assert_eq!(format.requests[0].name, Some("Same".into()));
+    }
+
+    #[test]
+    fn replace_host_test() {
+        let text = indoc! {r#"
+            @LOCAL = http://localhost
+            @OTHER = http://other.com
+
+            ### Static
+            GET http://localhost/users/{{id}} HTTP/1.1
+
+            ### Templated
+            GET {{LOCAL}}/get?q=1 HTTP/1.1
+
+            ### OtherTemplated
+            GET {{OTHER}}/get HTTP/1.1
+
+            ### DifferentPort
+            GET http://localhost:8080/get HTTP/1.1
+        "#};
+        let mut format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        format.replace_host("http://localhost", "https://prod.example.com");
+
+        let urls: Vec<&str> = format.requests.iter().map(|r| r.url.raw.as_str()).collect();
+        assert_eq!(urls, vec![
+            "https://prod.example.com/users/{{id}}",
+            "https://prod.example.com/get",
+            "{{OTHER}}/get",
+            "http://localhost:8080/get",
+        ]);
+        assert_eq!(format.requests[0].url.variable_names(), vec!["id"]);
+        assert_eq!(format.requests[1].query.get("q").unwrap().raw, "1");
+    }
+
+    #[test]
+    fn to_string_round_trip_test() {
+        for path in ["test_data/jetbrains.http", "test_data/http_bin.http", "test_data/vscode.rest"] {
+            let format = RestFormat::parse_file(path).unwrap();
+            let text = format.to_string();
+            let reparsed = RestFormat::parse(&text, format.flavor).unwrap();
+            assert_eq!(format, reparsed, "{path} did not round trip:\n{text}");
+        }
+
+        let text = indoc! {r#"
+            @HOST = https://example.com
+
+            ### Everything
+            # @no-log
+            # @language json
+            # @oauth2 token-url={{HOST}}/token scope="read write"
+            @id = 7
+            < {%
+                request.variables.set("page", "2")
+            %}
+            GET {{HOST}}/users/{{id}}?q=hello%20world&tags[]=a&tags[]=b&plus=%2B#details HTTP/1.1
+            Accept: application/json
+            Authorization: Bearer {{token}}
+
+            {
+              "a": 1
+            }
+            \@mention
+
+            ###
+            POST {{HOST}}/upload HTTP/1.1
+            Authorization: Basic Zm9vOmJhcg==
+
+            <@latin1 ./file.txt
+
+            ###
+            # @disabled
+            POST {{HOST}}/export HTTP/1.1
+            Authorization: {{auth}}
+
+            >> ./out.json
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let serialized = format.to_string();
+        let reparsed = RestFormat::parse(&serialized, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format, reparsed, "Did not round trip:\n{serialized}");
+        assert!(serialized.contains("?q=hello%20world&tags[]=a&tags[]=b&plus=%2B#details HTTP/1.1"));
+        assert!(serialized.contains("\n\\@mention\n"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_test() {
+        let format = RestFormat::parse_file("test_data/jetbrains.http").unwrap();
+        let json = serde_json::to_string(&format).unwrap();
+        let deserialized: RestFormat = serde_json::from_str(&json).unwrap();
+        assert_eq!(format, deserialized);
+
+        let template = serde_json::to_value(Template::new("{{HOST}}/get")).unwrap();
+        assert_eq!(template, serde_json::json!({
+            "parts": [{"Variable": {"name": "HOST", "default": null, "raw_inner": "HOST"}}, {"Text": "/get"}],
+            "raw": "{{HOST}}/get",
+        }));
+
+        // JSON without `raw_inner` still deserializes and writes the variables back
+        let old = serde_json::json!([
+            {"Variable": {"name": "HOST", "default": null}},
+            {"Variable": {"name": "page", "default": "1"}},
+        ]);
+        let parts: Vec<crate::template::TemplatePart> = serde_json::from_value(old).unwrap();
+        assert_eq!(parts[0].to_string(), "{{HOST}}");
+        assert_eq!(parts[1].to_string(), "{{page:1}}");
+    }
+
+    #[test]
+    fn validate_test() {
+        let text = indoc! {r#"
+            @HOST = https://{{domain}}
+
+            ### Login
+            < {% request.variables.set("nonce", "abc") %}
+            POST {{HOST}}/login?nonce={{nonce}} HTTP/1.1
+            X-Request-Id: {{$guid}}
+
+            {"user": "{{user}}", "page": {{page:1}}}
+
+            ###
+            GET {{HOST}}/users/{{id}} HTTP/1.1
+            Authorization: Bearer {{token}}
+        "#};
+        let mut format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let err = format.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Undefined variables in Login: domain, user; Request 2: domain, id, token",
+        );
+
+        for (name, value) in [("domain", "example.com"), ("user", "ben"), ("id", "1"), ("token", "abc")] {
+            format.variables.insert(name.into(), Template::new(value));
+        }
+        assert!(format.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_urls_test() {
+        let format = RestFormat::parse_file("test_data/urls.http").unwrap();
+        // The relative URL and the URL with an undefined variable are skipped
+        let invalid = format.validate_urls();
+        let indexes: Vec<usize> = invalid.iter().map(|(index, _)| *index).collect();
+        assert_eq!(indexes, vec![1, 4]);
+        assert_eq!(invalid[0].1, "https://example.com:99999/users: invalid port number");
+        assert!(invalid[1].1.starts_with("https://bad host.com/users: "));
+
+        let valid = RestFormat::parse_file("test_data/jetbrains.http").unwrap();
+        assert!(valid.validate_urls().is_empty());
+    }
+
+    #[test]
+    fn parse_all_in_dir_test() {
+        let dir = std::env::temp_dir().join(format!("rest_parser_dir_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let nested = dir.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.join("a.http"), "GET https://example.com/a HTTP/1.1").unwrap();
+        fs::write(dir.join("b.rest"), "GET https://example.com/b HTTP/1.1").unwrap();
+        fs::write(dir.join("broken.http"), "GET https://example.com HTTP/1.1\nNot a header").unwrap();
+        fs::write(dir.join("notes.txt"), "GET https://example.com/c HTTP/1.1").unwrap();
+        fs::write(nested.join("c.http"), "GET https://example.com/c HTTP/1.1").unwrap();
+
+        let parsed = RestFormat::parse_all_in_dir(&dir, false).unwrap();
+        let names: Vec<_> = parsed.iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a.http", "b.rest", "broken.http"]);
+
+        let (_, a) = &parsed[0];
+        let a = a.as_ref().unwrap();
+        assert_eq!(a.flavor, RestFlavor::Jetbrains);
+        assert_eq!(a.requests[0].url.raw, "https://example.com/a");
+        assert_eq!(parsed[1].1.as_ref().unwrap().flavor, RestFlavor::Vscode);
+        assert!(parsed[2].1.is_err());
+
+        let parsed = RestFormat::parse_all_in_dir(&dir, true).unwrap();
+        assert_eq!(parsed.len(), 4);
+        assert!(parsed.iter().any(|(path, _)| path.ends_with("nested/c.http")));
+
+        assert!(RestFormat::parse_all_in_dir(dir.join("missing"), false).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_encoding_test() {
+        let path = "test_data/windows1252.http";
+        assert!(RestFormat::parse_file(path).is_err());
+
+        let options = ParseOptions { file_encoding: Some("windows-1252".into()), ..Default::default() };
+        let format = RestFormat::parse_file_with_options(path, &options).unwrap();
+        assert_eq!(format.variables.get("name").unwrap().raw, "Renée");
+        let request = &format.requests[0];
+        assert_eq!(request.name.as_deref(), Some("Café"));
+        assert_eq!(request.url.render(&format.variables), "https://example.com/Renée");
+        assert_eq!(request.headers.get("X-Greeting").unwrap().raw, "\u{201c}hello\u{201d}");
+
+        let options = ParseOptions { file_encoding: Some("not-an-encoding".into()), ..Default::default() };
+        assert!(RestFormat::parse_file_with_options(path, &options).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn parse_file_with_env_test() {
+        let dir = std::env::temp_dir().join(format!("rest_parser_env_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("api.http");
+        fs::write(&file, "@port = 9000\nGET http://{{host}}:{{port}} HTTP/1.1").unwrap();
+
+        // No environment file
+        let format = RestFormat::parse_file_with_env(&file, "dev").unwrap();
+        assert_eq!(format.variables.keys().collect::<Vec<_>>(), vec!["port"]);
+
+        fs::write(
+            dir.join(crate::env::ENV_FILE_NAME),
+            r#"{"dev": {"host": "localhost", "port": 8080}, "prod": {"host": "example.com"}}"#,
+        ).unwrap();
+
+        let format = RestFormat::parse_file_with_env(&file, "dev").unwrap();
+        assert_eq!(format.variables.get("host").unwrap().raw, "localhost");
+        // The file definition wins over the environment
+        assert_eq!(format.variables.get("port").unwrap().raw, "9000");
+        assert_eq!(format.requests[0].url.render(&format.variables), "http://localhost:9000");
+
+        let format = RestFormat::parse_file_with_env(&file, "prod").unwrap();
+        assert_eq!(format.variables.get("host").unwrap().raw, "example.com");
+
+        assert!(RestFormat::parse_file_with_env(&file, "staging").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dotenv_variables_test() {
+        let dir = std::env::temp_dir().join(format!("rest_parser_dotenv_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("api.http");
+        fs::write(&file, indoc! {r#"
+            @HOST = {{$dotenv HOST}}
+
+            GET {{HOST}}/users?key={{$dotenv API_KEY}}&other={{$dotenv MISSING}} HTTP/1.1
+            Authorization: Bearer {{$dotenv TOKEN}}
+        "#}).unwrap();
+
+        // No `.env` file
+        let format = RestFormat::parse_file(&file).unwrap();
+        assert_eq!(format.base_dir.as_deref(), Some(dir.as_path()));
+        assert!(format.dotenv_variables().unwrap().is_empty());
+
+        fs::write(dir.join(".env"), "HOST=https://example.com\nAPI_KEY=\"abc123\"\nTOKEN={{not a variable}}\n").unwrap();
+        let format = RestFormat::parse_file(&file).unwrap();
+        let mut vars = format.dotenv_variables().unwrap();
+        vars.extend(format.variables.clone());
+
+        let request = &format.requests[0];
+        assert_eq!(request.url.render(&vars), "https://example.com/users");
+        assert_eq!(request.query_get("key", &vars).as_deref(), Some("abc123"));
+        // Missing keys are empty
+        assert_eq!(request.query_get("other", &vars).as_deref(), Some(""));
+        assert_eq!(request.to_curl(&vars).matches("Bearer {{not a variable}}").count(), 1);
+
+        // The secrets aren't written back
+        assert_eq!(format.variables["HOST"].raw, "{{$dotenv HOST}}");
+        assert!(!format.to_string().contains("abc123"));
+        assert_eq!(request.url.render(&format.variables), "/users");
+
+        // A `.env` that can't be read only matters when `$dotenv` is used
+        fs::remove_file(dir.join(".env")).unwrap();
+        fs::create_dir(dir.join(".env")).unwrap();
+        let format = RestFormat::parse_file(&file).unwrap();
+        assert!(format.dotenv_variables().is_err());
+        fs::write(&file, "GET https://example.com HTTP/1.1").unwrap();
+        let format = RestFormat::parse_file(&file).unwrap();
+        assert!(format.dotenv_variables().unwrap().is_empty());
+
+        // Formats that weren't parsed from a file have no `.env`
+        let format = RestFormat::parse("GET {{$dotenv HOST}} HTTP/1.1", RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.base_dir, None);
+        assert!(format.dotenv_variables().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn with_flavor_test() {
+        let format: RestFormat = "GET https://example.com HTTP/1.1".parse().unwrap();
+        assert_eq!(format.flavor, RestFlavor::Vscode);
+        assert_eq!(format.flavor.extension(), "rest");
+
+        let format = format.with_flavor(RestFlavor::Jetbrains);
+        assert_eq!(format.flavor, RestFlavor::Jetbrains);
+        assert_eq!(format.flavor.extension(), "http");
+        assert_eq!(format.requests.len(), 1);
+
+        // A body without a `Content-Type` is plain text for Jetbrains only
+        let format: RestFormat = "POST https://example.com HTTP/1.1\n\nhello".parse().unwrap();
+        assert_eq!(format.requests[0].body_content_type(format.flavor), None);
+        let format = format.with_flavor(RestFlavor::Jetbrains);
+        assert_eq!(format.requests[0].body_content_type(format.flavor).as_deref(), Some("text/plain"));
+
+        // A JSON body is JSON for every flavor and a header always wins
+        let text = "POST https://example.com HTTP/1.1\n\n{\"a\": 1}";
+        for flavor in [RestFlavor::Vscode, RestFlavor::Jetbrains, RestFlavor::Generic] {
+            let format = RestFormat::parse(text, flavor).unwrap();
+            assert_eq!(format.requests[0].body_content_type(flavor).as_deref(), Some("application/json"));
+        }
+        let format = RestFormat::parse("POST https://example.com HTTP/1.1\nContent-Type: text/csv\n\na,b", RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests[0].body_content_type(format.flavor).as_deref(), Some("text/csv"));
+    }
+
+    #[test]
+    fn auto_name_test() {
+        let text = indoc! {r#"
+            GET https://example.com/first HTTP/1.1
+
+            ### Named
+            GET https://example.com/named HTTP/1.1
+
+            ###
+            GET https://example.com/third HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests[0].name, None);
+        assert!(format.get_request("Request 1").is_none());
+
+        let options = ParseOptions { auto_name: true, ..Default::default() };
+        let format = RestFormat::parse_with_options(text, RestFlavor::Jetbrains, &options).unwrap();
+        let names: Vec<_> = format.requests.iter().map(|r| r.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("Request 1"), Some("Named"), Some("Request 3")]);
+        assert_eq!(format.get_request("Request 3").unwrap().url.raw, "https://example.com/third");
+        assert_eq!(format.get_request("Named").unwrap().url.raw, "https://example.com/named");
+    }
+
+    #[test]
+    fn default_http_version_test() {
+        let text = indoc! {r#"
+            # @http-version 2
+            @HOST = https://example.com
+
+            ### Versionless
+            GET {{HOST}}/a
+            Accept: */*
+
+            ### Explicit
+            GET {{HOST}}/b HTTP/1.1
+
+            ### Command
+            # @http-version 3
+            GET {{HOST}}/c
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let versions: Vec<_> = format.requests.iter().map(|r| r.http_version.as_deref()).collect();
+        assert_eq!(versions, vec![Some("2"), Some("1.1"), Some("3")]);
+        assert_eq!(format.requests[0].headers.get("Accept").unwrap().raw, "*/*");
+        assert!(format.requests.iter().all(|r| r.commands.is_empty()));
+
+        let reparsed = RestFormat::parse(&format.to_string(), RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format, reparsed);
+
+        // Without a default the version stays unknown
+        let format = RestFormat::parse("GET https://example.com/a", RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests[0].http_version, None);
+        assert_eq!(format.requests[0].url.raw, "https://example.com/a");
+    }
+
+    #[test]
+    fn parse_lenient_test() {
+        let text = indoc! {r#"
+            @HOST = https://example.com
+
+            ### Good
+            GET {{HOST}}/users HTTP/1.1
+
+            ### Broken
+            # @no-log
+            GET {{HOST}}/broken HTTP/1.1
+            Not a header
+
+            ### Also good
+            POST {{HOST}}/users HTTP/1.1
+        "#};
+        assert!(RestFormat::parse(text, RestFlavor::Generic).is_err());
+
+        let (format, errors) = RestFormat::parse_lenient(text, RestFlavor::Generic);
+        let names: Vec<_> = format.requests.iter().map(|r| r.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("Good"), Some("Also good")]);
+        assert_eq!(format.variables["HOST"].raw, "https://example.com");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 8);
+        assert!(errors[0].to_string().starts_with("Line 8: "));
+
+        let (format, errors) = RestFormat::parse_lenient("GET https://example.com HTTP/1.1", RestFlavor::Generic);
+        assert_eq!(format.requests.len(), 1);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn sections_test() {
+        let text = indoc! {r#"
+            # region Auth
+            ### Login
+            POST https://example.com/login HTTP/1.1
+
+            # endregion
+
+            // region Users
+            ### GetUser
+            GET https://example.com/users/1 HTTP/1.1
+            # endregion
+
+            ### Health
+            GET https://example.com/health HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let regions: Vec<_> = format.requests.iter().map(|r| r.region.as_deref()).collect();
+        assert_eq!(regions, vec![Some("Auth"), Some("Users"), None]);
+
+        let sections = format.sections();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].name, "Auth");
+        assert_eq!(sections[0].requests.len(), 1);
+        assert_eq!(sections[0].requests[0].name.as_deref(), Some("Login"));
+        assert_eq!(sections[1].name, "Users");
+        assert_eq!(sections[1].requests[0].name.as_deref(), Some("GetUser"));
+
+        // The region comments don't leak into the bodies
+        assert!(format.requests.iter().all(|r| r.body.is_none()));
+
+        let reparsed = RestFormat::parse(&format.to_string(), RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format, reparsed);
+    }
+
+    #[test]
+    fn diff_test() {
+        let old = indoc! {r#"
+            @HOST = https://example.com
+
+            ### Users
+            GET {{HOST}}/users HTTP/1.1
+
+            ### Removed
+            DELETE {{HOST}}/users/1 HTTP/1.1
+
+            ### Same
+            GET {{HOST}}/health HTTP/1.1
+        "#};
+        let new = indoc! {r#"
+            @HOST = https://example.com
+            @TOKEN = abc
+
+            ### Users
+            GET {{HOST}}/v2/users HTTP/1.1
+
+            ### Same
+            GET {{HOST}}/health HTTP/1.1
+
+            ### Added
+            POST {{HOST}}/users HTTP/1.1
+        "#};
+        let old = RestFormat::parse(old, RestFlavor::Jetbrains).unwrap();
+        let new = RestFormat::parse(new, RestFlavor::Jetbrains).unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_requests, vec!["Added"]);
+        assert_eq!(diff.removed_requests, vec!["Removed"]);
+        assert_eq!(diff.changed_requests.len(), 1);
+        assert_eq!(diff.changed_requests.get("Users").unwrap(), &vec!["url"]);
+        assert_eq!(diff.added_variables, vec!["TOKEN"]);
+        assert!(diff.removed_variables.is_empty());
+        assert!(diff.changed_variables.is_empty());
+
+        assert!(old.diff(&old).is_empty());
+
+        // Every field is compared
+        let old = RestFormat::parse("GET https://example.com HTTP/1.1\n\n{}", RestFlavor::Generic).unwrap();
+        let text = indoc! {r#"
+            # @language json
+            < {% request.variables.set("id", "1") %}
+            GET https://example.com HTTP/1.1
+
+            {}
+        "#};
+        let new = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let changes = &old.diff(&new).changed_requests["Request 1"];
+        assert!(changes.contains(&"body_language"));
+        assert!(changes.contains(&"script_variables"));
+    }
+
+    #[test]
+    fn fill_origin_headers_test() {
+        let text = indoc! {r#"
+            ### Missing
+            GET https://api.example.com/users HTTP/1.1
+
+            ### HasOrigin
+            GET https://api.example.com/users HTTP/1.1
+            origin: https://other.com
+        "#};
+        let mut format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        format.fill_origin_headers("https://example.com:8443/app/");
+
+        let missing = &format.requests[0].headers;
+        assert_eq!(missing.get("Origin").unwrap().raw, "https://example.com:8443");
+        assert_eq!(missing.get("Referer").unwrap().raw, "https://example.com:8443/app/");
+
+        let has_origin = &format.requests[1].headers;
+        assert_eq!(has_origin.get("origin").unwrap().raw, "https://other.com");
+        assert!(has_origin.get("Origin").is_none());
+        assert_eq!(has_origin.get("Referer").unwrap().raw, "https://example.com:8443/app/");
+    }
+
+    #[test]
+    #[cfg(feature = "system-vars")]
+    fn resolve_system_variables_test() {
+        let text = indoc! {r#"
+            @REQUEST_ID = {{$uuid}}
+            @HOST = https://example.com
+
+            ###
+            @TRACE = {{$randomInt 0 1000000}}-{{REQUEST_ID}}-{{$unknown}}
+            GET {{HOST}}/a?id={{REQUEST_ID}} HTTP/1.1
+            X-Request-Id: {{REQUEST_ID}}
+        "#};
+        let options = ParseOptions {
+            resolve_system_variables: true,
+            request_variables: true,
+            ..Default::default()
+        };
+        let format = RestFormat::parse_with_options(text, RestFlavor::Generic, &options).unwrap();
+        let request_id = &format.variables["REQUEST_ID"];
+        assert_eq!(request_id.as_static_str().unwrap().len(), 36);
+
+        // Both references render the same value, also in the request's context
+        let (request, context) = format.iter_with_variables().next().unwrap();
+        let vars = &format.variables;
+        assert_eq!(&context["REQUEST_ID"], request_id);
+        assert_eq!(request.query_get("id", vars), request.headers["X-Request-Id"].render(vars).into());
+        assert_eq!(request.query_get("id", vars).as_deref(), request_id.as_static_str());
+
+        // Other variables and unknown system variables are kept
+        let trace = &request.variables["TRACE"];
+        assert!(trace.raw.ends_with("-{{REQUEST_ID}}-{{$unknown}}"));
+        assert!(!trace.raw.contains("randomInt"));
+
+        // Off by default
+        let format = RestFormat::parse(text, RestFlavor::Generic).unwrap();
+        assert_eq!(format.variables["REQUEST_ID"].raw, "{{$uuid}}");
+    }
+
+    #[test]
+    #[cfg(not(feature = "system-vars"))]
+    fn resolve_system_variables_without_feature_test() {
+        let options = ParseOptions { resolve_system_variables: true, ..Default::default() };
+        let format = RestFormat::parse_with_options("@REQUEST_ID = {{$uuid}}\nGET https://example.com HTTP/1.1", RestFlavor::Generic, &options).unwrap();
+        assert_eq!(format.variables["REQUEST_ID"].raw, "{{$uuid}}");
+    }
+
+    #[test]
+    fn response_handler_test() {
+        use crate::{BodyKind, ResponseHandler};
+
+        let text = indoc! {r#"
+            ### Login
+            POST https://example.com/login HTTP/1.1
+            Content-Type: application/json
+
+            {"user": "{{user}}"}
+
+            > {%
+                // Save the token for later requests
+                client.global.set("token", response.body.token);
+                client.test("ok", function() { if (response.status !== 200) { throw {{}} } });
+            %}
+
+            ### Save
+            GET https://example.com/report HTTP/1.1
+
+            > {% client.log("saved"); %}
+
+            >> ./report.json
+
+            ### File
+            GET https://example.com/users HTTP/1.1
+
+            > ./handlers/{{name}}.js
+
+            ### Quote
+            POST https://example.com/notes HTTP/1.1
+
+            > not a handler
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+
+        let login = &format.requests[0];
+        let Some(ResponseHandler::Inline(script)) = &login.response_handler else {
+            panic!("Expected an inline handler: {:?}", login.response_handler);
+        };
+        assert!(script.starts_with("// Save the token"));
+        assert!(script.contains("throw {{}}"));
+        assert_eq!(login.body.as_ref().unwrap().templates()[0].raw, r#"{"user": "{{user}}"}"#);
+
+        let save = &format.requests[1];
+        assert_eq!(save.response_handler, Some(ResponseHandler::Inline(r#"client.log("saved");"#.into())));
+        assert_eq!(save.body.as_ref().unwrap().kind(), BodyKind::SaveToFile);
+
+        let file = &format.requests[2];
+        assert_eq!(file.response_handler, Some(ResponseHandler::File(Template::new("./handlers/{{name}}.js"))));
+        assert_eq!(file.body, None);
+        assert!(file.templates().iter().any(|template| template.contains_variable("name")));
+
+        let quote = &format.requests[3];
+        assert_eq!(quote.response_handler, None);
+        assert_eq!(quote.body.as_ref().unwrap().templates()[0].raw, "> not a handler");
+
+        let reparsed = RestFormat::parse(&format.to_string(), RestFlavor::Jetbrains).unwrap();
+        assert_eq!(reparsed.requests, format.requests);
+    }
+
+    #[test]
+    fn pre_request_script_test() {
+        let text = indoc! {r#"
+            ### WithScript
+            < {%
+                request.variables.set("firstname", "John");
+                request.variables.set("lastname", "Smith");
+            %}
+            GET https://example.com/{{firstname}} HTTP/1.1
+
+            ### Inline
+            < {% request.variables.set("id", "1") %}
+            GET https://example.com/{{id}} HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests.len(), 2);
+
+        let first = &format.requests[0];
+        assert_eq!(first.url.variable_names(), vec!["firstname"]);
+        assert_eq!(first.script_variables.get("firstname").unwrap().raw, "John");
+        assert_eq!(first.script_variables.get("lastname").unwrap().raw, "Smith");
+        assert!(first.pre_request_script.as_ref().unwrap().contains("request.variables.set"));
+
+        let second = &format.requests[1];
+        assert_eq!(second.script_variables.get("id").unwrap().raw, "1");
+    }
+
+    #[test]
+    fn enabled_requests_test() {
+        let text = indoc! {r#"
+            ### First
+            GET https://example.com/first HTTP/1.1
+
+            ### Second
+            # @disabled
+            GET https://example.com/second HTTP/1.1
+
+            ### Third
+            GET https://example.com/third HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests.len(), 3);
+        assert!(format.requests[1].disabled);
+
+        let enabled: Vec<Option<String>> = format
+            .enabled_requests()
+            .map(|request| request.name.clone())
+            .collect();
+        assert_eq!(enabled, vec![Some("First".into()), Some("Third".into())]);
+    }
+
+    #[test]
+    fn file_scope_commands_test() {
+        use crate::Command;
+        use std::time::Duration;
+
+        let text = indoc! {r#"
+            # @no-log
+            # @timeout 10
+            @HOST = https://example.com
+
+            ### First
+            GET {{HOST}}/a HTTP/1.1
+
+            ### Second
+            # @timeout 30
+            # @no-redirect
+            GET {{HOST}}/b HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.commands.len(), 2);
+        assert_eq!(format.requests[0].parsed_commands(), vec![
+            Command::NoLog,
+            Command::Timeout(Duration::from_secs(10)),
+        ]);
+        // A request's own command overrides the file's
+        assert_eq!(format.requests[1].parsed_commands(), vec![
+            Command::NoLog,
+            Command::Timeout(Duration::from_secs(30)),
+            Command::NoRedirect,
+        ]);
+
+        let reparsed = RestFormat::parse(&format.to_string(), RestFlavor::Jetbrains).unwrap();
+        assert_eq!(reparsed.commands, format.commands);
+        assert_eq!(reparsed.requests[1].commands, format.requests[1].commands);
+    }
+
+    #[test]
+    fn escaped_braces_body_test() {
+        use crate::Body;
+
+        let text = indoc! {r#"
+            POST https://example.com/templates HTTP/1.1
+            Content-Type: application/json
+
+            {"greeting": "Hello \{{user\}}", "from": "{{name}}"}
+        "#};
+        let mut format = RestFormat::parse(text, RestFlavor::Generic).unwrap();
+        format.variables.insert("name".into(), Template::new("Ben"));
+        let body = format.requests[0].body.clone().unwrap();
+        let Body::Text(template) = &body else { panic!("Expected a text body") };
+        assert_eq!(template.variable_names(), vec!["name"]);
+        assert_eq!(
+            body.body_text(&format.variables).unwrap(),
+            r#"{"greeting": "Hello {{user}}", "from": "Ben"}"#,
+        );
+
+        // The escaped form is written back
+        assert!(format.to_string().contains(r#""Hello \{{user\}}""#));
+    }
+
+    #[test]
+    fn empty_variable_test() {
+        // The trailing space after `=` is intentional
+        let text = "@EMPTY =\n@BLANK = \n@HOST = https://example.com\n\n###\n@SUFFIX =\nGET {{HOST}}/a{{EMPTY}}{{BLANK}}{{SUFFIX:default}} HTTP/1.1\n";
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.variables.keys().collect::<Vec<_>>(), vec!["EMPTY", "BLANK", "HOST", "SUFFIX"]);
+        assert_eq!(format.variables["EMPTY"], Template::new(""));
+        assert_eq!(format.variables["BLANK"].as_static_str(), Some(""));
+        assert_eq!(format.variables["SUFFIX"], Template::new(""));
+
+        // A defined empty value wins over the default
+        let request = &format.requests[0];
+        assert_eq!(request.url.render(&format.variables), "https://example.com/a");
+        assert!(format.required_variables().is_empty());
+
+        let reparsed = RestFormat::parse(&format.to_string(), RestFlavor::Jetbrains).unwrap();
+        assert_eq!(reparsed, format);
+    }
+
+    #[test]
+    fn separator_inside_body_string_test() {
+        let text = indoc! {r####"
+            ### Only
+            POST https://example.com/notes HTTP/1.1
+            Content-Type: application/json
+
+            {
+                "marker": "### not a sep",
+                "tags": [
+                    "###",
+                    "### Also not a sep @no-log"
+                ]
+            }
+        "####};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests.len(), 1);
+        let request = &format.requests[0];
+        assert_eq!(request.name.as_deref(), Some("Only"));
+        assert!(request.commands.is_empty());
+        let body = request.body.as_ref().unwrap().body_text(&format.variables).unwrap();
+        assert!(body.contains(r####""marker": "### not a sep""####));
+        assert!(body.contains(r####""### Also not a sep @no-log""####));
     }
 }
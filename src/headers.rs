@@ -6,11 +6,24 @@ use nom::{
 use base64::{prelude::BASE64_STANDARD, Engine};
 use std::str;
 
-use crate::template::Template;
+use crate::parser::RestVariables;
+use crate::template::{Template, TemplatePart};
 
-const AUTHORIZATION_HEADER: &str = "Authorization";
+pub(crate) const AUTHORIZATION_HEADER: &str = "Authorization";
 
 const CONTENT_TYPE: &str = "Content-Type";
+const REQUEST_TYPE_HEADER: &str = "X-REQUEST-TYPE";
+pub(crate) const OAUTH2_COMMAND: &str = "oauth2";
+
+/// Commands that are shorthand for a header
+/// `# @Content-Type application/json`
+const HEADER_COMMANDS: &[&str] = &[
+    CONTENT_TYPE,
+    "Accept",
+    "Accept-Encoding",
+    "Accept-Language",
+    "User-Agent",
+];
 
 pub(crate) struct RestHeaders {
     pub(crate) authorization: Option<Authorization>,
@@ -22,8 +35,10 @@ impl RestHeaders {
     /// This is just coercing them into templates
     /// If an authentication header can be found and parsed,
     /// turn it into an Authorization struct
+    /// With `decode_escapes` the `\n`, `\r` and `\t` escapes in values are decoded
     pub(crate) fn from_header_slice(
         headers_slice: &mut [httparse::Header],
+        decode_escapes: bool,
     ) -> anyhow::Result<Self> {
         let headers_vec: Vec<httparse::Header> = headers_slice
             .iter()
@@ -37,9 +52,17 @@ impl RestHeaders {
             let name = header.name.to_string();
             let str_val = str::from_utf8(header.value)
                 .context(format!("Cannot parse header {} as UTF8", name))?;
+            let decoded;
+            let str_val = match decode_escapes {
+                true => {
+                    decoded = decode_header_escapes(str_val);
+                    decoded.as_str()
+                }
+                false => str_val,
+            };
 
             // If successfully parse authentication from header, save it
-            // If it can't be parsed, it will be included as a normal header
+            // An empty value is included as a normal header
             if name.to_lowercase() == AUTHORIZATION_HEADER.to_lowercase() {
                 if let Ok(auth) = Authorization::from_header(str_val) {
                     authorization = Some(auth);
@@ -57,29 +80,430 @@ impl RestHeaders {
         })
     }
 
-    pub(crate) fn content_type(&self) -> String {
-        self.headers.get(CONTENT_TYPE)
-            .unwrap_or(&Template::new("unknown"))
-            .raw
-            .clone()
+    /// Promote commands that name a header into headers
+    /// A header written in the request takes priority over the command
+    /// Other commands are left as is
+    pub(crate) fn apply_header_commands(
+        &mut self,
+        commands: &mut IndexMap<String, Option<String>>,
+    ) {
+        commands.retain(|name, params| {
+            let header = HEADER_COMMANDS
+                .iter()
+                .find(|header| header.eq_ignore_ascii_case(name));
+            let (Some(header), Some(value)) = (header, params) else {
+                return true;
+            };
+
+            let exists = self.headers
+                .keys()
+                .any(|existing| existing.eq_ignore_ascii_case(header));
+            if !exists {
+                self.headers.insert(header.to_string(), Template::new(value));
+            }
+            false
+        });
+    }
+
+    /// Turn a `# @oauth2 token-url=... client-id=...` command into `Authorization::OAuth2`
+    /// A `Bearer` token in the request becomes the OAuth2 token
+    /// Other authorization schemes take priority over the command
+    pub(crate) fn apply_oauth2_command(
+        &mut self,
+        commands: &mut IndexMap<String, Option<String>>,
+    ) {
+        let Some(params) = commands.get(OAUTH2_COMMAND) else {
+            return;
+        };
+        let token = match &self.authorization {
+            None => None,
+            Some(Authorization::Bearer(token)) => Some(Template::new(token)),
+            Some(_) => return,
+        };
+
+        let config = Box::new(OAuth2Config::parse(params.as_deref().unwrap_or("")));
+        self.authorization = Some(Authorization::OAuth2 { token, config });
+        commands.shift_remove(OAUTH2_COMMAND);
+    }
+
+    /// Jetbrains marks GraphQL requests with `X-REQUEST-TYPE: GraphQL`
+    /// instead of a content type
+    pub(crate) fn is_graphql(&self) -> bool {
+        self.headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case(REQUEST_TYPE_HEADER) && value.raw.trim().eq_ignore_ascii_case("graphql")
+        })
+    }
+
+    pub(crate) fn content_type(&self) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(CONTENT_TYPE))
+            .map(|(_, value)| value.raw.clone())
+    }
+}
+
+/// Decode the `\n`, `\r` and `\t` escapes some generators write into header values
+/// Any other backslash is kept as is
+fn decode_header_escapes(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        let escaped = match (c, chars.peek()) {
+            ('\\', Some('n')) => '\n',
+            ('\\', Some('r')) => '\r',
+            ('\\', Some('t')) => '\t',
+            _ => {
+                decoded.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        decoded.push(escaped);
+    }
+    decoded
+}
+
+/// A parsed `Content-Type` or `Accept` media type
+/// `multipart/form-data; boundary=something`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ContentType {
+    /// The lowercase `type/subtype`
+    pub essence: String,
+    /// Parameters after the essence such as `charset` or `boundary`
+    pub params: IndexMap<String, String>,
+}
+
+impl ContentType {
+    pub fn parse(input: &str) -> Self {
+        let mut segments = input.split(';');
+        let essence = segments.next().unwrap_or("").trim().to_lowercase();
+        let params = segments
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| {
+                let value = value.trim().trim_matches('"');
+                (key.trim().to_lowercase(), value.to_string())
+            })
+            .collect();
+
+        Self { essence, params }
+    }
+
+    /// The `boundary` parameter of a multipart type
+    pub fn boundary(&self) -> Option<&str> {
+        self.params
+            .get("boundary")
+            .map(String::as_str)
+            .filter(|boundary| !boundary.is_empty())
+    }
+
+    /// Check if the media type matches a pattern
+    /// Supports wildcards like `application/*` and `*/*`
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.trim().to_lowercase();
+        let split = |value: &str| {
+            let (main, sub) = value.split_once('/').unwrap_or((value, ""));
+            (main.to_string(), sub.to_string())
+        };
+
+        let (main, sub) = split(&self.essence);
+        let (pattern_main, pattern_sub) = split(&pattern);
+
+        let main_matches = pattern_main == "*" || pattern_main == main;
+        let sub_matches = pattern_sub == "*" || pattern_sub == sub;
+        main_matches && sub_matches
     }
 }
 
 
 /// The `Authorization` header
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Authorization {
     Bearer(String),
     Basic {
         username: String,
         password: Option<String>,
+    },
+    /// `Digest username="ben", realm="api", nonce="abc", uri="/", response="def"`
+    /// Parameters other than the required ones are kept in `params`
+    Digest {
+        username: String,
+        realm: String,
+        nonce: String,
+        uri: String,
+        response: String,
+        params: IndexMap<String, String>,
+    },
+    /// The whole value is a variable without a scheme:
+    /// `Authorization: {{authHeader}}`
+    Raw(Template),
+    /// Any other scheme, the parameters are kept as written
+    /// `Hawk id="abc", mac="def"` or `AWS4-HMAC-SHA256 Credential=...`
+    Other {
+        scheme: String,
+        params: String,
+    },
+    /// A token described by a `# @oauth2` command
+    /// The token is the `Bearer` token if the request has one
+    OAuth2 {
+        token: Option<Template>,
+        config: Box<OAuth2Config>,
+    },
+    /// Several comma seperated schemes, some proxies send these
+    /// `Bearer abc, Basic dXNlcjpwYXNz`
+    Multiple(Vec<Authorization>),
+}
+
+/// The metadata of an OAuth2 flow
+/// `# @oauth2 token-url=https://auth.example.com/token client-id={{clientId}} scope="read write"`
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OAuth2Config {
+    pub token_url: Option<Template>,
+    pub client_id: Option<Template>,
+    pub client_secret: Option<Template>,
+    pub scope: Option<Template>,
+    pub grant_type: Option<Template>,
+    /// Any other `key=value` parameters
+    pub extra: IndexMap<String, Template>,
+}
+
+impl OAuth2Config {
+    /// Parse whitespace seperated `key=value` pairs, values may be quoted
+    fn parse(input: &str) -> Self {
+        let mut config = Self::default();
+        for (key, value) in split_params(input) {
+            let value = Template::new(&value);
+            match key.to_lowercase().replace('_', "-").as_str() {
+                "token-url" => config.token_url = Some(value),
+                "client-id" => config.client_id = Some(value),
+                "client-secret" => config.client_secret = Some(value),
+                "scope" => config.scope = Some(value),
+                "grant-type" => config.grant_type = Some(value),
+                _ => {
+                    config.extra.insert(key, value);
+                }
+            }
+        }
+        config
+    }
+
+    /// Convert back into the parameters of a `# @oauth2` command
+    pub(crate) fn to_params(&self) -> String {
+        let known = [
+            ("token-url", &self.token_url),
+            ("client-id", &self.client_id),
+            ("client-secret", &self.client_secret),
+            ("scope", &self.scope),
+            ("grant-type", &self.grant_type),
+        ];
+        known
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value.as_ref()?)))
+            .chain(self.extra.iter().map(|(key, value)| (key.as_str(), value)))
+            .map(|(key, value)| match value.raw.contains(char::is_whitespace) {
+                true => format!("{key}=\"{}\"", value.raw),
+                false => format!("{key}={}", value.raw),
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Every template in the config
+    pub fn templates(&self) -> Vec<&Template> {
+        [&self.token_url, &self.client_id, &self.client_secret, &self.scope, &self.grant_type]
+            .into_iter()
+            .flatten()
+            .chain(self.extra.values())
+            .collect()
+    }
+
+    /// Every template in the config, mutably
+    pub fn templates_mut(&mut self) -> Vec<&mut Template> {
+        [
+            &mut self.token_url,
+            &mut self.client_id,
+            &mut self.client_secret,
+            &mut self.scope,
+            &mut self.grant_type,
+        ]
+            .into_iter()
+            .flatten()
+            .chain(self.extra.values_mut())
+            .collect()
+    }
+}
+
+/// Split `a=1 b="two words"` into key value pairs
+fn split_params(input: &str) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut rest = input.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().to_string();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(char::is_whitespace).unwrap_or((after, "")),
+        };
+        pairs.push((key, value.to_string()));
+        rest = remaining.trim_start();
+    }
+    pairs
+}
+
+/// Digest parameters that are written without quotes
+const UNQUOTED_DIGEST_PARAMS: &[&str] = &["algorithm", "qop", "nc", "stale"];
+
+/// Schemes that start another credential in a comma seperated header
+const MULTIPLE_SCHEMES: &[&str] = &["Bearer ", "Basic ", "Digest "];
+
+/// Split a header with several schemes at the commas that start a known scheme
+/// Commas between the parameters of a scheme or inside quotes don't split: `Digest a="1", b="2"`
+fn split_schemes(input: &str) -> Vec<&str> {
+    let mut schemes = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, c) in input.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                let next = input[index + 1..].trim_start();
+                if MULTIPLE_SCHEMES.iter().any(|scheme| next.starts_with(scheme)) {
+                    schemes.push(input[start..index].trim());
+                    start = index + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    schemes.push(input[start..].trim());
+    schemes
+}
+
+/// Split comma seperated `key="value"` pairs, quoted values may contain commas
+/// `None` if a pair has no `=` or a quote isn't closed
+fn split_digest_params(input: &str) -> Option<IndexMap<String, String>> {
+    let mut params = IndexMap::new();
+    let mut rest = input.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => match after.find(',') {
+                Some(end) => (&after[..end], &after[end..]),
+                None => (after, ""),
+            },
+        };
+        params.insert(key.trim().to_lowercase(), value.trim().to_string());
+        rest = remaining.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
     }
+    Some(params)
 }
 
 impl Authorization {
+    /// Convert back into the value of an Authorization header
+    pub fn to_header(&self) -> String {
+        match self {
+            Self::Bearer(token) => format!("Bearer {token}"),
+            Self::Basic { username, password } => {
+                let credentials = match password {
+                    Some(password) => format!("{username}:{password}"),
+                    None => username.clone(),
+                };
+                format!("Basic {}", BASE64_STANDARD.encode(credentials))
+            }
+            Self::Digest { username, realm, nonce, uri, response, params } => {
+                let known = [
+                    ("username", username),
+                    ("realm", realm),
+                    ("nonce", nonce),
+                    ("uri", uri),
+                    ("response", response),
+                ];
+                let params = known
+                    .into_iter()
+                    .chain(params.iter().map(|(key, value)| (key.as_str(), value)))
+                    .map(|(key, value)| match UNQUOTED_DIGEST_PARAMS.contains(&key) {
+                        true => format!("{key}={value}"),
+                        false => format!("{key}=\"{value}\""),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("Digest {params}")
+            }
+            Self::Raw(template) => template.raw.clone(),
+            Self::Other { scheme, params } if params.is_empty() => scheme.clone(),
+            Self::Other { scheme, params } => format!("{scheme} {params}"),
+            Self::OAuth2 { token: Some(token), .. } => format!("Bearer {}", token.raw),
+            // The token still needs to be fetched
+            Self::OAuth2 { token: None, .. } => "".into(),
+            Self::Multiple(schemes) => schemes
+                .iter()
+                .map(Self::to_header)
+                .collect::<Vec<String>>()
+                .join(", "),
+        }
+    }
+
+    /// Render the value of the Authorization header with the variables filled in
+    /// Basic credentials are rendered before they are encoded: `{{user}}:{{password}}`
+    pub fn render_header(&self, vars: &RestVariables) -> String {
+        match self {
+            Self::Basic { username, password } => {
+                let render = |value: &str| Template::new(value).render(vars);
+                let rendered = Self::Basic {
+                    username: render(username),
+                    password: password.as_deref().map(render),
+                };
+                rendered.to_header()
+            }
+            Self::Multiple(schemes) => schemes
+                .iter()
+                .map(|scheme| scheme.render_header(vars))
+                .collect::<Vec<String>>()
+                .join(", "),
+            other => Template::new(&other.to_header()).render(vars),
+        }
+    }
+
     /// Convert the value of an Authorization header into an authentication
-    /// struct Can either be Bearer or Basic
+    /// struct Can either be Bearer, Basic, Digest, a raw variable or any other scheme
+    /// Comma seperated schemes are parsed into `Multiple`: `Bearer abc, Basic dXNlcjpwYXNz`
+    /// A Basic or Digest value that can't be decoded is kept as `Other`
+    /// Only an empty value is an error
     pub fn from_header(input: &str) -> anyhow::Result<Self> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(anyhow!("The auth header is empty"));
+        }
+        let schemes = split_schemes(input);
+        if schemes.len() > 1 {
+            return schemes
+                .into_iter()
+                .map(Self::from_header)
+                .collect::<anyhow::Result<Vec<Self>>>()
+                .map(Self::Multiple);
+        }
+        Self::parse_scheme(input).or_else(|_| Self::other(input))
+    }
+
+    /// Keep the scheme and its parameters without interpreting them
+    /// A scheme that starts with a variable is `Raw`: `{{scheme}} abc`
+    fn other(input: &str) -> anyhow::Result<Self> {
+        let template = Template::new(input);
+        if let Some(TemplatePart::Variable { .. }) = template.parts.first() {
+            return Ok(Self::Raw(template));
+        }
+
+        let (scheme, params) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        Ok(Self::Other { scheme: scheme.into(), params: params.trim().into() })
+    }
+
+    fn parse_scheme(input: &str) -> anyhow::Result<Self> {
         fn bearer(input: &str) -> IResult<&str, &str> {
             tag("Bearer ")(input)
         }
@@ -112,7 +536,23 @@ impl Authorization {
             return Ok(Self::Basic { username, password });
         }
 
-        Err(anyhow!("Failed to parse auth header"))
+        if let Some(params) = input.strip_prefix("Digest ") {
+            let mut params = split_digest_params(params)
+                .ok_or_else(|| anyhow!("Malformed digest auth header"))?;
+            let mut required = |key: &str| {
+                params.shift_remove(key).ok_or_else(|| anyhow!("Digest auth is missing {key}"))
+            };
+            return Ok(Self::Digest {
+                username: required("username")?,
+                realm: required("realm")?,
+                nonce: required("nonce")?,
+                uri: required("uri")?,
+                response: required("response")?,
+                params,
+            });
+        }
+
+        Err(anyhow!("Unknown auth scheme"))
     }
 }
 
@@ -120,6 +560,24 @@ impl Authorization {
 mod test {
     use super::*;
 
+    #[test]
+    fn content_type_matches_test() {
+        let json = ContentType::parse("application/json; charset=UTF-8");
+        assert_eq!(json.essence, "application/json");
+        assert_eq!(json.params.get("charset").unwrap(), "UTF-8");
+
+        assert!(json.matches("application/json"));
+        assert!(json.matches("Application/JSON"));
+        assert!(json.matches("application/*"));
+        assert!(json.matches("*/*"));
+        assert!(!json.matches("text/*"));
+        assert!(!json.matches("application/xml"));
+
+        let multipart = ContentType::parse(r#"multipart/form-data; boundary="abc""#);
+        assert!(multipart.matches("multipart/*"));
+        assert_eq!(multipart.params.get("boundary").unwrap(), "abc");
+    }
+
     #[test]
     fn parse_auth_header_test() {
         let example = "Basic Zm9vOmJhcg==";
@@ -151,4 +609,107 @@ mod test {
             _ => panic!("Should be bearer auth!"),
         }
     }
+
+    #[test]
+    fn auth_to_header_test() {
+        for example in ["Basic Zm9vOmJhcg==", "Basic dXNlcm5hbWV3aXRob3V0cGFzc3dvcmQ=", "Bearer abc", "{{auth}}"] {
+            let auth = Authorization::from_header(example).unwrap();
+            assert_eq!(auth.to_header(), example);
+        }
+    }
+
+    #[test]
+    fn parse_digest_auth_header_test() {
+        let example = r#"Digest username="ben", realm="api@example.com", nonce="dcd98b", uri="/dir/index.html", qop=auth, nc=00000001, cnonce="0a4f113b", response="6629fae4", opaque="5ccc, 069""#;
+        let auth = Authorization::from_header(example).unwrap();
+        let Authorization::Digest { username, realm, nonce, uri, response, params } = &auth else {
+            panic!("Should be digest auth!");
+        };
+        assert_eq!(username, "ben");
+        assert_eq!(realm, "api@example.com");
+        assert_eq!(nonce, "dcd98b");
+        assert_eq!(uri, "/dir/index.html");
+        assert_eq!(response, "6629fae4");
+        assert_eq!(params.keys().collect::<Vec<_>>(), vec!["qop", "nc", "cnonce", "opaque"]);
+        assert_eq!(params["opaque"], "5ccc, 069");
+        assert_eq!(Authorization::from_header(&auth.to_header()).unwrap(), auth);
+        assert!(auth.to_header().contains(r#"qop=auth, nc=00000001, cnonce="0a4f113b""#));
+
+        // Malformed or incomplete digests are kept as written
+        for example in [r#"Digest username="ben"#, r#"Digest username="ben", realm="api""#, "Digest nonsense"] {
+            let auth = Authorization::from_header(example).unwrap();
+            assert!(matches!(auth, Authorization::Other { ref scheme, .. } if scheme == "Digest"));
+            assert_eq!(auth.to_header(), example);
+        }
+    }
+
+    #[test]
+    fn parse_raw_auth_header_test() {
+        let example = "{{authHeader}}";
+        match Authorization::from_header(example).unwrap() {
+            Authorization::Raw(template) => {
+                assert_eq!(template.parts, vec![TemplatePart::var("authHeader")]);
+            }
+            _ => panic!("Should be raw auth!"),
+        }
+
+        // A variable scheme is raw, a variable after a scheme isn't
+        assert!(matches!(Authorization::from_header("{{a}}{{b}}").unwrap(), Authorization::Raw(_)));
+        assert!(matches!(Authorization::from_header("Token {{token}}").unwrap(), Authorization::Other { .. }));
+    }
+
+    #[test]
+    fn parse_other_auth_header_test() {
+        let example = r#"Hawk id="dh37fgj492je", ts="1353832234", mac="6R4rV5iE+NPoym+WwjeHzjAGXUtLNIxmo1vpMofpLAE=""#;
+        assert_eq!(
+            Authorization::from_header(example).unwrap(),
+            Authorization::Other {
+                scheme: "Hawk".into(),
+                params: r#"id="dh37fgj492je", ts="1353832234", mac="6R4rV5iE+NPoym+WwjeHzjAGXUtLNIxmo1vpMofpLAE=""#.into(),
+            },
+        );
+
+        let example = "AWS4-HMAC-SHA256 Credential=AKID/20130524/us-east-1/s3/aws4_request, Signature={{signature}}";
+        let auth = Authorization::from_header(example).unwrap();
+        assert!(matches!(&auth, Authorization::Other { scheme, .. } if scheme == "AWS4-HMAC-SHA256"));
+        assert_eq!(auth.to_header(), example);
+
+        // A Basic value that isn't base64 is kept as written
+        let auth = Authorization::from_header("Basic {{credentials}}").unwrap();
+        assert_eq!(auth, Authorization::Other { scheme: "Basic".into(), params: "{{credentials}}".into() });
+
+        assert_eq!(Authorization::from_header("Bearer {{token}}").unwrap(), Authorization::Bearer("{{token}}".into()));
+        assert_eq!(Authorization::from_header("Negotiate").unwrap().to_header(), "Negotiate");
+        assert!(Authorization::from_header("").is_err());
+        assert!(Authorization::from_header("   ").is_err());
+    }
+
+    #[test]
+    fn parse_multiple_auth_header_test() {
+        let example = "Bearer abc123, Basic dXNlcjpwYXNz";
+        let auth = Authorization::from_header(example).unwrap();
+        assert_eq!(auth, Authorization::Multiple(vec![
+            Authorization::Bearer("abc123".into()),
+            Authorization::Basic { username: "user".into(), password: Some("pass".into()) },
+        ]));
+        assert_eq!(auth.to_header(), example);
+
+        let example = r#"Basic dXNlcjpwYXNz, Digest username="ben", realm="api", nonce="n", uri="/", response="r""#;
+        let Authorization::Multiple(schemes) = Authorization::from_header(example).unwrap() else {
+            panic!("Expected multiple schemes");
+        };
+        assert_eq!(schemes.len(), 2);
+        assert!(matches!(&schemes[1], Authorization::Digest { username, .. } if username == "ben"));
+
+        // A trailing comma is part of the token
+        assert_eq!(Authorization::from_header("Bearer abc,").unwrap(), Authorization::Bearer("abc,".into()));
+        assert_eq!(Authorization::from_header("Bearer abc, Basic").unwrap(), Authorization::Bearer("abc, Basic".into()));
+
+        // A scheme name inside quotes doesn't start another credential
+        let example = r#"Foo realm="a, Bearer b""#;
+        assert_eq!(Authorization::from_header(example).unwrap(), Authorization::Other {
+            scheme: "Foo".into(),
+            params: r#"realm="a, Bearer b""#.into(),
+        });
+    }
 }
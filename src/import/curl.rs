@@ -0,0 +1,246 @@
+//! Import a `curl` command as a request
+//! `curl -X POST 'https://example.com/users?page=1' -H 'Content-Type: application/json' -d '{"a": 1}'`
+
+use anyhow::{anyhow, Context};
+use base64::prelude::*;
+use url::form_urlencoded;
+
+use crate::headers::AUTHORIZATION_HEADER;
+use crate::parser::{RequestBlock, BODY_DELIMITER, FORM_URL_ENCODED, REQUEST_NEWLINE};
+use crate::{ParseOptions, RestFlavor, RestRequest};
+
+const CONTENT_TYPE: &str = "Content-Type";
+
+/// Flags that take a value but don't change the request
+const IGNORED_VALUE_FLAGS: &[&str] = &[
+    "o", "output", "m", "max-time", "connect-timeout", "w", "write-out", "retry",
+];
+
+impl RestRequest {
+    /// Parse a `curl` command into a request
+    /// Understands `-X`, `-H`, `-d` (and `--data-raw`, `--data-binary`), `--data-urlencode` and `-u`
+    /// Multiple data flags are joined with `&` and `-d @file` loads the body from a file
+    /// Other flags are ignored
+    pub fn from_curl(cmd: &str) -> anyhow::Result<Self> {
+        let args = split_shell_words(cmd)?;
+        let mut args = args
+            .iter()
+            .map(String::as_str)
+            .skip_while(|arg| *arg == "curl")
+            .peekable();
+
+        let mut method: Option<String> = None;
+        let mut url: Option<String> = None;
+        let mut headers: Vec<String> = vec![];
+        let mut data: Vec<String> = vec![];
+        let mut body_file: Option<String> = None;
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.strip_prefix("--") {
+                Some(long) => match long.split_once('=') {
+                    Some((flag, value)) => (flag, Some(value)),
+                    None => (long, None),
+                },
+                None => match arg.strip_prefix('-') {
+                    Some(short) if !short.is_empty() => {
+                        let (flag, value) = short.split_at(1);
+                        (flag, Some(value).filter(|value| !value.is_empty()))
+                    }
+                    _ => {
+                        url = Some(arg.to_string());
+                        continue;
+                    }
+                },
+            };
+
+            let takes_value = matches!(
+                flag,
+                "X" | "request" | "H" | "header" | "d" | "data" | "data-raw" | "data-binary"
+                    | "data-ascii" | "data-urlencode" | "u" | "user" | "url"
+            ) || IGNORED_VALUE_FLAGS.contains(&flag);
+            if !takes_value {
+                continue;
+            }
+
+            let value = inline_value
+                .or_else(|| args.next())
+                .ok_or_else(|| anyhow!("Missing value for curl flag {arg}"))?;
+            match flag {
+                "X" | "request" => method = Some(value.to_uppercase()),
+                "H" | "header" => headers.push(value.to_string()),
+                "d" | "data" | "data-binary" | "data-ascii" => match value.strip_prefix('@') {
+                    Some(path) => body_file = Some(path.to_string()),
+                    None => data.push(value.to_string()),
+                },
+                // Unlike `-d`, a leading `@` is literal
+                "data-raw" => data.push(value.to_string()),
+                "data-urlencode" => data.push(url_encode_data(value)),
+                "u" | "user" => {
+                    let encoded = BASE64_STANDARD.encode(value);
+                    headers.push(format!("{AUTHORIZATION_HEADER}: Basic {encoded}"));
+                }
+                "url" => url = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        let url = url.ok_or_else(|| anyhow!("The curl command has no URL"))?;
+        let body = match (body_file, data.is_empty()) {
+            (Some(path), true) => Some(format!("< {path}")),
+            (Some(_), false) => {
+                return Err(anyhow!("A curl body file can't be combined with other data"))
+            }
+            (None, true) => None,
+            (None, false) => Some(data.join("&")),
+        };
+        let method = method.unwrap_or_else(|| match body {
+            Some(_) => "POST".into(),
+            None => "GET".into(),
+        });
+
+        let has_content_type = headers.iter().any(|header| {
+            header
+                .split_once(':')
+                .is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case(CONTENT_TYPE))
+        });
+        // curl sends data as a form unless told otherwise
+        if body.is_some() && !has_content_type {
+            headers.push(format!("{CONTENT_TYPE}: {FORM_URL_ENCODED}"));
+        }
+
+        let mut raw_request = format!("{method} {url} HTTP/1.1");
+        for header in headers {
+            raw_request += REQUEST_NEWLINE;
+            raw_request += &header;
+        }
+        if let Some(body) = body {
+            raw_request += BODY_DELIMITER;
+            raw_request += &body;
+        }
+
+        let block = RequestBlock { raw_request, ..Default::default() };
+        RestRequest::from_raw_request(block, RestFlavor::Generic, &ParseOptions::default())
+            .context("Failed to parse the curl command as a request")
+    }
+}
+
+/// `--data-urlencode` encodes the content but not the name: `name=content`
+fn url_encode_data(value: &str) -> String {
+    let encode = |content: &str| form_urlencoded::byte_serialize(content.as_bytes()).collect::<String>();
+    match value.split_once('=') {
+        Some(("", content)) => encode(content),
+        Some((name, content)) => format!("{name}={}", encode(content)),
+        None => encode(value),
+    }
+}
+
+/// Split a command into arguments like a POSIX shell
+/// Handles single and double quotes, backslash escapes and line continuations
+fn split_shell_words(input: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = vec![];
+    let mut current: Option<String> = None;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(escaped) => current.get_or_insert_with(String::new).push(escaped),
+                None => {}
+            },
+            '\'' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unclosed single quote in curl command")),
+                    }
+                }
+            }
+            '"' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {}
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => word.push(escaped),
+                            Some(other) => {
+                                word.push('\\');
+                                word.push(other);
+                            }
+                            None => return Err(anyhow!("Unclosed double quote in curl command")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unclosed double quote in curl command")),
+                    }
+                }
+            }
+            c if c.is_whitespace() => words.extend(current.take()),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(current);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::headers::Authorization;
+    use crate::template::Template;
+    use crate::Body;
+
+    #[test]
+    fn split_shell_words_test() {
+        let words = split_shell_words("curl -H 'A: b c' \"x \\\"y\\\"\" plain\\ word \\\n  ''").unwrap();
+        assert_eq!(words, vec!["curl", "-H", "A: b c", "x \"y\"", "plain word", ""]);
+        assert!(split_shell_words("curl 'oops").is_err());
+    }
+
+    #[test]
+    fn from_curl_test() {
+        let cmd = r#"curl -X PUT 'https://example.com/users/1?fields=name&tags[]=a' \
+            -H 'Content-Type: application/json' \
+            -H "Accept: */*" \
+            -u user:pass \
+            --data '{"name": "Ben"}'"#;
+        let request = RestRequest::from_curl(cmd).unwrap();
+        assert_eq!(request.method.raw, "PUT");
+        assert_eq!(request.url.raw, "https://example.com/users/1");
+        assert_eq!(request.query.get("fields").unwrap().raw, "name");
+        assert_eq!(request.query.get("tags[]").unwrap().raw, "a");
+        assert_eq!(request.headers.get("Content-Type").unwrap().raw, "application/json");
+        assert_eq!(request.headers.get("Accept").unwrap().raw, "*/*");
+        assert_eq!(request.authorization, Some(Authorization::Basic {
+            username: "user".into(),
+            password: Some("pass".into()),
+        }));
+        assert_eq!(request.body, Some(Body::Text(Template::new(r#"{"name": "Ben"}"#))));
+
+        // Data without a method is a form POST
+        let request = RestRequest::from_curl(
+            "curl -sL https://example.com/search -d q=1 --data-urlencode 'name=a b&c'",
+        ).unwrap();
+        assert_eq!(request.method.raw, "POST");
+        assert_eq!(request.headers.get("Content-Type").unwrap().raw, FORM_URL_ENCODED);
+        assert_eq!(request.body, Some(Body::Text(Template::new("q=1&name=a+b%26c"))));
+
+        let request = RestRequest::from_curl("curl https://example.com --data-binary @./body.json").unwrap();
+        assert!(matches!(request.body, Some(Body::LoadFromFile { .. })));
+
+        let request = RestRequest::from_curl("curl -XDELETE --url=https://example.com/1 -o out.txt").unwrap();
+        assert_eq!(request.method.raw, "DELETE");
+        assert_eq!(request.url.raw, "https://example.com/1");
+        assert_eq!(request.body, None);
+
+        let request = RestRequest::from_curl("curl https://example.com --data-raw @handle").unwrap();
+        assert_eq!(request.body, Some(Body::Text(Template::new("@handle"))));
+        assert!(RestRequest::from_curl("curl https://example.com -d a=1 -d @./body.json").is_err());
+
+        assert!(RestRequest::from_curl("curl -X GET").is_err());
+        assert!(RestRequest::from_curl("curl https://example.com -H").is_err());
+    }
+}
@@ -0,0 +1,3 @@
+//! Converting the formats of other HTTP clients into requests
+
+pub mod curl;
@@ -1,13 +1,13 @@
 use indexmap::IndexMap;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_till},
+    bytes::complete::{tag, take_till, take_till1},
     character::complete::{
         alpha1, alphanumeric1, char, newline, space0, space1,
     },
-    combinator::{opt, recognize},
+    combinator::{map, opt, recognize},
     multi::many0_count,
-    sequence::{pair, tuple},
+    sequence::{pair, preceded, tuple},
     IResult, Parser,
 };
 use std::str;
@@ -20,6 +20,13 @@ const REQUEST_DELIMITER: &str = "###";
 
 const NAME_ANNOTATION: &str = "@name";
 const COMMAND_ANNOTATION: &str = "@";
+const ESCAPED_AT: &str = "\\@";
+const REGION_START: &str = "region";
+const REGION_END: &str = "endregion";
+
+const PRE_REQUEST_SCRIPT_START: &str = "< {%";
+const RESPONSE_HANDLER_START: &str = "> {%";
+const SCRIPT_END: &str = "%}";
 
 /// A single line during parsing
 /// This is the equivalent of a lex token
@@ -27,6 +34,7 @@ const COMMAND_ANNOTATION: &str = "@";
 pub enum Line {
     /// A section seperator:
     /// `### RequestName` or `###`
+    /// Inline commands (`### RequestName @no-log`) follow as `Command` lines
     Seperator(Option<String>),
     
     /// A request name annotation:
@@ -40,36 +48,110 @@ pub enum Line {
         params: Option<String>,
     },
 
+    /// A variable declared inside a request block:
+    /// `@my_variable = hello`
+    /// Variables declared before the first request are file-level
+    /// and returned separately by `parse_lines`
+    Variable {
+        name: String,
+        value: Template,
+    },
+
+    /// The start or end of a named group of requests:
+    /// `# region Auth` is `Some("Auth")` and `# endregion` is `None`
+    Region(Option<String>),
+
+    /// A Jetbrains pre-request script before the request line:
+    /// `< {% request.variables.set("name", "value") %}`
+    /// Only the inside of the `{% %}` is kept
+    PreRequestScript(String),
+
     /// A single line of a request:
     /// `POST https://example.com HTTP/1.1`
     Request(String),
 }
 
-/// Attempt to parse an optionally named seperator
-/// `### {optional_name}`
-fn parse_seperator(input: &str) -> IResult<&str, Option<String>> {
+/// A command parsed from a seperator line: `(name, params)`
+type SeperatorCommand = (String, Option<String>);
+
+/// Attempt to parse an optionally named seperator with optional commands
+/// `### {optional_name} {optional @commands}`
+/// `### GetUser @no-log @timeout 10`
+fn parse_seperator(
+    input: &str,
+) -> IResult<&str, (Option<String>, Vec<SeperatorCommand>)> {
     let (input, _) = tag(REQUEST_DELIMITER)(input)?;
-    let (input, req_name) =
-        opt(pair(space1, take_till(|c| c == ' ' || c == '\n')))(input)?;
+    let (input, rest) = opt(pair(space1, take_till(|c| c == '\n')))(input)?;
+
+    let mut name_words: Vec<&str> = vec![];
+    let mut commands: Vec<(String, Vec<&str>)> = vec![];
+    for word in rest.map(|(_, r)| r).unwrap_or("").split_whitespace() {
+        match (word.strip_prefix(COMMAND_ANNOTATION), commands.last_mut()) {
+            (Some(cmd_name), _) if !cmd_name.is_empty() => {
+                commands.push((cmd_name.to_string(), vec![]))
+            }
+            (_, Some((_, params))) => params.push(word),
+            (_, None) => name_words.push(word),
+        }
+    }
 
-    let potential_name = req_name.map(|(_, name)| name.to_string());
-    Ok((input, potential_name))
+    let potential_name = match name_words.join(" ") {
+        name if name.is_empty() => None,
+        name => Some(strip_quotes(&name).to_string()),
+    };
+
+    let commands = commands
+        .into_iter()
+        .map(|(name, params)| match params.join(" ") {
+            params if params.is_empty() => (name, None),
+            params => (name, Some(params)),
+        })
+        .collect();
+
+    Ok((input, (potential_name, commands)))
 }
 
 /// A comment can start with `//` or `#`
-fn starting_comment(line: &str) -> StrResult {
+fn starting_comment(line: &str) -> StrResult<'_> {
     alt((tag("//"), tag("#")))(line)
 }
 
 /// Attempt to parse a name annotation
-/// `# @name RequestName`
+/// `# @name RequestName`, `# @name Get User` or `# @name "Get User"`
 fn parse_request_name_annotation(input: &str) -> IResult<&str, &str> {
     let (input, _) = pair(starting_comment, space0)(input)?;
     let (input, _) = tag(NAME_ANNOTATION)(input)?;
     let (input, _) = pair(alt((char('='), char(' '))), space0)(input)?;
-    let (input, req_name) = take_till(|c| c == ' ' || c == '\n')(input)?;
+    let (input, req_name) = take_till(|c| c == '\n')(input)?;
+
+    Ok((input, strip_quotes(req_name.trim())))
+}
 
-    Ok((input, req_name))
+/// Attempt to parse a region comment
+/// `# region Auth` -> `Some("Auth")`, `# endregion` or `# endregion Auth` -> `None`
+fn parse_region(input: &str) -> IResult<&str, Option<&str>> {
+    let (input, _) = pair(starting_comment, space0)(input)?;
+    alt((
+        map(
+            tuple((tag(REGION_END), opt(pair(space1, take_till(|c| c == '\n'))), newline)),
+            |_| None,
+        ),
+        map(
+            preceded(pair(tag(REGION_START), space1), take_till1(|c| c == '\n')),
+            |name: &str| Some(name.trim()),
+        ),
+    ))(input)
+}
+
+/// Remove a matching pair of surrounding quotes
+/// `"Get User"` -> `Get User`
+fn strip_quotes(input: &str) -> &str {
+    for quote in ['"', '\''] {
+        if input.len() >= 2 && input.starts_with(quote) && input.ends_with(quote) {
+            return &input[1..input.len() - 1];
+        }
+    }
+    input
 }
 
 
@@ -92,6 +174,11 @@ fn parse_request_command(input: &str) -> IResult<&str, (&str, Option<&str>)> {
 }
 
 
+/// A dynamic system variable: `$guid`, `$randomInt`
+pub fn parse_system_variable_identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(char('$'), parse_variable_identifier)).parse(input)
+}
+
 pub fn parse_variable_identifier(input: &str) -> IResult<&str, &str> {
     recognize(pair(
         alpha1,
@@ -121,28 +208,97 @@ fn is_comment(line: &str) -> bool {
 }
 
 /// Parse an input string line by line
+/// Returns the lines and the file-level variables
 pub fn parse_lines(
     input: &str,
 ) -> anyhow::Result<(Vec<Line>, RestVariables)> {
-    let mut lines: Vec<Line> = vec![];
+    let (lines, variables) = parse_numbered_lines(input)?;
+    Ok((lines.into_iter().map(|(_, line)| line).collect(), variables))
+}
+
+/// Like `parse_lines` but each line is paired with its 1-based line number in `input`
+/// A multi-line pre-request script has the number of its first line
+pub fn parse_numbered_lines(
+    input: &str,
+) -> anyhow::Result<(Vec<(usize, Line)>, RestVariables)> {
+    let mut lines: Vec<(usize, Line)> = vec![];
     let mut variables: IndexMap<String, Template> = IndexMap::new();
-    for line in input.trim().lines() {
-        let line = &format!("{line}\n");
-        if let Ok((_, seperator_name)) = parse_seperator(line) {
-            lines.push(Line::Seperator(seperator_name));
+    // Once a seperator or request line is seen, variables belong to a request
+    let mut in_request_block = false;
+    // The lines of a pre-request script that hasn't been closed yet
+    let mut script: Option<(usize, Vec<&str>)> = None;
+    // Inside a response handler script that hasn't been closed yet
+    let mut in_handler = false;
+    // Between the request line and the blank line before the body
+    let mut in_headers = false;
+    let mut in_body = false;
+    // The lines trimmed from the start still count towards the line numbers
+    let skipped = input[..input.len() - input.trim_start().len()].matches('\n').count();
+    for (index, raw_line) in input.trim().lines().enumerate() {
+        let number = skipped + index + 1;
+        if let Some((start, script_lines)) = &mut script {
+            match raw_line.trim_end().strip_suffix(SCRIPT_END) {
+                Some(last) => {
+                    script_lines.push(last);
+                    lines.push((*start, Line::PreRequestScript(script_lines.join("\n").trim().into())));
+                    script = None;
+                }
+                None => script_lines.push(raw_line),
+            }
+            continue;
+        }
+
+        // Response handlers are left to the parser, their lines are kept as is
+        // so comments in the script aren't dropped
+        if in_handler {
+            in_handler = !raw_line.contains(SCRIPT_END);
+            lines.push((number, Line::Request(raw_line.trim_end().into())));
+            continue;
+        }
+        if let Some(start) = raw_line.trim_start().strip_prefix(RESPONSE_HANDLER_START) {
+            in_request_block = true;
+            in_handler = !start.contains(SCRIPT_END);
+            lines.push((number, Line::Request(raw_line.trim_end().into())));
+            continue;
+        }
+
+        if let Some(start) = raw_line.trim_start().strip_prefix(PRE_REQUEST_SCRIPT_START) {
+            in_request_block = true;
+            match start.trim_end().strip_suffix(SCRIPT_END) {
+                Some(inline) => lines.push((number, Line::PreRequestScript(inline.trim().into()))),
+                None => script = Some((number, vec![start])),
+            }
+            continue;
+        }
+
+        let line = &format!("{raw_line}\n");
+        if let Ok((_, (seperator_name, commands))) = parse_seperator(line) {
+            lines.push((number, Line::Seperator(seperator_name)));
+            // Inline commands apply to the upcoming request
+            for (name, params) in commands {
+                lines.push((number, Line::Command { name, params }));
+            }
+            in_request_block = true;
+            in_headers = false;
+            in_body = false;
             continue;
         }
 
         if let Ok((_, name)) = parse_request_name_annotation(line) {
-            lines.push(Line::Name(name.into()));
+            lines.push((number, Line::Name(name.into())));
+            continue;
+        }
+
+        if let Ok((_, region)) = parse_region(line) {
+            lines.push((number, Line::Region(region.map(String::from))));
             continue;
         }
 
         if let Ok((_, (name, params))) = parse_request_command(line) {
-            lines.push(Line::Command {
+            lines.push((number, Line::Command {
                 name: name.to_string(),
                 params: params.map(|x| x.to_string()),
-            });
+            }));
             continue;
         }
 
@@ -152,12 +308,45 @@ pub fn parse_lines(
             continue
         }
 
+        // `\@` at the start of a line is a literal `@`, not a variable
+        if let Some(rest) = line.strip_prefix(ESCAPED_AT) {
+            in_request_block = true;
+            lines.push((number, Line::Request(format!("@{}", rest.trim_end()))));
+            continue;
+        }
+
         if let Ok((_, (key, val))) = parse_variable_assignment(line) {
-            variables.insert(key.into(), Template::new(val));
+            if in_request_block {
+                lines.push((number, Line::Variable {
+                    name: key.into(),
+                    value: Template::new(val),
+                }));
+            } else {
+                variables.insert(key.into(), Template::new(val));
+            }
             continue;
         }
 
-        lines.push(Line::Request(line.trim().into()));
+        // An indented line in the headers continues a folded header, the parser joins it
+        let trimmed = line.trim();
+        let is_continuation = in_headers && line.starts_with([' ', '\t']) && !trimmed.is_empty();
+        match is_continuation {
+            true => lines.push((number, Line::Request(line.trim_end().into()))),
+            false => lines.push((number, Line::Request(trimmed.into()))),
+        }
+
+        match trimmed.is_empty() {
+            true if in_headers => {
+                in_headers = false;
+                in_body = true;
+            }
+            // Blank lines before the first request don't start a block
+            true => {}
+            false => {
+                in_request_block = true;
+                in_headers = !in_body;
+            }
+        }
     }
     Ok((lines, variables))
 }
@@ -186,18 +375,54 @@ mod test {
         assert_eq!(var, ("Cool-Word", "super_cool"));
     }
 
+    #[test]
+    fn parse_variable_with_equals_test() {
+        let example_var = "@QUERY = a=1&b=2\n";
+        let (rest, var) = parse_variable_assignment(example_var).unwrap();
+
+        assert_eq!(var, ("QUERY", "a=1&b=2"));
+        assert_eq!(rest, "");
+
+        let example_var = "@TOKEN=abc==\n";
+        let (_, var) = parse_variable_assignment(example_var).unwrap();
+
+        assert_eq!(var, ("TOKEN", "abc=="));
+    }
+
+    #[test]
+    fn parse_empty_variable_test() {
+        for example_var in ["@EMPTY =\n", "@EMPTY = \n", "@EMPTY=\n"] {
+            let (rest, var) = parse_variable_assignment(example_var).unwrap();
+            assert_eq!(var, ("EMPTY", ""), "{example_var:?}");
+            assert_eq!(rest, "");
+        }
+    }
+
+    #[test]
+    fn parse_escaped_at_test() {
+        let input = "POST https://example.com HTTP/1.1\n\n\\@handle = not a variable\n\\@mention";
+        let (lines, variables) = parse_lines(input).unwrap();
+        assert!(variables.is_empty());
+        assert_eq!(lines, vec![
+            Line::Request("POST https://example.com HTTP/1.1".into()),
+            Line::Request("".into()),
+            Line::Request("@handle = not a variable".into()),
+            Line::Request("@mention".into()),
+        ]);
+    }
+
     #[test]
     fn parse_seperator_line() {
         let line = "### RequestName";
-        let (_, name_opt) = parse_seperator(line).unwrap();
+        let (_, (name_opt, _)) = parse_seperator(line).unwrap();
         assert_eq!(name_opt, Some("RequestName".into()));
 
         let line = "#######";
-        let (_, name_opt) = parse_seperator(line).unwrap();
+        let (_, (name_opt, _)) = parse_seperator(line).unwrap();
         assert_eq!(name_opt, None);
 
         let line = "###";
-        let (_, name_opt) = parse_seperator(line).unwrap();
+        let (_, (name_opt, _)) = parse_seperator(line).unwrap();
         assert_eq!(name_opt, None);
 
         let line = "#";
@@ -205,6 +430,44 @@ mod test {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn parse_seperator_commands_test() {
+        let line = "### Foo @no-log @timeout 10\n";
+        let (_, (name_opt, commands)) = parse_seperator(line).unwrap();
+        assert_eq!(name_opt, Some("Foo".into()));
+        assert_eq!(commands, vec![
+            ("no-log".to_string(), None),
+            ("timeout".to_string(), Some("10".to_string())),
+        ]);
+
+        let line = "### @connection-timeout 2 m\n";
+        let (_, (name_opt, commands)) = parse_seperator(line).unwrap();
+        assert_eq!(name_opt, None);
+        assert_eq!(commands, vec![
+            ("connection-timeout".to_string(), Some("2 m".to_string())),
+        ]);
+
+        let (lines, _) = parse_lines("### Foo @no-log @timeout 10\nGET https://example.com").unwrap();
+        assert_eq!(lines, vec![
+            Line::Seperator(Some("Foo".into())),
+            Line::Command { name: "no-log".into(), params: None },
+            Line::Command { name: "timeout".into(), params: Some("10".into()) },
+            Line::Request("GET https://example.com".into()),
+        ]);
+    }
+
+    #[test]
+    fn parse_region_test() {
+        assert_eq!(parse_region("# region Auth\n").unwrap().1, Some("Auth"));
+        assert_eq!(parse_region("//region User Admin \n").unwrap().1, Some("User Admin"));
+        assert_eq!(parse_region("# endregion\n").unwrap().1, None);
+        assert_eq!(parse_region("# endregion Auth\n").unwrap().1, None);
+
+        assert!(parse_region("# regional offices\n").is_err());
+        assert!(parse_region("# region\n").is_err());
+        assert!(parse_region("# just a comment\n").is_err());
+    }
+
     #[test]
     fn parse_request_name_test() {
         let line = "# @name=hello";
@@ -219,6 +482,26 @@ mod test {
         assert!(parse_request_name_annotation(line).is_err());
     }
 
+    #[test]
+    fn parse_multi_word_request_name_test() {
+        let line = "# @name \"Get User\"\n";
+        let (_, name) = parse_request_name_annotation(line).unwrap();
+        assert_eq!(name, "Get User");
+
+        let line = "# @name Get User\n";
+        let (_, name) = parse_request_name_annotation(line).unwrap();
+        assert_eq!(name, "Get User");
+
+        let line = "// @name='Get User' \n";
+        let (_, name) = parse_request_name_annotation(line).unwrap();
+        assert_eq!(name, "Get User");
+
+        // Unmatched quotes are kept
+        let line = "# @name \"Get User\n";
+        let (_, name) = parse_request_name_annotation(line).unwrap();
+        assert_eq!(name, "\"Get User");
+    }
+
     #[test]
     fn parse_request_command_test() {
         let line = "# @no-log";
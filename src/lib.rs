@@ -3,6 +3,20 @@ pub mod parser;
 pub mod format;
 pub mod headers;
 pub mod template;
+pub mod system;
+pub mod command;
+pub mod method;
+#[cfg(feature = "json")]
+pub mod env;
+pub mod export;
+pub mod import;
+#[cfg(feature = "reqwest")]
+pub mod reqwest;
 
-pub use format::RestFormat;
-pub use parser::{RestRequest, RestVariables, RestFlavor, Body};
+pub use command::Command;
+pub use method::Method;
+pub use format::{RestFormat, FormatDiff, ParseError, Section};
+pub use parser::{
+    RestRequest, RestUrl, QueryParams, QueryMap, RestVariables, RestVariablesExt, RestFlavor, Body, BodyKind, ParseOptions,
+    MultipartPart, MultipartContent, ResponseHandler,
+};
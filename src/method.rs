@@ -0,0 +1,130 @@
+//! Typed HTTP methods: `GET`, `POST`, etc
+//! The method is stored as written in `RestRequest::method`,
+//! use `RestRequest::method_kind` to get it as a `Method`
+
+use std::fmt;
+
+use crate::RestRequest;
+
+/// The method of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Head,
+    Options,
+    Trace,
+    Connect,
+    /// Any other method, a typo like `PSOT` or a templated method like `{{verb}}`
+    Custom(String),
+}
+
+impl Method {
+    /// Classify a method, the standard methods match in any case: `get` is `Method::Get`
+    pub fn parse(method: &str) -> Self {
+        let method = method.trim();
+        let standard = [
+            Self::Get,
+            Self::Post,
+            Self::Put,
+            Self::Delete,
+            Self::Patch,
+            Self::Head,
+            Self::Options,
+            Self::Trace,
+            Self::Connect,
+        ];
+        standard
+            .into_iter()
+            .find(|standard| standard.as_str().eq_ignore_ascii_case(method))
+            .unwrap_or(Self::Custom(method.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+            Self::Patch => "PATCH",
+            Self::Head => "HEAD",
+            Self::Options => "OPTIONS",
+            Self::Trace => "TRACE",
+            Self::Connect => "CONNECT",
+            Self::Custom(method) => method,
+        }
+    }
+
+    /// Check if the method is one of the standard methods
+    pub fn is_standard(&self) -> bool {
+        !matches!(self, Self::Custom(_))
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl RestRequest {
+    /// The request's method as a `Method`
+    /// A templated method like `{{verb}}` is `Method::Custom` until it's rendered,
+    /// use `Method::parse(&request.method.render(vars))` for the rendered method
+    pub fn method_kind(&self) -> Method {
+        match self.method.as_static_str() {
+            Some(method) => Method::parse(method),
+            None => Method::Custom(self.method.raw.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RestFlavor, RestFormat};
+    use indoc::indoc;
+
+    #[test]
+    fn method_kind_test() {
+        let text = indoc! {r#"
+            ###
+            GET https://example.com HTTP/1.1
+
+            ###
+            delete https://example.com/1 HTTP/1.1
+
+            ###
+            PSOT https://example.com HTTP/1.1
+
+            ###
+            {{verb}} https://example.com HTTP/1.1
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let methods: Vec<Method> = format.requests.iter().map(RestRequest::method_kind).collect();
+        assert_eq!(methods, vec![
+            Method::Get,
+            Method::Delete,
+            Method::Custom("PSOT".into()),
+            Method::Custom("{{verb}}".into()),
+        ]);
+        assert!(!methods[2].is_standard());
+
+        // The method is kept as written
+        assert_eq!(format.requests[1].method.raw, "delete");
+        assert!(format.to_string().contains("delete https://example.com/1"));
+    }
+
+    #[test]
+    fn parse_method_test() {
+        assert_eq!(Method::parse("PATCH"), Method::Patch);
+        assert_eq!(Method::parse(" options "), Method::Options);
+        assert_eq!(Method::parse("PROPFIND"), Method::Custom("PROPFIND".into()));
+        assert_eq!(Method::Connect.to_string(), "CONNECT");
+        assert_eq!(Method::Custom("PURGE".into()).as_str(), "PURGE");
+    }
+}
@@ -1,33 +1,138 @@
-///! Parses a `.rest` or `.http` file
-///! These files are used in many IDEs such as Jetbrains, VSCode, and
-///! Visual Studio Jetbrains and nvim-rest call it `.http`
-///! VSCode and Visual Studio call it `.rest`
+//! Parses a `.rest` or `.http` file
+//! These files are used in many IDEs such as Jetbrains, VSCode, and
+//! Visual Studio Jetbrains and nvim-rest call it `.http`
+//! VSCode and Visual Studio call it `.rest`
 
 use anyhow::{anyhow, Context};
 use indexmap::IndexMap;
 use nom::{
-    bytes::{complete::tag, streaming::take_until}, character::complete::alphanumeric1, combinator::opt, error::Error as NomError, sequence::pair, IResult
+    bytes::{complete::{tag, take_while1}, streaming::take_until}, combinator::opt, error::Error as NomError, sequence::pair, IResult
 };
 use core::fmt;
 use std::{path::Path, str::{self, FromStr}};
-use url::Url;
+use url::{form_urlencoded, Url};
 
-use crate::template::Template;
+use crate::system::{dotenv_entry, resolve_system_variable, DOTENV};
+use crate::template::{try_render_map, Template, TemplateMap, TemplatePart};
 
-use super::headers::{Authorization, RestHeaders};
+use super::headers::{Authorization, ContentType, RestHeaders, AUTHORIZATION_HEADER, OAUTH2_COMMAND};
 
 type StrResult<'a> = Result<(&'a str, &'a str), nom::Err<NomError<&'a str>>>;
 
 pub(crate) const REQUEST_NEWLINE: &str = "\r\n";
 pub(crate) const BODY_DELIMITER: &str = "\r\n\r\n";
 
-const FORM_URL_ENCODED: &str = "application/x-www-form-urlencoded";
+pub(crate) const FORM_URL_ENCODED: &str = "application/x-www-form-urlencoded";
+const JSON_CONTENT_TYPE: &str = "application/json";
+const NDJSON_CONTENT_TYPES: &[&str] = &["application/x-ndjson", "application/jsonlines"];
+const TEXT_CONTENT_TYPE: &str = "text/plain";
+const XML_CONTENT_TYPE: &str = "application/xml";
+const GRAPHQL_CONTENT_TYPE: &str = "application/graphql";
+const MULTIPART_CONTENT_TYPE: &str = "multipart/form-data";
+const CONTENT_DISPOSITION: &str = "Content-Disposition";
+
+const DISABLED_COMMAND: &str = "disabled";
+const LANGUAGE_COMMAND: &str = "language";
+pub(crate) const HTTP_VERSION_COMMAND: &str = "http-version";
+const HTTP_VERSION_PREFIX: &str = "HTTP/";
+const SETTINGS_COMMAND: &str = "settings";
+
+const CONTENT_LENGTH: &str = "Content-Length";
+
+/// Methods that conventionally have no body
+const BODILESS_METHODS: &[&str] = &["HEAD", "OPTIONS", "TRACE"];
+
+const HTTP_METHODS: &[&str] = &[
+    "GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "TRACE", "CONNECT",
+];
 
 pub type RestVariables = IndexMap<String, Template>;
 
+/// Query parameters with every value of each key in order: `?tags[]=a&tags[]=b`
+pub type QueryMap = IndexMap<String, Vec<Template>>;
+
+/// Helpers for building and working with `RestVariables`
+pub trait RestVariablesExt {
+    /// Extend the variables from plain string pairs
+    /// `vars.extend_str([("HOST", "https://example.com")])`
+    fn extend_str<'a>(&mut self, pairs: impl IntoIterator<Item = (&'a str, &'a str)>);
+
+    /// Render every variable, following references to other variables
+    /// Undefined variables use their default or render as empty, cyclic references are an error
+    /// `{{$dotenv KEY}}` uses the `.env` values like `Template::render`, see `RestFormat::dotenv_variables`
+    /// The result keeps the declaration order
+    fn resolve_all(&self) -> anyhow::Result<IndexMap<String, String>>;
+}
+
+impl RestVariablesExt for RestVariables {
+    fn extend_str<'a>(&mut self, pairs: impl IntoIterator<Item = (&'a str, &'a str)>) {
+        self.extend(
+            pairs
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), Template::new(value))),
+        );
+    }
+
+    fn resolve_all(&self) -> anyhow::Result<IndexMap<String, String>> {
+        let mut resolved = IndexMap::new();
+        for name in self.keys() {
+            resolve_variable(self, name, &mut resolved, &mut vec![])?;
+        }
+        Ok(self
+            .keys()
+            .filter_map(|name| resolved.shift_remove_entry(name))
+            .collect())
+    }
+}
+
+/// Resolve a variable depth first, `stack` holds the chain being resolved
+fn resolve_variable<'a>(
+    variables: &'a RestVariables,
+    name: &'a str,
+    resolved: &mut IndexMap<String, String>,
+    stack: &mut Vec<&'a str>,
+) -> anyhow::Result<String> {
+    if let Some(value) = resolved.get(name) {
+        return Ok(value.clone());
+    }
+    let Some(template) = variables.get(name) else {
+        return Ok("".into());
+    };
+    if stack.contains(&name) {
+        stack.push(name);
+        return Err(anyhow!("Cyclic variable reference: {}", stack.join(" -> ")));
+    }
+
+    stack.push(name);
+    let mut value = "".to_string();
+    for part in &template.parts {
+        match part {
+            TemplatePart::Text(text) => value += text,
+            TemplatePart::Variable { name: inner, default, .. } => match variables.contains_key(inner) {
+                true => value += &resolve_variable(variables, inner, resolved, stack)?,
+                false => value += default.as_deref().unwrap_or(""),
+            },
+            TemplatePart::System { name, args } if name == DOTENV => {
+                if let Some((key, _)) = dotenv_entry(variables, args) {
+                    value += &resolve_variable(variables, key, resolved, stack)?;
+                }
+            }
+            TemplatePart::System { name, args } => {
+                value += &resolve_system_variable(name, args).unwrap_or_default()
+            }
+            TemplatePart::Response { .. } => {}
+        }
+    }
+    stack.pop();
+
+    resolved.insert(name.to_string(), value.clone());
+    Ok(value)
+}
+
 /// The specific type of REST file.
 /// They are all similar with slightly different feature sets
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RestFlavor {
     Vscode,
     Jetbrains,
@@ -44,6 +149,31 @@ impl RestFlavor {
             _ => Self::Generic,
         }
     }
+
+    /// The file extension used when writing this flavor, the inverse of `from_path`
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Vscode => "rest",
+            Self::Jetbrains | Self::Generic => "http",
+        }
+    }
+
+    /// The content type to assume for a body without a `Content-Type` header
+    /// A body that looks like JSON (`{`, `[`) or XML (`<?xml`, `<tag`) is inferred by every flavor,
+    /// Jetbrains treats any other body as plain text and the others don't guess
+    pub fn default_content_type(&self, body_hint: &str) -> Option<&'static str> {
+        let trimmed = body_hint.trim_start();
+        let looks_like_json = trimmed.starts_with('{') || trimmed.starts_with('[');
+        let looks_like_xml = trimmed
+            .strip_prefix('<')
+            .is_some_and(|rest| rest.starts_with("?xml") || rest.starts_with(|c: char| c.is_ascii_alphabetic()));
+        match self {
+            _ if looks_like_json => Some(JSON_CONTENT_TYPE),
+            _ if looks_like_xml => Some(XML_CONTENT_TYPE),
+            Self::Jetbrains => Some(TEXT_CONTENT_TYPE),
+            Self::Vscode | Self::Generic => None,
+        }
+    }
 }
 
 impl fmt::Display for RestFlavor {
@@ -57,13 +187,64 @@ impl fmt::Display for RestFlavor {
     }
 }
 
+/// Options to control parsing
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Error if a request body is larger than this many bytes
+    pub max_body_bytes: Option<usize>,
+    /// The most headers a request can have, defaults to 64
+    pub max_headers: Option<usize>,
+    /// Error on ambiguous input instead of picking a winner
+    /// For example a request with two different names (normally the last wins)
+    pub strict: bool,
+    /// Name unnamed requests by their position: `Request 1`, `Request 2`, etc
+    pub auto_name: bool,
+    /// Scope `@` definitions after the first `###` or request line to that request
+    /// Off by default so every definition applies to the whole file
+    pub request_variables: bool,
+    /// Resolve system variables in `@` definitions once while parsing
+    /// `@REQUEST_ID = {{$uuid}}` then has the same value everywhere it's used
+    /// Unknown system variables are kept, ignored without the `system-vars` feature
+    pub resolve_system_variables: bool,
+    /// Decode `\n`, `\r` and `\t` escapes in header values into the characters
+    /// Off by default so the escapes are kept verbatim, other backslashes are always kept
+    pub decode_header_escapes: bool,
+    /// Decode files from a legacy encoding instead of UTF-8: `windows-1252`, `shift_jis`
+    /// Any label known to `encoding_rs` works, only used when parsing files
+    pub file_encoding: Option<String>,
+}
+
+/// The header limit when `ParseOptions::max_headers` isn't set
+const DEFAULT_MAX_HEADERS: usize = 64;
+
 const LOAD_SYMBOL: &str = "<"; 
+const HANDLER_SYMBOL: &str = ">";
+const SCRIPT_START: &str = "{%";
+const SCRIPT_END: &str = "%}";
+/// A response handler file needs a script extension to tell it apart from body text
+const HANDLER_FILE_EXTENSION: &str = ".js";
 const SAVE_SYMBOL: &str = ">>"; 
 const VAR_SYMBOL: &str = "@"; 
+/// Ends a preview that was cut off
+const ELLIPSIS: &str = "...";
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Body {
     Text(Template),
+    /// Newline delimited JSON, one record per line
+    /// The line endings and the newline after the last line (if any) are kept as written
+    Ndjson(Template),
+    /// A GraphQL query followed by a blank line and a JSON block of variables
+    GraphQL {
+        query: Template,
+        variables: Option<Template>,
+    },
+    /// A `multipart/form-data` body split on the boundary from the `Content-Type`
+    Multipart {
+        boundary: String,
+        parts: Vec<MultipartPart>,
+    },
     LoadFromFile {
         process_variables: bool,
         encoding: Option<String>,
@@ -75,35 +256,150 @@ pub enum Body {
     },
 }
 
+/// A single part of a `Body::Multipart`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultipartPart {
+    /// The `name` from the `Content-Disposition` header
+    pub name: String,
+    /// The `filename` from the `Content-Disposition` header
+    pub filename: Option<String>,
+    /// Every header of the part, including `Content-Disposition`
+    pub headers: IndexMap<String, Template>,
+    pub content: MultipartContent,
+}
 
-impl Body {
-    fn parse(input: &str, content_type: &str) -> Self {
-        let input = if content_type == FORM_URL_ENCODED {
-            &input.replace("\r\n", "").replace("\n", "")
-        } else {
-            input
+/// The content of a multipart part, written inline or loaded with `< ./file.txt`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MultipartContent {
+    Text(Template),
+    LoadFromFile {
+        process_variables: bool,
+        encoding: Option<String>,
+        filepath: Template,
+    },
+}
+
+impl MultipartPart {
+    fn parse(input: &str) -> Self {
+        let input = input.strip_prefix(REQUEST_NEWLINE).unwrap_or(input);
+        let input = input.strip_suffix(REQUEST_NEWLINE).unwrap_or(input);
+        let (header_lines, content) = match input.split_once(BODY_DELIMITER) {
+            Some((header_lines, content)) => (header_lines, content),
+            None => (input, ""),
         };
 
-        fn parse_from_file(inp: &str) -> IResult<&str, Body> {
-            let (inp, _) = tag(LOAD_SYMBOL)(inp)?;
-            
-            let (inp, at_sign) = opt(tag(VAR_SYMBOL))(inp)?;
-            let process_variables = at_sign.is_some();
+        let headers: IndexMap<String, Template> = header_lines
+            .split(REQUEST_NEWLINE)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), Template::new(value.trim())))
+            .collect();
+        let disposition = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(CONTENT_DISPOSITION))
+            .map(|(_, value)| ContentType::parse(&value.raw))
+            .unwrap_or_default();
+
+        let content = match parse_from_file(content) {
+            Ok((_, Body::LoadFromFile { process_variables, encoding, filepath })) => {
+                MultipartContent::LoadFromFile { process_variables, encoding, filepath }
+            }
+            _ => MultipartContent::Text(Template::new(content)),
+        };
+
+        Self {
+            name: disposition.params.get("name").cloned().unwrap_or_default(),
+            filename: disposition.params.get("filename").cloned(),
+            headers,
+            content,
+        }
+    }
+
+    /// The `Content-Type` header of the part
+    pub fn content_type(&self) -> Option<&Template> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value)
+    }
 
-            let (inp, encoding) = opt(alphanumeric1)(inp)?;
-            let encoding = encoding.map(|e| e.to_string());
+    /// Every template in the part
+    pub fn templates(&self) -> Vec<&Template> {
+        let mut templates: Vec<&Template> = self.headers.values().collect();
+        match &self.content {
+            MultipartContent::Text(text) => templates.push(text),
+            MultipartContent::LoadFromFile { filepath, .. } => templates.push(filepath),
+        }
+        templates
+    }
+
+    /// Every template in the part, mutably
+    pub fn templates_mut(&mut self) -> Vec<&mut Template> {
+        let mut templates: Vec<&mut Template> = self.headers.values_mut().collect();
+        match &mut self.content {
+            MultipartContent::Text(text) => templates.push(text),
+            MultipartContent::LoadFromFile { filepath, .. } => templates.push(filepath),
+        }
+        templates
+    }
+}
+
+/// The kind of a `Body` without its contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    Text,
+    Ndjson,
+    GraphQL,
+    Multipart,
+    LoadFromFile,
+    SaveToFile,
+}
 
-            // A space seperates the optional encoding and the filepath 
-            let (inp, _) = tag(" ")(inp)?;
+impl Body {
+    /// Get the kind of body without exposing the inner data
+    pub fn kind(&self) -> BodyKind {
+        match self {
+            Body::Text(_) => BodyKind::Text,
+            Body::Ndjson(_) => BodyKind::Ndjson,
+            Body::GraphQL { .. } => BodyKind::GraphQL,
+            Body::Multipart { .. } => BodyKind::Multipart,
+            Body::LoadFromFile { .. } => BodyKind::LoadFromFile,
+            Body::SaveToFile { .. } => BodyKind::SaveToFile,
+        }
+    }
 
-            let body = Body::LoadFromFile { 
-                process_variables,
-                encoding,
-                filepath: Template::new(inp),
-            }; 
+    /// Every template in the body
+    pub fn templates(&self) -> Vec<&Template> {
+        match self {
+            Body::Text(text) | Body::Ndjson(text) => vec![text],
+            Body::GraphQL { query, variables } => std::iter::once(query).chain(variables).collect(),
+            Body::Multipart { parts, .. } => parts.iter().flat_map(MultipartPart::templates).collect(),
+            Body::LoadFromFile { filepath, .. } => vec![filepath],
+            Body::SaveToFile { text, filepath } => vec![text, filepath],
+        }
+    }
 
-            Ok(("", body))
+    /// Every template in the body, mutably
+    pub fn templates_mut(&mut self) -> Vec<&mut Template> {
+        match self {
+            Body::Text(text) | Body::Ndjson(text) => vec![text],
+            Body::GraphQL { query, variables } => std::iter::once(query).chain(variables).collect(),
+            Body::Multipart { parts, .. } => {
+                parts.iter_mut().flat_map(MultipartPart::templates_mut).collect()
+            }
+            Body::LoadFromFile { filepath, .. } => vec![filepath],
+            Body::SaveToFile { text, filepath } => vec![text, filepath],
         }
+    }
+
+    fn parse(input: &str, content_type: &str) -> Self {
+        let content_type = ContentType::parse(content_type);
+        let input = if content_type.matches(FORM_URL_ENCODED) {
+            &input.replace("\r\n", "").replace("\n", "")
+        } else {
+            input
+        };
 
         fn parse_save_file(inp: &str) -> IResult<&str, Body> {
             let (inp, main_body) = take_until(SAVE_SYMBOL)(inp)?;
@@ -125,43 +421,288 @@ impl Body {
             return body
         }
 
+        if NDJSON_CONTENT_TYPES.iter().any(|ndjson| content_type.matches(ndjson)) {
+            return Body::Ndjson(Template::new(input))
+        }
+
+        if content_type.matches(MULTIPART_CONTENT_TYPE) {
+            if let Some(boundary) = content_type.boundary() {
+                let parts = parse_multipart_parts(input, boundary);
+                if !parts.is_empty() {
+                    return Body::Multipart { boundary: boundary.to_string(), parts }
+                }
+            }
+        }
+
+        if content_type.matches(GRAPHQL_CONTENT_TYPE) {
+            if let Some((query, variables)) = split_graphql_variables(input) {
+                return Body::GraphQL {
+                    query: Template::new(query),
+                    variables: Some(Template::new(variables)),
+                }
+            }
+        }
+
         Body::Text(Template::new(input))
     }
+
+    /// Render the bytes that would be sent as the body, `body_text` as bytes
+    /// File bodies are `None` since they aren't read here
+    pub fn body_bytes(&self, vars: &RestVariables) -> Option<Vec<u8>> {
+        self.body_text(vars).map(String::into_bytes)
+    }
+
+    /// Render an `Ndjson` body and split it into its lines without the newlines
+    /// `None` for other kinds of bodies
+    pub fn ndjson_lines(&self, vars: &RestVariables) -> Option<Vec<String>> {
+        match self {
+            Body::Ndjson(text) => Some(text.render(vars).lines().map(String::from).collect()),
+            _ => None,
+        }
+    }
+
+    /// Render the text that would be sent as the body
+    /// For `SaveToFile` this is the body sent before the response is saved
+    /// File bodies can't be rendered without reading the file so they are `None`
+    pub fn body_text(&self, vars: &RestVariables) -> Option<String> {
+        match self {
+            Body::Text(text) | Body::Ndjson(text) | Body::SaveToFile { text, .. } => {
+                Some(text.render(vars))
+            }
+            Body::GraphQL { query, variables } => Some(graphql_text(
+                &query.render(vars),
+                variables.as_ref().map(|variables| variables.render(vars)).as_deref(),
+            )),
+            Body::Multipart { boundary, parts } => multipart_text(boundary, parts, vars),
+            Body::LoadFromFile { .. } => None,
+        }
+    }
+
+    /// Read a `LoadFromFile` body relative to `base_dir` and decode it with its encoding
+    /// `<@latin1 ./body.txt`, UTF-8 when no encoding is given
+    /// Variables are rendered for `<@`, other bodies are returned as the text
+    pub fn load_contents(&self, base_dir: &Path, vars: &RestVariables) -> anyhow::Result<String> {
+        let Body::LoadFromFile { process_variables, encoding, filepath } = self else {
+            return Err(anyhow!("The body isn't loaded from a file"));
+        };
+
+        let path = base_dir.join(filepath.render(vars));
+        let bytes = std::fs::read(&path)
+            .context(format!("Error reading request body file {path:?}"))?;
+        let encoding = match encoding {
+            Some(label) => encoding_for_label(label)?,
+            None => encoding_rs::UTF_8,
+        };
+        let (text, _, had_errors) = encoding.decode(&bytes);
+        if had_errors {
+            return Err(anyhow!("Request body file {path:?} is not valid {}", encoding.name()));
+        }
+
+        Ok(match process_variables {
+            true => Template::new(&text).render(vars),
+            false => text.into_owned(),
+        })
+    }
+
+    /// Render the path the response should be saved to: `>> ./response.json`
+    pub fn save_target(&self, vars: &RestVariables) -> Option<String> {
+        match self {
+            Body::SaveToFile { filepath, .. } => Some(filepath.render(vars)),
+            Body::Text(_)
+            | Body::Ndjson(_)
+            | Body::GraphQL { .. }
+            | Body::Multipart { .. }
+            | Body::LoadFromFile { .. } => None,
+        }
+    }
+
+    #[cfg(feature = "json")]
+    fn render_text(&self, vars: &RestVariables) -> anyhow::Result<String> {
+        self.body_text(vars)
+            .ok_or_else(|| anyhow!("The body is loaded from a file"))
+    }
+
+    /// A single line preview of the body for display, at most `max_len` characters
+    /// A longer preview is cut off and ends with `...`
+    pub fn rendered_preview(&self, vars: &RestVariables, max_len: usize) -> String {
+        let text = match self {
+            Body::Text(text) | Body::Ndjson(text) | Body::SaveToFile { text, .. } => text.render(vars),
+            Body::GraphQL { .. } => self.body_text(vars).unwrap_or_default(),
+            Body::Multipart { parts, .. } => {
+                let names: Vec<&str> = parts.iter().map(|part| part.name.as_str()).collect();
+                format!("<multipart: {}>", names.join(", "))
+            }
+            Body::LoadFromFile { filepath, .. } => format!("<file: {}>", filepath.render(vars)),
+        };
+
+        let single_line = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if single_line.chars().count() <= max_len {
+            return single_line;
+        }
+
+        let truncated: String = single_line.chars().take(max_len.saturating_sub(ELLIPSIS.len())).collect();
+        format!("{truncated}{ELLIPSIS}").chars().take(max_len).collect()
+    }
+
+    /// Render the body and parse it as strict JSON
+    #[cfg(feature = "json")]
+    pub fn as_json(&self, vars: &RestVariables) -> anyhow::Result<serde_json::Value> {
+        let text = self.render_text(vars)?;
+        serde_json::from_str(&text).context("The body is not valid JSON")
+    }
+
+    /// Render the body and parse each non-empty line as JSON
+    /// Used by bulk endpoints like Elasticsearch's `_bulk`
+    #[cfg(feature = "json")]
+    pub fn as_ndjson(&self, vars: &RestVariables) -> anyhow::Result<Vec<serde_json::Value>> {
+        let text = self.render_text(vars)?;
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .context(format!("Line {} of the body is not valid JSON", index + 1))
+            })
+            .collect()
+    }
+
+    /// Render the body and parse it as JSON5
+    /// Hand written bodies often have trailing commas and comments
+    #[cfg(feature = "json5")]
+    pub fn as_json_lenient(&self, vars: &RestVariables) -> anyhow::Result<serde_json::Value> {
+        let text = self.render_text(vars)?;
+        json5::from_str(&text).context("The body is not valid JSON5")
+    }
+}
+
+/// A script run on the response
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResponseHandler {
+    /// A Jetbrains script: `> {% client.global.set("token", response.body.token); %}`
+    Inline(String),
+    /// A script file: `> ./handler.js`
+    File(Template),
 }
 
+/// Everything collected for a single request while reading lines
 #[derive(Debug, Clone, Default)]
+pub(crate) struct RequestBlock {
+    pub(crate) name: Option<String>,
+    pub(crate) commands: IndexMap<String, Option<String>>,
+    pub(crate) variables: RestVariables,
+    pub(crate) pre_request_script: Option<String>,
+    pub(crate) region: Option<String>,
+    /// The request line, headers and body
+    pub(crate) raw_request: String,
+}
+
+#[cfg(test)]
+impl RequestBlock {
+    pub(crate) fn new(raw_request: &str) -> Self {
+        Self {
+            raw_request: raw_request.into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RestRequest {
     pub name: Option<String>,
     pub url: Template,
-    pub query: IndexMap<String, Template>,
+    /// The query parameters in order, a repeated key keeps every value and `get` returns the first
+    pub query: QueryParams,
+    /// Everything after the `#` in the URL
+    pub fragment: Option<Template>,
     pub body: Option<Body>,
     pub method: Template,
+    /// The version from the request line without the `HTTP/` prefix: `1.1`, `2`
+    /// Falls back to a `# @http-version 2` command, `None` when neither is given
+    pub http_version: Option<String>,
     pub headers: IndexMap<String, Template>,
     pub authorization: Option<Authorization>,
     pub commands: IndexMap<String, Option<String>>,
+    /// The variables declared in the request's block with `ParseOptions::request_variables`
+    /// They override the file variables for this request, see `RestFormat::iter_with_variables`
+    pub variables: RestVariables,
+    /// The request is annotated with `# @disabled`
+    pub disabled: bool,
+    /// The body language hint: `# @language json`
+    /// It takes precedence over the `Content-Type` when parsing the body
+    pub body_language: Option<String>,
+    /// Tool specific settings as written: `# @settings {"retries": 3}`
+    /// Parse them with `RestRequest::settings_json`
+    pub settings: Option<String>,
+    /// A Jetbrains pre-request script: `< {% ... %}`
+    pub pre_request_script: Option<String>,
+    /// Variables set in the pre-request script with `request.variables.set`
+    pub script_variables: IndexMap<String, Template>,
+    /// The script run on the response: `> {% ... %}` or `> ./handler.js`
+    pub response_handler: Option<ResponseHandler>,
+    /// The index of the fenced code block the request came from
+    /// Only set by `RestFormat::parse_markdown`
+    pub markdown_block: Option<usize>,
+    /// The region the request is in: `# region Auth` ... `# endregion`
+    pub region: Option<String>,
 }
 
 impl RestRequest {
     /// Convert a name and a raw request into structured data 
     pub(crate) fn from_raw_request(
-        name: Option<String>,
-        commands: IndexMap<String, Option<String>>,
-        raw_request: &str,
+        block: RequestBlock,
+        flavor: RestFlavor,
+        options: &ParseOptions,
     ) -> anyhow::Result<Self> {
+        let RequestBlock {
+            name,
+            commands,
+            variables,
+            pre_request_script,
+            region,
+            raw_request,
+        } = block;
+
+        // The body's last line ending is trimmed with the block but an NDJSON body keeps it
+        let body_line_ending = final_line_ending(&raw_request);
+        // The script isn't a template and it shouldn't end up in the body
+        let (raw_request, response_handler) = take_response_handler(raw_request.trim());
+        let body_line_ending = match response_handler {
+            Some(_) => REQUEST_NEWLINE,
+            None => body_line_ending,
+        };
+        // Leading blank lines would be mistaken for the body delimiter
+        let raw_request = join_split_request_line(raw_request.trim());
+        let raw_request = normalize_request_line(&raw_request);
+        let (raw_request, line_version) = take_http_version(&raw_request);
         let (req_portion, raw_body_portion) =
-            parse_request_and_raw_body(raw_request.trim());
+            parse_request_and_raw_body(&raw_request);
+
+        if let (Some(max), Some(body)) = (options.max_body_bytes, &raw_body_portion) {
+            if body.len() > max {
+                return Err(anyhow!(
+                    "The request body is {} bytes which exceeds the maximum of {max} bytes",
+                    body.len()
+                ));
+            }
+        }
 
-        // We need an empty buffer of headers (max of 64)
-        let mut headers = [httparse::EMPTY_HEADER; 64];
+        // We need an empty buffer of headers
+        let max_headers = options.max_headers.unwrap_or(DEFAULT_MAX_HEADERS);
+        let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
         let mut req = httparse::Request::new(&mut headers);
        
         // Clean up vars from request so it can be parsed 
+        let req_portion = unfold_headers(&req_portion);
         let req_portion = Self::apply_placeholder(&req_portion, true);
 
         let req_buffer = req_portion.as_bytes();
-        req.parse(req_buffer).map_err(|parse_err| {
-            println!("{:?}", parse_err); 
-            anyhow!("Failed to parse request! {parse_err:?}")
+        req.parse(req_buffer).map_err(|parse_err| match parse_err {
+            httparse::Error::TooManyHeaders => anyhow!(
+                "The request has more than {max_headers} headers, raise `ParseOptions::max_headers` to parse it"
+            ),
+            parse_err => anyhow!("Failed to parse request! {parse_err:?}"),
         })?;
 
         let path = req
@@ -170,144 +711,1095 @@ impl RestRequest {
 
         let path = Self::apply_placeholder(path, false);
 
-        let RestUrl { url, query } = RestUrl::from_str(&path)?;
-        let rest_headers = RestHeaders::from_header_slice(req.headers)?;
-        let content_type = rest_headers.content_type(); 
+        let RestUrl { url, query, fragment } = RestUrl::from_str(&path)?;
+        let mut rest_headers = RestHeaders::from_header_slice(req.headers, options.decode_header_escapes)?;
+        let mut commands = commands;
+        rest_headers.apply_header_commands(&mut commands);
+        rest_headers.apply_oauth2_command(&mut commands);
+        let content_type = match rest_headers.is_graphql() {
+            true => Some(GRAPHQL_CONTENT_TYPE.to_string()),
+            false => rest_headers.content_type(),
+        };
         let RestHeaders { headers, authorization } = rest_headers;
+        let body_language = commands
+            .shift_remove(LANGUAGE_COMMAND)
+            .flatten()
+            .map(|language| language.trim().to_lowercase());
 
         let method = Template::new(req.method.unwrap_or("GET"));
+        let command_version = commands
+            .shift_remove(HTTP_VERSION_COMMAND)
+            .flatten()
+            .map(|version| version.trim().to_string());
+        let http_version = line_version.or(command_version);
         
-        let body = raw_body_portion.map(|body| Body::parse(&body, &content_type));
+        let body = raw_body_portion.map(|body| {
+            let content_type = body_language
+                .as_deref()
+                .map(language_content_type)
+                .or(content_type.clone())
+                .or(flavor.default_content_type(&body).map(String::from))
+                .unwrap_or("unknown".into());
+            match Body::parse(&body, &content_type) {
+                Body::Ndjson(text) => Body::Ndjson(Template::new(&format!("{}{body_line_ending}", text.raw))),
+                body => body,
+            }
+        });
+
+        let disabled = commands.contains_key(DISABLED_COMMAND);
+        let settings = take_settings(&mut commands);
+        #[cfg(feature = "json")]
+        if let (Some(raw), true) = (&settings, options.strict) {
+            parse_settings(raw)?;
+        }
+        let script_variables = pre_request_script
+            .as_deref()
+            .map(parse_script_variables)
+            .unwrap_or_default();
 
         Ok(Self {
             name,
             method,
+            http_version,
             url,
             body,
             query,
+            fragment,
             headers,
             authorization,
             commands,
+            variables,
+            disabled,
+            body_language,
+            settings,
+            pre_request_script,
+            script_variables,
+            response_handler,
+            markdown_block: None,
+            region,
         })
     }
 
-    fn apply_placeholder(path: &str, apply: bool) -> String {
-        let open_d = "{{ ";
-        let close_d = " }}";
-        let open_p = "_TO_";
-        let close_p = "_TC_";
+    /// Every template in the request (method, url, query, fragment, headers,
+    /// body and authorization) excluding the request's variables
+    pub fn templates(&self) -> Vec<&Template> {
+        let mut templates = vec![&self.method, &self.url];
+        templates.extend(self.query.values());
+        templates.extend(self.fragment.as_ref());
+        templates.extend(self.headers.values());
+        if let Some(body) = &self.body {
+            templates.extend(body.templates());
+        }
+        if let Some(ResponseHandler::File(path)) = &self.response_handler {
+            templates.push(path);
+        }
+        match &self.authorization {
+            Some(Authorization::Raw(template)) => templates.push(template),
+            Some(Authorization::OAuth2 { token, config }) => {
+                templates.extend(token.as_ref());
+                templates.extend(config.templates());
+            }
+            _ => {}
+        }
+        templates
+    }
 
-        let (rep1, rep2) = if apply {
-            ((open_d, open_p),  (close_d, close_p))
-        } else {
-            ((open_p, open_d), (close_p, close_d))
-        }; 
+    /// Every template in the request, mutably
+    pub fn templates_mut(&mut self) -> Vec<&mut Template> {
+        let mut templates = vec![&mut self.method, &mut self.url];
+        templates.extend(self.query.values_mut());
+        templates.extend(self.fragment.as_mut());
+        templates.extend(self.headers.values_mut());
+        if let Some(body) = &mut self.body {
+            templates.extend(body.templates_mut());
+        }
+        if let Some(ResponseHandler::File(path)) = &mut self.response_handler {
+            templates.push(path);
+        }
+        match &mut self.authorization {
+            Some(Authorization::Raw(template)) => templates.push(template),
+            Some(Authorization::OAuth2 { token, config }) => {
+                templates.extend(token.as_mut());
+                templates.extend(config.templates_mut());
+            }
+            _ => {}
+        }
+        templates
+    }
 
-        path.replace(rep1.0, rep1.1)
-            .replace(rep2.0, rep2.1)
+    /// Get a single query parameter rendered with the variables
+    /// A repeated key returns its first value
+    pub fn query_get(&self, key: &str, vars: &RestVariables) -> Option<String> {
+        self.query
+            .get(key)
+            .map(|value| value.render(vars))
     }
-}
 
-#[derive(Debug, Clone)]
-struct RestUrl {
-    url: Template,
-    query: IndexMap<String, Template>,
-}
+    /// Every value of each query key in order, including repeated keys
+    /// `?tags[]=a&tags[]=b` -> `tags[]: [a, b]`
+    pub fn query_values(&self) -> QueryMap {
+        self.query.iter().fold(QueryMap::new(), |mut values, (key, value)| {
+            values.entry(key.clone()).or_default().push(value.clone());
+            values
+        })
+    }
 
-/// Parse the query portion of a URL
-///
-/// This injects the query portion into a fake url
-/// The template literals in the url would screw up parsing
-/// I'd rather use a well tested crate than implementing query parsing
-/// There's no public interface in URL to parse the query portion alone
-fn parse_query(
-    query_portion: &str,
-) -> anyhow::Result<IndexMap<String, Template>> {
-    let fake_url = Url::parse(&format!("http://localhost?{query_portion}"))
-        .context(format!("Invalid query (Query: {query_portion})"))?;
+    /// Render the full URL with the query and fragment
+    /// Query values are rendered and then percent-encoded with the keys: `q={{search}}` -> `q=hello+world`
+    /// Values are kept decoded after parsing so `%20` in the file isn't encoded twice
+    pub fn rendered_url(&self, vars: &RestVariables) -> String {
+        let mut url = self.url.render(vars);
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        for (key, value) in self.query.iter() {
+            query.append_pair(key, &value.render(vars));
+        }
+        let query = query.finish();
+        if !query.is_empty() {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            url = format!("{url}{separator}{query}");
+        }
+        if let Some(fragment) = &self.fragment {
+            url = format!("{url}#{}", fragment.render(vars));
+        }
+        url
+    }
 
-    let mut query: IndexMap<String, Template> = IndexMap::new();
-    for (k, v) in fake_url.query_pairs() {
-        let template = Template::new(&v);
-        query.insert(k.into(), template);
+    /// A copy of the request with a query parameter added
+    /// An existing parameter keeps its position and all of its values are replaced
+    pub fn with_query_param(&self, key: &str, value: impl Into<Template>) -> Self {
+        let mut request = self.clone();
+        request.query.set(key, value);
+        request
     }
-    Ok(query)
-}
 
-impl FromStr for RestUrl {
-    type Err = anyhow::Error;
+    /// Render the headers, failing with every undefined variable across them
+    pub fn try_render_headers(&self, vars: &RestVariables) -> Result<IndexMap<String, String>, Vec<String>> {
+        try_render_map(&self.headers, vars)
+    }
 
-    fn from_str(path: &str) -> Result<Self, Self::Err> {
-        fn url_and_query(input: &str) -> StrResult {
-            let (query, (url, _)) = pair(take_until("?"), tag("?"))(input)?;
-            Ok((url, query))
+    /// Set a header, replacing any header with the same name (case-insensitive)
+    /// A replaced header keeps its position, otherwise the header is appended
+    pub fn set_header(&mut self, name: &str, value: impl Into<Template>) {
+        let value = value.into();
+        match self.header_index(name) {
+            Some(index) => {
+                self.headers.shift_remove_index(index);
+                self.headers.shift_insert(index, name.to_string(), value);
+            }
+            None => {
+                self.headers.insert(name.to_string(), value);
+            }
         }
+    }
 
-        if let Ok((url_part, query_part)) = url_and_query(path) {
-            let url = Template::new(url_part);
-            let query = parse_query(query_part)?;
+    /// Merge a shared set of headers into the request, matching names case-insensitively
+    /// With `overwrite` an existing header takes the new value in place, otherwise it's kept
+    /// New headers are appended in the order of `extra`
+    pub fn merge_headers(&mut self, extra: &TemplateMap, overwrite: bool) {
+        for (name, value) in extra {
+            if overwrite || self.header_index(name).is_none() {
+                self.set_header(name, value.clone());
+            }
+        }
+    }
 
-            Ok(Self { url, query })
-        } else {
-            let url: String = path.to_string().try_into()?;
+    /// Remove a header by name (case-insensitive) and return its value
+    pub fn remove_header(&mut self, name: &str) -> Option<Template> {
+        let index = self.header_index(name)?;
+        self.headers.shift_remove_index(index).map(|(_, value)| value)
+    }
 
-            // The url is just a string or template
-            Ok(Self {
-                url: Template::new(&url), 
-                query: IndexMap::new(),
-            })
+    pub(crate) fn header_index(&self, name: &str) -> Option<usize> {
+        self.headers
+            .keys()
+            .position(|existing| existing.eq_ignore_ascii_case(name))
+    }
+
+    /// The `Authorization` header reconstructed from `authorization`
+    /// `None` without authorization or for an OAuth2 flow without a token yet
+    pub fn authorization_header(&self) -> Option<(String, String)> {
+        let value = self.authorization.as_ref()?.to_header();
+        if value.is_empty() {
+            return None;
         }
+        Some((AUTHORIZATION_HEADER.to_string(), value))
     }
-}
 
-/// `httparse` does not parse bodies
-/// We need to seperate them from the request portion
-fn parse_request_and_raw_body(input: &str) -> (String, Option<String>) {
-    fn take_until_body(raw: &str) -> IResult<&str, String> {
-        let (raw, (init_body, rest)) = pair(
-            take_until(BODY_DELIMITER),
-            opt(pair(tag(SAVE_SYMBOL), take_until(BODY_DELIMITER)))
-        )(raw)?;
+    /// A stable hash of the request's structure, useful as a cache key
+    /// Covers the method, url, query and headers (both sorted) and the body
+    /// Header names are compared case-insensitively, everything else is raw
+    pub fn fingerprint(&self) -> u64 {
+        let mut query: Vec<(&String, &Template)> = self.query.iter().collect();
+        query.sort_by(|a, b| (a.0, &a.1.raw).cmp(&(b.0, &b.1.raw)));
+
+        let mut headers: Vec<(String, &str)> = self.headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.raw.as_str()))
+            .collect();
+        headers.sort();
+
+        let mut hasher = Fnv1a::default();
+        hasher.write(&self.method.raw);
+        hasher.write(&self.url.raw);
+        for (key, value) in query {
+            hasher.write(key);
+            hasher.write(&value.raw);
+        }
+        for (name, value) in headers {
+            hasher.write(&name);
+            hasher.write(value);
+        }
+        if let Some(body) = &self.body {
+            hasher.write(&format!("{:?}", body.kind()));
+            for template in body.templates() {
+                hasher.write(&template.raw);
+            }
+        }
+        hasher.0
+    }
 
-        let addition = match rest {
-            Some((a, b)) => format!("{a}{b}"),
-            None => "".to_string()
+    /// Render the request into its HTTP/1.1 wire format
+    /// The authorization and `Content-Length` headers are reconstructed and the query is percent-encoded
+    /// A body loaded from a file is read relative to `base_dir`, use `RestFormat::base_dir` for a parsed file
+    pub fn to_raw_http(&self, vars: &RestVariables, base_dir: &Path) -> anyhow::Result<String> {
+        let method = self.method.render(vars);
+        // The fragment is never sent
+        let rendered_url = self.rendered_url(vars);
+        let full_url = rendered_url.split('#').next().unwrap_or_default().to_string();
+
+        let mut headers: Vec<(String, String)> = vec![];
+        // Use the origin form (`/path?query`) with a `Host` header when possible
+        let target = match Url::parse(&full_url) {
+            Ok(parsed) if parsed.has_host() => {
+                let has_host = self.headers.keys().any(|name| name.eq_ignore_ascii_case("Host"));
+                if !has_host {
+                    let host = &parsed[url::Position::BeforeHost..url::Position::AfterPort];
+                    headers.push(("Host".into(), host.into()));
+                }
+                parsed[url::Position::BeforePath..url::Position::AfterQuery].to_string()
+            }
+            _ => full_url,
         };
 
-        let full_body = format!("{init_body}{addition}");
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case(CONTENT_LENGTH) {
+                headers.push((name.clone(), value.render(vars)));
+            }
+        }
 
-        Ok((raw, full_body))  
+        if let Some(authorization) = &self.authorization {
+            let value = authorization.render_header(vars);
+            if !value.is_empty() {
+                headers.push((AUTHORIZATION_HEADER.into(), value));
+            }
+        }
+
+        let body = match &self.body {
+            Some(body @ Body::LoadFromFile { .. }) => body.load_contents(base_dir, vars)?,
+            Some(body) => body.body_text(vars).unwrap_or_default(),
+            None => "".into(),
+        };
+        if !body.is_empty() {
+            headers.push((CONTENT_LENGTH.into(), body.len().to_string()));
+        }
+
+        let mut raw = format!("{method} {target} HTTP/1.1{REQUEST_NEWLINE}");
+        for (name, value) in headers {
+            raw += &format!("{name}: {value}{REQUEST_NEWLINE}");
+        }
+        raw += REQUEST_NEWLINE;
+        raw += &body;
+        Ok(raw)
     }
 
-    match take_until_body(input) {
-        Ok((body_portion, req_portion)) => {
-            // TODO: Figure out how to deal with spaces in templates here (maybe regex transform? "{{ +" -> "XXX") 
-            let req_portion = req_portion.replace("{{ ", "{{");
-            let req_with_end = format!("{req_portion}{REQUEST_NEWLINE}");
-            (req_with_end, Some(body_portion.trim().into()))
+    /// Check the request for things that parse but are likely mistakes
+    /// Returns a list of warnings, empty if nothing looks wrong
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        let method = self.method.raw.to_uppercase();
+        if self.has_body() && BODILESS_METHODS.contains(&method.as_str()) {
+            warnings.push(format!("{method} requests should not have a body"));
         }
-        _ => (input.into(), None),
+        warnings
     }
-}
 
+    /// Check if the request has a body
+    pub fn has_body(&self) -> bool {
+        self.body.is_some()
+    }
 
-#[cfg(test)]
-mod test {
-    use crate::template::TemplatePart;
+    /// Get the kind of body the request has
+    pub fn body_kind(&self) -> Option<BodyKind> {
+        self.body.as_ref().map(Body::kind)
+    }
 
-    use super::*;
-    use indoc::indoc;
+    /// The content type of the body: the `# @language` hint, the `Content-Type` header
+    /// or what `flavor` assumes for a body without one, `None` without a body
+    pub fn body_content_type(&self, flavor: RestFlavor) -> Option<String> {
+        let body = self.body.as_ref()?;
+        let header = self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.raw.clone());
+        let hint = body.templates().first().map(|template| template.raw.as_str()).unwrap_or_default();
+        self.body_language
+            .as_deref()
+            .map(language_content_type)
+            .or(header)
+            .or(flavor.default_content_type(hint).map(String::from))
+    }
 
-    #[test]
-    fn parse_url_test() {
-        let example = "{{VAR}}?x={{b}}&word=cool";
-        let parsed = RestUrl::from_str(example).unwrap();
-        assert_eq!(parsed.url.to_string(), "{{VAR}}");
-        assert_eq!(parsed.query.get("x").unwrap().to_string(), "{{b}}");
-        assert_eq!(parsed.query.get("word").unwrap().to_string(), "cool");
+    /// Parse the `# @settings` blob as JSON, `None` without settings
+    #[cfg(feature = "json")]
+    pub fn settings_json(&self) -> anyhow::Result<Option<serde_json::Value>> {
+        self.settings.as_deref().map(parse_settings).transpose()
+    }
 
-        let example = "https://example.com";
-        let parsed: RestUrl = example.parse().unwrap();
+    /// `httparse` splits the request line at spaces so the spaces inside `{{ }}` are swapped
+    /// for a placeholder: `GET {{$dotenv HOST}}/a` -> `GET {{$dotenv_TS_HOST}}/a`
+    /// Only the request line is changed, `apply: false` swaps the spaces back
+    fn apply_placeholder(input: &str, apply: bool) -> String {
+        const SPACE_PLACEHOLDER: &str = "_TS_";
+        if !apply {
+            return input.replace(SPACE_PLACEHOLDER, " ");
+        }
+
+        let (line, rest) = input
+            .split_once(REQUEST_NEWLINE)
+            .map_or((input, None), |(line, rest)| (line, Some(rest)));
+        let mut chunks = line.split("{{");
+        let mut protected = chunks.next().unwrap_or_default().to_string();
+        for chunk in chunks {
+            let (inside, after) = chunk.split_once("}}").map_or((chunk, None), |(inside, after)| (inside, Some(after)));
+            protected += &format!("{{{{{}", inside.replace(' ', SPACE_PLACEHOLDER));
+            if let Some(after) = after {
+                protected += &format!("}}}}{after}");
+            }
+        }
+        match rest {
+            Some(rest) => format!("{protected}{REQUEST_NEWLINE}{rest}"),
+            None => protected,
+        }
+    }
+}
+
+/// Write the request back in the `.http` format, without a `###` seperator
+/// Parsing the output gives back an equal request
+/// Body lines starting with `@` are escaped, comment-like body lines can't be kept
+impl fmt::Display for RestRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            writeln!(f, "# @name {name}")?;
+        }
+        for (name, params) in &self.commands {
+            match params {
+                Some(params) => writeln!(f, "# @{name} {params}")?,
+                None => writeln!(f, "# @{name}")?,
+            }
+        }
+        if let Some(language) = &self.body_language {
+            writeln!(f, "# @{LANGUAGE_COMMAND} {language}")?;
+        }
+        if let Some(settings) = &self.settings {
+            writeln!(f, "# @{SETTINGS_COMMAND} {settings}")?;
+        }
+        if let Some(Authorization::OAuth2 { config, .. }) = &self.authorization {
+            writeln!(f, "# @{OAUTH2_COMMAND} {}", config.to_params())?;
+        }
+        for (name, value) in &self.variables {
+            writeln!(f, "@{name} = {value}")?;
+        }
+        if let Some(script) = &self.pre_request_script {
+            match script.contains('\n') {
+                true => writeln!(f, "< {{%\n{script}\n%}}")?,
+                false => writeln!(f, "< {{% {script} %}}")?,
+            }
+        }
+
+        write!(f, "{} {}", self.method, self.url)?;
+        let query = self.query
+            .iter()
+            .map(|(key, value)| format!("{}={}", encode_query_text(key), encode_query_template(value)))
+            .collect::<Vec<String>>()
+            .join("&");
+        if !query.is_empty() {
+            write!(f, "?{query}")?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{fragment}")?;
+        }
+        match &self.http_version {
+            Some(version) => writeln!(f, " {HTTP_VERSION_PREFIX}{version}")?,
+            None => writeln!(f)?,
+        }
+
+        for (name, value) in &self.headers {
+            // A decoded newline would end the header
+            let value = value.to_string().replace('\r', "\\r").replace('\n', "\\n");
+            writeln!(f, "{name}: {value}")?;
+        }
+        if let Some((name, value)) = self.authorization_header() {
+            writeln!(f, "{name}: {value}")?;
+        }
+
+        let Some(body) = &self.body else {
+            return self.fmt_response_handler(f);
+        };
+        writeln!(f)?;
+        match body {
+            Body::Text(text) => writeln!(f, "{}", escape_body(&text.raw))?,
+            Body::Ndjson(text) => {
+                let text = text.raw.strip_suffix(final_line_ending(&text.raw)).unwrap_or(&text.raw);
+                writeln!(f, "{}", escape_body(text))?
+            }
+            Body::GraphQL { query, variables } => {
+                writeln!(f, "{}", escape_body(&query.raw))?;
+                if let Some(variables) = variables {
+                    writeln!(f, "\n{}", escape_body(&variables.raw))?;
+                }
+            }
+            Body::Multipart { boundary, parts } => {
+                for part in parts {
+                    writeln!(f, "--{boundary}")?;
+                    for (name, value) in &part.headers {
+                        writeln!(f, "{name}: {value}")?;
+                    }
+                    writeln!(f)?;
+                    match &part.content {
+                        MultipartContent::Text(text) => writeln!(f, "{}", escape_body(&text.raw))?,
+                        MultipartContent::LoadFromFile { process_variables, encoding, filepath } => {
+                            let at = if *process_variables { VAR_SYMBOL } else { "" };
+                            let encoding = encoding.as_deref().unwrap_or("");
+                            writeln!(f, "{LOAD_SYMBOL}{at}{encoding} {filepath}")?;
+                        }
+                    }
+                }
+                writeln!(f, "--{boundary}--")?;
+            }
+            Body::LoadFromFile { process_variables, encoding, filepath } => {
+                let at = if *process_variables { VAR_SYMBOL } else { "" };
+                let encoding = encoding.as_deref().unwrap_or("");
+                writeln!(f, "{LOAD_SYMBOL}{at}{encoding} {filepath}")?;
+            }
+            Body::SaveToFile { text, filepath } => {
+                if !text.raw.is_empty() {
+                    writeln!(f, "{}\n", escape_body(&text.raw))?;
+                }
+                writeln!(f, "{SAVE_SYMBOL} {filepath}")?;
+            }
+        }
+        self.fmt_response_handler(f)
+    }
+}
+
+impl RestRequest {
+    fn fmt_response_handler(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.response_handler {
+            Some(ResponseHandler::Inline(script)) if script.contains('\n') => {
+                writeln!(f, "\n{HANDLER_SYMBOL} {SCRIPT_START}\n{script}\n{SCRIPT_END}")
+            }
+            Some(ResponseHandler::Inline(script)) => {
+                writeln!(f, "\n{HANDLER_SYMBOL} {SCRIPT_START} {script} {SCRIPT_END}")
+            }
+            Some(ResponseHandler::File(path)) => writeln!(f, "\n{HANDLER_SYMBOL} {path}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Take the response handler out of the raw request, it follows the body
+/// `> {% ... %}` is an inline script and `> ./handler.js` a script file
+/// A `>> ./response.json` redirect is left in place
+fn take_response_handler(input: &str) -> (String, Option<ResponseHandler>) {
+    fn handler_start(line: &str) -> Option<&str> {
+        let rest = line.trim_start().strip_prefix(HANDLER_SYMBOL)?.trim();
+        let is_script = rest.starts_with(SCRIPT_START);
+        let is_file = !rest.starts_with(HANDLER_SYMBOL)
+            && !rest.contains(char::is_whitespace)
+            && rest.to_lowercase().ends_with(HANDLER_FILE_EXTENSION);
+        (is_script || is_file).then_some(rest)
+    }
+
+    // The request line is never a handler
+    let mut offset = 0;
+    for (index, line) in input.split(REQUEST_NEWLINE).enumerate() {
+        let line_end = offset + line.len();
+        let handler = handler_start(line).filter(|_| index > 0);
+        let Some(handler) = handler else {
+            offset = line_end + REQUEST_NEWLINE.len();
+            continue;
+        };
+
+        let before = input[..offset].trim_end();
+        if !handler.starts_with(SCRIPT_START) {
+            let handler = ResponseHandler::File(Template::new(handler));
+            return (join_around_handler(before, &input[line_end..]), Some(handler));
+        }
+
+        // The script can span lines, it ends at the first `%}`
+        let script_start = offset + line.find(SCRIPT_START).unwrap_or(0) + SCRIPT_START.len();
+        let Some(script_len) = input[script_start..].find(SCRIPT_END) else {
+            // An unclosed script is left alone
+            return (input.into(), None);
+        };
+        let script = input[script_start..script_start + script_len].replace(REQUEST_NEWLINE, "\n");
+        let after = &input[script_start + script_len + SCRIPT_END.len()..];
+        let handler = ResponseHandler::Inline(script.trim().into());
+        return (join_around_handler(before, after), Some(handler));
+    }
+    (input.into(), None)
+}
+
+/// Join the raw request back together without the handler
+/// Anything after the handler, like a `>> ./response.json` redirect, is kept after a blank line
+fn join_around_handler(before: &str, after: &str) -> String {
+    match after.trim() {
+        "" => before.into(),
+        after => format!("{before}{BODY_DELIMITER}{after}"),
+    }
+}
+
+/// Use plain newlines and escape lines that would parse as variables
+fn escape_body(text: &str) -> String {
+    text.lines()
+        .map(|line| match line.starts_with(VAR_SYMBOL) {
+            true => format!("\\{line}"),
+            false => line.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Percent-encode the characters that would change how a query is parsed
+fn encode_query_text(text: &str) -> String {
+    let mut encoded = String::new();
+    for c in text.chars() {
+        match c {
+            ' ' | '&' | '=' | '#' | '%' | '+' | '\t' => encoded += &format!("%{:02X}", c as u32),
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// Percent-encode the text of a query value but keep its variables
+fn encode_query_template(template: &Template) -> String {
+    template.parts
+        .iter()
+        .map(|part| match part {
+            TemplatePart::Text(text) => encode_query_text(text),
+            variable => variable.to_string(),
+        })
+        .collect()
+}
+
+/// A URL template split into its parts
+/// `{{HOST}}/users?id={{id}}#details`
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RestUrl {
+    /// Everything before the query
+    pub url: Template,
+    /// The query parameters in order, a repeated key keeps every value
+    pub query: QueryParams,
+    /// Everything after the `#`
+    pub fragment: Option<Template>,
+}
+
+/// Parse the query portion of a URL
+///
+/// This injects the query portion into a fake url
+/// The template literals in the url would screw up parsing
+/// I'd rather use a well tested crate than implementing query parsing
+/// There's no public interface in URL to parse the query portion alone
+/// Repeated keys keep every value and keys are not normalized (`tags[]` stays `tags[]`)
+fn parse_query(
+    query_portion: &str,
+) -> anyhow::Result<QueryParams> {
+    let fake_url = Url::parse(&format!("http://localhost?{query_portion}"))
+        .context(format!("Invalid query (Query: {query_portion})"))?;
+
+    let query = fake_url.query_pairs()
+        .map(|(k, v)| (k.into_owned(), Template::new(&v)))
+        .collect();
+    Ok(query)
+}
+
+/// Query parameters in the order they're written, a key can be given more than once
+/// `?tags[]=a&tags[]=b` keeps both values and `get` returns the first one
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryParams(Vec<(String, Template)>);
+
+impl QueryParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first value of a key
+    pub fn get(&self, key: &str) -> Option<&Template> {
+        self.0
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Every value of a key in order
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Template> {
+        self.0
+            .iter()
+            .filter(move |(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Add a value after the others, a repeated key keeps its earlier values
+    pub fn append(&mut self, key: &str, value: impl Into<Template>) {
+        self.0.push((key.to_string(), value.into()));
+    }
+
+    /// Set a key to a single value
+    /// An existing key keeps the position of its first value and its other values are removed
+    pub fn set(&mut self, key: &str, value: impl Into<Template>) {
+        let mut value = Some(value.into());
+        self.0.retain_mut(|(name, existing)| {
+            if name != key {
+                return true;
+            }
+            match value.take() {
+                Some(value) => {
+                    *existing = value;
+                    true
+                }
+                None => false,
+            }
+        });
+        if let Some(value) = value {
+            self.append(key, value);
+        }
+    }
+
+    /// The number of key value pairs, a repeated key counts once per value
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Every key value pair in order, a repeated key appears once per value
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Template)> {
+        self.0.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Template> {
+        self.0.iter().map(|(_, value)| value)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Template> {
+        self.0.iter_mut().map(|(_, value)| value)
+    }
+}
+
+/// The first value of a key, panics if it's missing like `IndexMap`
+impl std::ops::Index<&str> for QueryParams {
+    type Output = Template;
+
+    fn index(&self, key: &str) -> &Template {
+        self.get(key).expect("the query key is missing")
+    }
+}
+
+impl FromIterator<(String, Template)> for QueryParams {
+    fn from_iter<I: IntoIterator<Item = (String, Template)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl FromStr for RestUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        fn url_and_query(input: &str) -> StrResult<'_> {
+            let (query, (url, _)) = pair(take_until("?"), tag("?"))(input)?;
+            Ok((url, query))
+        }
+
+        let (path, fragment) = match path.split_once('#') {
+            Some((path, fragment)) => (path, Some(Template::new(fragment))),
+            None => (path, None),
+        };
+
+        if let Ok((url_part, query_part)) = url_and_query(path) {
+            let url = Template::new(url_part);
+            let query = parse_query(query_part)?;
+
+            Ok(Self { url, query, fragment })
+        } else {
+            let url = path.to_string();
+
+            // The url is just a string or template
+            Ok(Self {
+                url: Template::new(&url), 
+                query: QueryParams::new(),
+                fragment,
+            })
+        }
+    }
+}
+
+impl fmt::Display for RestUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.url)?;
+
+        let query = self.query
+            .iter()
+            .map(|(key, value)| format!("{}={}", encode_query_text(key), encode_query_template(value)))
+            .collect::<Vec<String>>()
+            .join("&");
+        if !query.is_empty() {
+            write!(f, "?{query}")?;
+        }
+
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{fragment}")?;
+        }
+        Ok(())
+    }
+}
+
+const SCRIPT_SET_VARIABLE: &str = "request.variables.set(";
+
+/// Best effort extraction of the variables set in a pre-request script
+/// `request.variables.set("name", "value")`
+fn parse_script_variables(script: &str) -> IndexMap<String, Template> {
+    fn quoted(input: &str) -> Option<(&str, &str)> {
+        let input = input.trim_start();
+        let quote = input.chars().next().filter(|c| ['"', '\'', '`'].contains(c))?;
+        let (value, rest) = input[1..].split_once(quote)?;
+        Some((value, rest))
+    }
+
+    fn set_call(input: &str) -> Option<(&str, &str)> {
+        let (name, rest) = quoted(input)?;
+        let rest = rest.trim_start().strip_prefix(',')?;
+        let (value, rest) = quoted(rest)?;
+        rest.trim_start().strip_prefix(')')?;
+        Some((name, value))
+    }
+
+    script
+        .split(SCRIPT_SET_VARIABLE)
+        .skip(1)
+        .filter_map(set_call)
+        .map(|(name, value)| (name.to_string(), Template::new(value)))
+        .collect()
+}
+
+/// Some files write the method and the URL on seperate lines:
+/// ```text
+/// GET
+/// https://example.com HTTP/1.1
+/// ```
+/// Join them into one request line
+fn join_split_request_line(input: &str) -> String {
+    let mut lines = input.splitn(3, REQUEST_NEWLINE);
+    let (Some(method), Some(url)) = (lines.next(), lines.next()) else {
+        return input.into();
+    };
+
+    let is_method = HTTP_METHODS.contains(&method.trim());
+    let url_trimmed = url.trim_start();
+    let is_url = url_trimmed.starts_with('/')
+        || url_trimmed.starts_with("{{")
+        || url_trimmed.contains("://");
+
+    if !is_method || !is_url {
+        return input.into();
+    }
+
+    match lines.next() {
+        Some(rest) => format!("{} {url_trimmed}{REQUEST_NEWLINE}{rest}", method.trim()),
+        None => format!("{} {url_trimmed}", method.trim()),
+    }
+}
+
+/// 64 bit FNV-1a, unlike `DefaultHasher` the output never changes between releases
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv1a {
+    /// Hash a field followed by a seperator so `ab` + `c` differs from `a` + `bc`
+    fn write(&mut self, field: &str) {
+        for byte in field.bytes().chain([0xff]) {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// `httparse` expects single spaces between the parts of the request line
+/// Collapse tabs and repeated spaces there, the rest of the request is untouched
+fn normalize_request_line(input: &str) -> String {
+    let (line, rest) = input
+        .split_once(REQUEST_NEWLINE)
+        .map_or((input, None), |(line, rest)| (line, Some(rest)));
+
+    let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+    match rest {
+        Some(rest) => format!("{line}{REQUEST_NEWLINE}{rest}"),
+        None => line,
+    }
+}
+
+/// Take the version off the request line, it's optional and `httparse` only knows HTTP/1.x
+/// The line always ends with `HTTP/1.1` afterwards: `GET /a HTTP/2` -> (`GET /a HTTP/1.1`, `2`)
+fn take_http_version(input: &str) -> (String, Option<String>) {
+    let (line, rest) = input
+        .split_once(REQUEST_NEWLINE)
+        .map_or((input, None), |(line, rest)| (line, Some(rest)));
+
+    let (line, version) = match line.rsplit_once(' ') {
+        Some((start, last)) if last.starts_with(HTTP_VERSION_PREFIX) => {
+            (start, Some(last[HTTP_VERSION_PREFIX.len()..].to_string()))
+        }
+        _ => (line, None),
+    };
+
+    let line = format!("{line} {HTTP_VERSION_PREFIX}1.1");
+    match rest {
+        Some(rest) => (format!("{line}{REQUEST_NEWLINE}{rest}"), version),
+        None => (line, version),
+    }
+}
+
+/// Take the raw `# @settings` blob out of the commands
+fn take_settings(commands: &mut IndexMap<String, Option<String>>) -> Option<String> {
+    commands.get(SETTINGS_COMMAND)?.as_ref()?;
+    commands.shift_remove(SETTINGS_COMMAND).flatten()
+}
+
+/// Parse a `# @settings` blob as JSON
+#[cfg(feature = "json")]
+fn parse_settings(raw: &str) -> anyhow::Result<serde_json::Value> {
+    serde_json::from_str(raw).map_err(|err| anyhow!("Invalid JSON in @settings: {err}"))
+}
+
+/// Look up an encoding by any label `encoding_rs` knows: `latin1`, `shift_jis`, `utf-16`
+/// The hyphen can be left out of the UTF labels: `utf8`, `utf16le`
+pub(crate) fn encoding_for_label(label: &str) -> anyhow::Result<&'static encoding_rs::Encoding> {
+    let label = label.trim();
+    let hyphenated = label
+        .to_lowercase()
+        .strip_prefix("utf")
+        .filter(|rest| !rest.starts_with('-'))
+        .map(|rest| format!("utf-{rest}"));
+    encoding_rs::Encoding::for_label(label.as_bytes())
+        .or_else(|| encoding_rs::Encoding::for_label(hyphenated?.as_bytes()))
+        .ok_or_else(|| anyhow!("Unknown encoding '{label}'"))
+}
+
+/// A body loaded from a file: `< ./body.json` or `<@ ./body.json` to process variables
+fn parse_from_file(inp: &str) -> IResult<&str, Body> {
+    let (inp, _) = tag(LOAD_SYMBOL)(inp)?;
+    
+    let (inp, at_sign) = opt(tag(VAR_SYMBOL))(inp)?;
+    let process_variables = at_sign.is_some();
+
+    let (inp, encoding) = opt(take_while1(|c: char| c.is_alphanumeric() || c == '-' || c == '_'))(inp)?;
+    let encoding = encoding.map(|e| e.to_string());
+
+    // A space seperates the optional encoding and the filepath 
+    let (inp, _) = tag(" ")(inp)?;
+
+    let body = Body::LoadFromFile { 
+        process_variables,
+        encoding,
+        filepath: Template::new(inp),
+    }; 
+
+    Ok(("", body))
+}
+
+/// Split a multipart body on `--boundary` lines, stopping at the closing `--boundary--`
+/// Anything before the first boundary is ignored
+fn parse_multipart_parts(input: &str, boundary: &str) -> Vec<MultipartPart> {
+    let delimiter = format!("--{boundary}");
+    input
+        .split(delimiter.as_str())
+        .skip(1)
+        .take_while(|part| !part.starts_with("--"))
+        .map(MultipartPart::parse)
+        .collect()
+}
+
+/// Render a multipart body, `None` if a part is loaded from a file
+fn multipart_text(boundary: &str, parts: &[MultipartPart], vars: &RestVariables) -> Option<String> {
+    let mut text = String::new();
+    for part in parts {
+        text += &format!("--{boundary}{REQUEST_NEWLINE}");
+        for (name, value) in &part.headers {
+            text += &format!("{name}: {}{REQUEST_NEWLINE}", value.render(vars));
+        }
+        text += REQUEST_NEWLINE;
+        match &part.content {
+            MultipartContent::Text(content) => text += &content.render(vars),
+            MultipartContent::LoadFromFile { .. } => return None,
+        }
+        text += REQUEST_NEWLINE;
+    }
+    text += &format!("--{boundary}--{REQUEST_NEWLINE}");
+    Some(text)
+}
+
+/// Split a GraphQL body into the query and the trailing JSON variables block
+/// The variables are the last blank line separated block when it's a JSON object
+fn split_graphql_variables(input: &str) -> Option<(&str, &str)> {
+    let (query, variables) = input.rsplit_once(BODY_DELIMITER)?;
+    let variables = variables.trim();
+    let is_object = variables.starts_with('{') && variables.ends_with('}');
+    (is_object && !query.trim().is_empty()).then(|| (query.trim_end(), variables))
+}
+
+/// Join a GraphQL query and its variables back into a single body
+pub(crate) fn graphql_text(query: &str, variables: Option<&str>) -> String {
+    match variables {
+        Some(variables) => format!("{query}{BODY_DELIMITER}{variables}"),
+        None => query.to_string(),
+    }
+}
+
+/// The content type a `# @language` hint stands for
+/// `json` -> `application/json`, `graphql` -> `application/graphql`
+fn language_content_type(language: &str) -> String {
+    match language {
+        "json" => JSON_CONTENT_TYPE.into(),
+        "text" | "plaintext" => TEXT_CONTENT_TYPE.into(),
+        "form" | "urlencoded" => FORM_URL_ENCODED.into(),
+        other => format!("application/{other}"),
+    }
+}
+
+/// Headers can be folded onto indented continuation lines
+/// ```text
+/// Authorization: Bearer
+///     eyJhbGciOiJIUzI1NiJ9
+/// ```
+/// Join each continuation onto its header with a single space
+fn unfold_headers(input: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    for (index, line) in input.split(REQUEST_NEWLINE).enumerate() {
+        // The request line itself is never folded
+        let is_continuation = index > 1
+            && line.starts_with([' ', '\t'])
+            && !line.trim().is_empty();
+
+        match lines.last_mut() {
+            Some(previous) if is_continuation => {
+                previous.push(' ');
+                previous.push_str(line.trim());
+            }
+            _ => lines.push(line.into()),
+        }
+    }
+    lines.join(REQUEST_NEWLINE)
+}
+
+/// `httparse` does not parse bodies
+/// We need to seperate them from the request portion
+/// The line ending right after the last line that isn't blank: `\r\n`, `\n` or nothing
+fn final_line_ending(input: &str) -> &'static str {
+    let tail = &input[input.trim_end().len()..];
+    [REQUEST_NEWLINE, "\n"]
+        .into_iter()
+        .find(|ending| tail.starts_with(ending))
+        .unwrap_or("")
+}
+
+fn parse_request_and_raw_body(input: &str) -> (String, Option<String>) {
+    fn take_until_body(raw: &str) -> IResult<&str, String> {
+        let (raw, (init_body, rest)) = pair(
+            take_until(BODY_DELIMITER),
+            opt(pair(tag(SAVE_SYMBOL), take_until(BODY_DELIMITER)))
+        )(raw)?;
+
+        let addition = match rest {
+            Some((a, b)) => format!("{a}{b}"),
+            None => "".to_string()
+        };
+
+        let full_body = format!("{init_body}{addition}");
+
+        Ok((raw, full_body))  
+    }
+
+    match take_until_body(input) {
+        Ok((body_portion, req_portion)) => {
+            let req_with_end = format!("{req_portion}{REQUEST_NEWLINE}");
+            (req_with_end, Some(body_portion.trim().into()))
+        }
+        _ => match split_by_content_length(input) {
+            Some((req_portion, body_portion)) => {
+                (format!("{req_portion}{REQUEST_NEWLINE}"), Some(body_portion.into()))
+            }
+            // Without a body the last header still needs to be terminated
+            None => (format!("{input}{REQUEST_NEWLINE}"), None),
+        },
+    }
+}
+
+/// A fallback for requests without a blank line before the body
+/// The `Content-Length` header is used to find where the body starts
+fn split_by_content_length(input: &str) -> Option<(&str, &str)> {
+    let content_length: usize = input
+        .split(REQUEST_NEWLINE)
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(CONTENT_LENGTH))
+        .and_then(|(_, value)| value.trim().parse().ok())?;
+
+    if content_length == 0 || content_length >= input.len() {
+        return None;
+    }
+
+    let split_at = input.len() - content_length;
+    if !input.is_char_boundary(split_at) {
+        return None;
+    }
+
+    // The body has to start on its own line
+    let (req_portion, body_portion) = input.split_at(split_at);
+    let req_portion = req_portion.strip_suffix(REQUEST_NEWLINE)?;
+    Some((req_portion, body_portion))
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::template::TemplatePart;
+
+    use super::*;
+    use indoc::indoc;
+
+    fn parse_raw(raw: &str) -> RestRequest {
+        RestRequest::from_raw_request(RequestBlock::new(raw), RestFlavor::Generic, &ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn parse_url_test() {
+        let example = "{{VAR}}?x={{b}}&word=cool";
+        let parsed = RestUrl::from_str(example).unwrap();
+        assert_eq!(parsed.url.to_string(), "{{VAR}}");
+        assert_eq!(parsed.query.get("x").unwrap().to_string(), "{{b}}");
+        assert_eq!(parsed.query.get("word").unwrap().to_string(), "cool");
+
+        let example = "https://example.com";
+        let parsed: RestUrl = example.parse().unwrap();
         assert_eq!(parsed.url.to_string(), "https://example.com");
         assert_eq!(parsed.query.len(), 0);
 
@@ -328,6 +1820,73 @@ mod test {
         assert_eq!(parsed.query.get("word").unwrap().to_string(), "cool");
     }
 
+    #[test]
+    fn public_rest_url_test() {
+        let parsed: RestUrl = "{{HOST}}/users?id={{id}}&sort=asc#details".parse().unwrap();
+        assert_eq!(parsed.url.variable_names(), vec!["HOST"]);
+        assert_eq!(parsed.query.get("id").unwrap().variable_names(), vec!["id"]);
+        assert_eq!(parsed.query.get("sort").unwrap().raw, "asc");
+        assert_eq!(parsed.fragment, Some(Template::new("details")));
+        assert_eq!(parsed.to_string(), "{{HOST}}/users?id={{id}}&sort=asc#details");
+
+        let parsed: RestUrl = "https://example.com/path".parse().unwrap();
+        assert_eq!(parsed.url.raw, "https://example.com/path");
+        assert!(parsed.query.is_empty());
+        assert_eq!(parsed.fragment, None);
+        assert_eq!(parsed.to_string(), "https://example.com/path");
+
+        let parsed: RestUrl = "https://example.com/#top".parse().unwrap();
+        assert_eq!(parsed.url.raw, "https://example.com/");
+        assert_eq!(parsed.fragment, Some(Template::new("top")));
+    }
+
+    #[test]
+    fn unusual_scheme_url_test() {
+        let parsed: RestUrl = "file:///path/to/data.json?x=1".parse().unwrap();
+        assert_eq!(parsed.url.raw, "file:///path/to/data.json");
+        assert_eq!(parsed.query.get("x").unwrap().raw, "1");
+        assert_eq!(parsed.to_string(), "file:///path/to/data.json?x=1");
+
+        let parsed: RestUrl = "myscheme://host".parse().unwrap();
+        assert_eq!(parsed.url.raw, "myscheme://host");
+        assert!(parsed.query.is_empty());
+
+        for (raw, url, query) in [
+            ("GET file:///path?x=1 HTTP/1.1", "file:///path", Some("1")),
+            ("GET ftp://files.example.com/pub/readme.txt", "ftp://files.example.com/pub/readme.txt", None),
+            ("POST myscheme://host HTTP/1.1", "myscheme://host", None),
+            ("GET my-scheme+v2.1://{{HOST}}/a?x={{x}}", "my-scheme+v2.1://{{HOST}}/a", Some("{{x}}")),
+        ] {
+            let req = parse_raw(raw);
+            assert_eq!(req.url.raw, url);
+            assert_eq!(req.query.get("x").map(|value| value.raw.as_str()), query);
+        }
+    }
+
+    #[test]
+    fn spaces_in_request_line_variables_test() {
+        let raw = "GET {{$dotenv HOST}}/a?r={{$randomInt 1 10}}&id={{ id }} HTTP/1.1\r\nX-Id: {{ id }}";
+        let req = parse_raw(raw);
+        assert_eq!(req.url.raw, "{{$dotenv HOST}}/a");
+        assert_eq!(req.query["r"].raw, "{{$randomInt 1 10}}");
+        assert_eq!(req.query["id"].raw, "{{ id }}");
+        assert_eq!(req.headers["X-Id"].raw, "{{ id }}");
+    }
+
+    #[test]
+    fn spaced_variables_with_body_round_trip_test() {
+        let raw = "POST {{ HOST }}/x?q={{ q }} HTTP/1.1\r\nX-A: {{ a }}\r\n\r\n{\"b\": \"{{ b }}\"}";
+        let req = parse_raw(raw);
+        assert_eq!(req.url.raw, "{{ HOST }}/x");
+        assert_eq!(req.query["q"].raw, "{{ q }}");
+        assert_eq!(req.headers["X-A"].raw, "{{ a }}");
+
+        let written = req.to_string();
+        assert!(written.starts_with("POST {{ HOST }}/x?q={{ q }} HTTP/1.1\nX-A: {{ a }}\n"));
+        let reparsed = crate::RestFormat::parse(&written, RestFlavor::Generic).unwrap();
+        assert_eq!(reparsed.requests[0], req);
+    }
+
     #[test]
     fn parse_request_and_raw_body_test() {
         let example = indoc! {r#"
@@ -366,6 +1925,29 @@ mod test {
         );
     }
     
+    #[test]
+    fn parse_body_by_content_length_test() {
+        let example = [
+            "POST /post HTTP/1.1",
+            "Content-Type: application/json",
+            "Content-Length: 12",
+            r#"{"a": "bcd"}"#,
+        ].join("\r\n");
+
+        let (req, body) = parse_request_and_raw_body(&example);
+        assert_eq!(req, "POST /post HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 12\r\n");
+        assert_eq!(body, Some(r#"{"a": "bcd"}"#.to_string()));
+
+        let req = parse_raw(&example);
+        assert_eq!(req.headers.len(), 2);
+        assert_eq!(req.body, Some(Body::Text(Template::new(r#"{"a": "bcd"}"#))));
+
+        // The length has to line up with the start of a line
+        let example = "POST /post HTTP/1.1\r\nContent-Length: 5\r\nX-A: abcdefg";
+        let (_, body) = parse_request_and_raw_body(example);
+        assert_eq!(body, None);
+    }
+
     #[test]
     fn parse_body_test() {
         let content_type = "text/plain"; 
@@ -418,6 +2000,980 @@ mod test {
             c=3
         "#};
         assert_eq!(Body::parse(form_body, FORM_URL_ENCODED), text("a=1&b=2&c=3"));
+
+        let form_content_type = "Application/X-WWW-Form-Urlencoded; charset=utf-8";
+        assert_eq!(Body::parse(form_body, form_content_type), text("a=1&b=2&c=3"));
+    }
+
+    #[test]
+    fn ndjson_body_variant_test() {
+        let vars = RestVariables::new();
+        let raw = "POST https://example.com/_bulk HTTP/1.1\r\nContent-Type: application/x-ndjson\r\n\r\n{\"index\": {}}\r\n\r\n{\"name\": \"joe\"}\r\n";
+        let req = parse_raw(raw);
+        let body = req.body.clone().unwrap();
+        assert_eq!(body.kind(), BodyKind::Ndjson);
+        // Blank lines, the line endings and the final newline are kept
+        assert_eq!(body.body_bytes(&vars).unwrap(), b"{\"index\": {}}\r\n\r\n{\"name\": \"joe\"}\r\n");
+        assert_eq!(body.ndjson_lines(&vars).unwrap(), vec!["{\"index\": {}}", "", "{\"name\": \"joe\"}"]);
+
+        // Without a final newline none is added
+        let raw = "POST https://example.com/_bulk HTTP/1.1\r\nContent-Type: application/x-ndjson\r\n\r\n{\"a\": 1}\r\n{\"b\": 2}";
+        let without_newline = parse_raw(raw);
+        let without_newline = without_newline.body.unwrap();
+        assert_eq!(without_newline.body_bytes(&vars).unwrap(), b"{\"a\": 1}\r\n{\"b\": 2}");
+        assert_eq!(without_newline.ndjson_lines(&vars).unwrap(), vec!["{\"a\": 1}", "{\"b\": 2}"]);
+
+        let body = Body::parse("{\"a\": 1}\n{\"b\": 2}\n", "application/jsonlines");
+        assert_eq!(body, Body::Ndjson(Template::new("{\"a\": 1}\n{\"b\": 2}\n")));
+
+        // Constructed bodies are sent exactly as written
+        let body = Body::Ndjson(Template::new("{\"a\": 1}"));
+        assert_eq!(body.body_bytes(&vars).unwrap(), b"{\"a\": 1}");
+
+        let body = Body::parse("{\"a\": 1}", "application/json");
+        assert_eq!(body.kind(), BodyKind::Text);
+        assert_eq!(body.ndjson_lines(&vars), None);
+
+        let text = req.to_string();
+        let reparsed = crate::RestFormat::parse(&text, RestFlavor::Generic).unwrap();
+        assert_eq!(reparsed.requests[0].body, req.body);
+    }
+
+    #[test]
+    fn load_contents_test() {
+        let dir = std::env::temp_dir().join(format!("rest_parser_load_contents_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("latin1.txt"), b"caf\xe9 {{name}}").unwrap();
+        std::fs::write(dir.join("utf16.txt"), b"\xff\xfeh\x00i\x00").unwrap();
+        std::fs::write(dir.join("utf8.txt"), "caf\u{e9} {{name}}").unwrap();
+
+        let mut vars = RestVariables::new();
+        vars.insert("name".into(), Template::new("Ben"));
+        let load = |body: &str| Body::parse(body, "text/plain").load_contents(&dir, &vars);
+
+        // Without an encoding the file has to be UTF-8
+        assert!(load("< ./latin1.txt").is_err());
+        assert_eq!(load("<latin1 ./latin1.txt").unwrap(), "caf\u{e9} {{name}}");
+        assert_eq!(load("<@latin1 ./latin1.txt").unwrap(), "caf\u{e9} Ben");
+        assert_eq!(load("<utf-16 ./utf16.txt").unwrap(), "hi");
+        assert_eq!(load("<utf16 ./utf16.txt").unwrap(), "hi");
+        assert_eq!(load("<@utf-8 ./utf8.txt").unwrap(), "caf\u{e9} Ben");
+        assert_eq!(load("<@ ./utf8.txt").unwrap(), "caf\u{e9} Ben");
+
+        let error = load("<klingon ./utf8.txt").unwrap_err();
+        assert_eq!(error.to_string(), "Unknown encoding 'klingon'");
+        assert!(load("< ./missing.txt").is_err());
+        assert!(Body::Text(Template::new("a")).load_contents(&dir, &vars).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn graphql_body_test() {
+        let text = indoc! {r#"
+            POST https://example.com/graphql HTTP/1.1
+            Content-Type: application/json
+            X-REQUEST-TYPE: GraphQL
+
+            query ($id: ID!) {
+              user(id: $id) { name }
+            }
+
+            {
+              "id": "{{id}}"
+            }
+
+            ###
+            POST https://example.com/graphql HTTP/1.1
+            Content-Type: application/graphql
+
+            query { users { id } }
+        "#};
+        let format = crate::RestFormat::parse(text, RestFlavor::Generic).unwrap();
+
+        let body = format.requests[0].body.clone().unwrap();
+        assert_eq!(body.kind(), BodyKind::GraphQL);
+        let Body::GraphQL { query, variables } = &body else { unreachable!() };
+        assert_eq!(query.raw, "query ($id: ID!) {\r\nuser(id: $id) { name }\r\n}");
+        assert_eq!(variables.as_ref().unwrap().raw, "{\r\n\"id\": \"{{id}}\"\r\n}");
+        // The marker header is kept
+        assert!(format.requests[0].headers.contains_key("X-REQUEST-TYPE"));
+
+        let mut vars = RestVariables::new();
+        vars.insert("id".into(), Template::new("7"));
+        assert_eq!(
+            body.body_text(&vars).unwrap(),
+            "query ($id: ID!) {\r\nuser(id: $id) { name }\r\n}\r\n\r\n{\r\n\"id\": \"7\"\r\n}",
+        );
+
+        // No variables block so it stays text
+        let body = format.requests[1].body.clone().unwrap();
+        assert_eq!(body, Body::Text(Template::new("query { users { id } }")));
+
+        let reparsed = crate::RestFormat::parse(&format.to_string(), RestFlavor::Generic).unwrap();
+        assert_eq!(reparsed.requests[0].body, format.requests[0].body);
+    }
+
+    #[test]
+    fn multipart_body_test() {
+        let text = indoc! {r#"
+            POST https://example.com/upload HTTP/1.1
+            Content-Type: multipart/form-data; boundary=WebAppBoundary
+
+            --WebAppBoundary
+            Content-Disposition: form-data; name="title"
+
+            {{title}}
+            --WebAppBoundary
+            Content-Disposition: form-data; name="data"; filename="data.json"
+            Content-Type: application/json
+
+            < ./data.json
+            --WebAppBoundary--
+
+            ###
+            POST https://example.com/upload HTTP/1.1
+            Content-Type: multipart/form-data
+
+            --WebAppBoundary
+            Content-Disposition: form-data; name="title"
+
+            hello
+            --WebAppBoundary--
+        "#};
+        let format = crate::RestFormat::parse(text, RestFlavor::Generic).unwrap();
+
+        let body = format.requests[0].body.clone().unwrap();
+        let Body::Multipart { boundary, parts } = &body else { panic!("Expected multipart: {body:?}") };
+        assert_eq!(boundary, "WebAppBoundary");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "title");
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].content, MultipartContent::Text(Template::new("{{title}}")));
+        assert_eq!(parts[1].name, "data");
+        assert_eq!(parts[1].filename.as_deref(), Some("data.json"));
+        assert_eq!(parts[1].content_type().unwrap().raw, "application/json");
+        assert_eq!(
+            parts[1].content,
+            MultipartContent::LoadFromFile {
+                process_variables: false,
+                encoding: None,
+                filepath: Template::new("./data.json"),
+            },
+        );
+        assert_eq!(body.templates().len(), 5);
+        assert_eq!(body.body_text(&RestVariables::new()), None);
+        assert_eq!(body.rendered_preview(&RestVariables::new(), 50), "<multipart: title, data>");
+
+        let reparsed = crate::RestFormat::parse(&format.to_string(), RestFlavor::Generic).unwrap();
+        assert_eq!(reparsed.requests[0].body, format.requests[0].body);
+
+        // Without a boundary the body stays text
+        let body = format.requests[1].body.clone().unwrap();
+        assert_eq!(body.kind(), BodyKind::Text);
+
+        let mut vars = RestVariables::new();
+        vars.insert("title".into(), Template::new("Hi"));
+        let body = Body::parse(
+            "--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\n{{title}}\r\n--b--",
+            "multipart/form-data; boundary=b",
+        );
+        assert_eq!(
+            body.body_text(&vars).unwrap(),
+            "--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHi\r\n--b--\r\n",
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn parse_ndjson_body_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("INDEX".into(), Template::new("users"));
+
+        let body = Body::Text(Template::new(indoc! {r#"
+            {"index": {"_index": "{{INDEX}}"}}
+            {"name": "joe"}
+            {"name": "jane"}"#}));
+        let values = body.as_ndjson(&vars).unwrap();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0]["index"]["_index"], "users");
+        assert_eq!(values[2]["name"], "jane");
+
+        let body = Body::Text(Template::new("{\"a\": 1}\r\n\r\n{\"b\": 2}\r\n"));
+        let values = body.as_ndjson(&vars).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[1]["b"], 2);
+
+        let body = Body::Text(Template::new("{\"a\": 1}\n{oops}"));
+        let err = body.as_ndjson(&vars).unwrap_err();
+        assert_eq!(err.to_string(), "Line 2 of the body is not valid JSON");
+    }
+
+    #[cfg(feature = "json5")]
+    #[test]
+    fn parse_lenient_json_body_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("NAME".into(), Template::new("joe"));
+
+        let body = Body::Text(Template::new(indoc! {r#"
+            {
+                // The user
+                "name": "{{NAME}}",
+                "tags": ["a", "b",],
+            }"#}));
+
+        assert!(body.as_json(&vars).is_err());
+
+        let value = body.as_json_lenient(&vars).unwrap();
+        assert_eq!(value["name"], "joe");
+        assert_eq!(value["tags"][1], "b");
+
+        let strict = Body::Text(Template::new(r#"{"name": "{{NAME}}"}"#));
+        assert_eq!(strict.as_json(&vars).unwrap()["name"], "joe");
+    }
+
+    #[test]
+    fn body_kind_test() {
+        fn request(raw: &str) -> RestRequest {
+            let raw = raw.replace("\n", "\r\n");
+            parse_raw(&raw)
+        }
+
+        let req = request("GET https://example.com HTTP/1.1\n");
+        assert!(!req.has_body());
+        assert_eq!(req.body_kind(), None);
+
+        let req = request("POST https://example.com HTTP/1.1\n\n{\"a\": 1}");
+        assert!(req.has_body());
+        assert_eq!(req.body_kind(), Some(BodyKind::Text));
+
+        let req = request("POST https://example.com HTTP/1.1\n\n< ./file.json");
+        assert!(req.has_body());
+        assert_eq!(req.body_kind(), Some(BodyKind::LoadFromFile));
+
+        let req = request("POST https://example.com HTTP/1.1\n\n{\"a\": 1}\n\n>> ./out.json");
+        assert!(req.has_body());
+        assert_eq!(req.body_kind(), Some(BodyKind::SaveToFile));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn settings_command_test() {
+        let text = indoc! {r#"
+            ### Valid
+            # @settings {"retries": 3, "follow": true, "tags": ["a"]}
+            GET https://example.com HTTP/1.1
+
+            ### Invalid
+            # @settings {retries: 3
+            GET https://example.com HTTP/1.1
+        "#};
+        let format = crate::RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+
+        let valid = &format.requests[0];
+        let settings = valid.settings_json().unwrap().unwrap();
+        assert_eq!(settings["retries"], 3);
+        assert_eq!(settings["follow"], true);
+        assert_eq!(settings["tags"][0], "a");
+        assert!(!valid.commands.contains_key("settings"));
+
+        let invalid = &format.requests[1];
+        assert_eq!(invalid.settings.as_deref(), Some("{retries: 3"));
+        assert!(invalid.settings_json().is_err());
+        assert!(!invalid.commands.contains_key("settings"));
+
+        let options = ParseOptions { strict: true, ..Default::default() };
+        assert!(crate::RestFormat::parse_with_options(text, RestFlavor::Jetbrains, &options).is_err());
+    }
+
+    #[test]
+    fn authorization_header_test() {
+        let bearer = parse_raw("GET https://example.com HTTP/1.1\r\nAuthorization: Bearer {{token}}\r\n");
+        assert_eq!(
+            bearer.authorization_header(),
+            Some(("Authorization".to_string(), "Bearer {{token}}".to_string()))
+        );
+
+        let basic = parse_raw("GET https://example.com HTTP/1.1\r\nauthorization: Basic Zm9vOmJhcg==\r\n");
+        assert_eq!(
+            basic.authorization_header(),
+            Some(("Authorization".to_string(), "Basic Zm9vOmJhcg==".to_string()))
+        );
+
+        let none = parse_raw("GET https://example.com HTTP/1.1\r\n");
+        assert_eq!(none.authorization_header(), None);
+    }
+
+    #[test]
+    fn leading_blank_lines_test() {
+        let raw = "\r\n\r\n\r\nPOST https://example.com HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{\"a\": 1}";
+        let req = parse_raw(raw);
+        assert_eq!(req.method.raw, "POST");
+        assert_eq!(req.headers.get("Content-Type").unwrap().raw, "application/json");
+        assert_eq!(req.body, Some(Body::Text(Template::new("{\"a\": 1}"))));
+
+        let text = "###\n\n\n  \nGET https://example.com HTTP/1.1\nAccept: */*\n";
+        let format = crate::RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        assert_eq!(format.requests.len(), 1);
+        assert_eq!(format.requests[0].headers.get("Accept").unwrap().raw, "*/*");
+        assert_eq!(format.requests[0].body, None);
+    }
+
+    #[test]
+    fn header_value_with_commas_test() {
+        let raw = "GET https://example.com HTTP/1.1\r\nPrefer: return=representation, wait=10\r\nX-Tags: \"a, b\", {{tag}}, c\r\n";
+        let req = parse_raw(raw);
+        assert_eq!(req.headers.len(), 2);
+        assert_eq!(req.headers.get("Prefer").unwrap(), &Template::new("return=representation, wait=10"));
+        assert_eq!(req.headers.get("Prefer").unwrap().parts, vec![TemplatePart::text("return=representation, wait=10")]);
+
+        let tags = req.headers.get("X-Tags").unwrap();
+        assert_eq!(tags.raw, "\"a, b\", {{tag}}, c");
+        let mut vars = RestVariables::new();
+        vars.extend_str([("tag", "d")]);
+        assert_eq!(tags.render(&vars), "\"a, b\", d, c");
+    }
+
+    #[test]
+    fn fingerprint_test() {
+        let parse = |raw: &str| {
+            let raw = raw.replace('\n', "\r\n");
+            parse_raw(&raw)
+        };
+
+        let req = parse("POST {{HOST}}/users?a=1&b=2 HTTP/1.1\nAccept: */*\nX-Id: 1\n\n{\"a\": 1}");
+        let same = parse("POST {{HOST}}/users?a=1&b=2 HTTP/1.1\nAccept: */*\nX-Id: 1\n\n{\"a\": 1}");
+        assert_eq!(req.fingerprint(), same.fingerprint());
+
+        // Order of the query and headers doesn't matter
+        let reordered = parse("POST {{HOST}}/users?b=2&a=1 HTTP/1.1\nx-id: 1\nAccept: */*\n\n{\"a\": 1}");
+        assert_eq!(req.fingerprint(), reordered.fingerprint());
+
+        let changed_header = parse("POST {{HOST}}/users?a=1&b=2 HTTP/1.1\nAccept: */*\nX-Id: 2\n\n{\"a\": 1}");
+        assert_ne!(req.fingerprint(), changed_header.fingerprint());
+
+        let changed_body = parse("POST {{HOST}}/users?a=1&b=2 HTTP/1.1\nAccept: */*\nX-Id: 1\n\n{\"a\": 2}");
+        assert_ne!(req.fingerprint(), changed_body.fingerprint());
+    }
+
+    #[test]
+    fn rest_url_display_round_trip_test() {
+        let url = RestUrl::from_str("https://x.com/a?q=a%26b&r=c%3Dd&s=50%25+off&{{k}}={{v}}#top").unwrap();
+        assert_eq!(url.query["q"].raw, "a&b");
+        assert_eq!(url.query["r"].raw, "c=d");
+        assert_eq!(url.query["s"].raw, "50% off");
+
+        let written = url.to_string();
+        assert_eq!(written, "https://x.com/a?q=a%26b&r=c%3Dd&s=50%25%20off&{{k}}={{v}}#top");
+        assert_eq!(RestUrl::from_str(&written).unwrap(), url);
+    }
+
+    #[test]
+    fn array_query_test() {
+        let url = RestUrl::from_str("{{HOST}}/posts?tags[]=a&tags[]={{tag}}&page=1&page=2").unwrap();
+        assert_eq!(url.query.get("tags[]").unwrap().raw, "a");
+        let tags: Vec<&str> = url.query.get_all("tags[]").map(|t| t.raw.as_str()).collect();
+        assert_eq!(tags, vec!["a", "{{tag}}"]);
+        assert_eq!(url.query.get("page").unwrap().raw, "1");
+        assert_eq!(url.query.len(), 4);
+        assert!(url.query.get("tags").is_none());
+        assert_eq!(url.to_string(), "{{HOST}}/posts?tags[]=a&tags[]={{tag}}&page=1&page=2");
+
+        let raw = "GET https://example.com/posts?tags[]=a&tags[]=b HTTP/1.1\r\n";
+        let req = parse_raw(raw);
+        let vars = RestVariables::new();
+        assert_eq!(req.query_get("tags[]", &vars).unwrap(), "a");
+        let values = req.query_values();
+        let tags: Vec<&str> = values["tags[]"].iter().map(|t| t.raw.as_str()).collect();
+        assert_eq!(tags, vec!["a", "b"]);
+        assert_eq!(req.templates().len(), 4);
+        assert!(req.to_raw_http(&vars, Path::new(".")).unwrap().starts_with("GET /posts?tags%5B%5D=a&tags%5B%5D=b HTTP/1.1"));
+    }
+
+    #[test]
+    fn take_http_version_test() {
+        assert_eq!(
+            take_http_version("GET /a HTTP/2\r\nAccept: */*"),
+            ("GET /a HTTP/1.1\r\nAccept: */*".to_string(), Some("2".to_string())),
+        );
+        assert_eq!(take_http_version("GET /a HTTP/1.0"), ("GET /a HTTP/1.1".to_string(), Some("1.0".to_string())));
+        assert_eq!(take_http_version("GET /a"), ("GET /a HTTP/1.1".to_string(), None));
+    }
+
+    #[test]
+    fn with_query_param_test() {
+        let raw = "GET https://example.com/posts?tags[]=a&tags[]=b&page=1 HTTP/1.1\r\n";
+        let req = parse_raw(raw);
+        let vars = RestVariables::from_iter([("size".to_string(), Template::new("50"))]);
+
+        let added = req.with_query_param("limit", "{{size}}".to_string());
+        assert_eq!(added.query_get("limit", &vars).unwrap(), "50");
+        assert!(added.to_raw_http(&vars, Path::new(".")).unwrap().starts_with("GET /posts?tags%5B%5D=a&tags%5B%5D=b&page=1&limit=50 HTTP/1.1"));
+
+        let overwritten = req.with_query_param("tags[]", Template::new("c"));
+        assert!(overwritten.to_raw_http(&vars, Path::new(".")).unwrap().starts_with("GET /posts?tags%5B%5D=c&page=1 HTTP/1.1"));
+
+        // The original request is untouched
+        assert!(req.query.get("limit").is_none());
+        assert_eq!(req.query_values()["tags[]"].len(), 2);
+        assert_eq!(overwritten.query.get_all("tags[]").count(), 1);
+    }
+
+    #[test]
+    fn oauth2_command_test() {
+        let text = indoc! {r#"
+            ### WithToken
+            # @oauth2 token-url=https://auth.example.com/token client-id={{clientId}} scope="read write" audience=api
+            GET https://example.com HTTP/1.1
+            Authorization: Bearer {{token}}
+
+            ### WithoutToken
+            # @oauth2 client_id=abc grant-type=client_credentials
+            GET https://example.com HTTP/1.1
+
+            ### Basic
+            # @oauth2 client-id=abc
+            GET https://example.com HTTP/1.1
+            Authorization: Basic Zm9vOmJhcg==
+        "#};
+        let format = crate::RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+
+        let with_token = &format.requests[0];
+        let Some(Authorization::OAuth2 { token, config }) = &with_token.authorization else {
+            panic!("Expected OAuth2: {:?}", with_token.authorization);
+        };
+        assert_eq!(token.as_ref().unwrap().raw, "{{token}}");
+        assert_eq!(config.token_url.as_ref().unwrap().raw, "https://auth.example.com/token");
+        assert_eq!(config.client_id.as_ref().unwrap().raw, "{{clientId}}");
+        assert_eq!(config.scope.as_ref().unwrap().raw, "read write");
+        assert_eq!(config.extra.get("audience").unwrap().raw, "api");
+        assert!(!with_token.commands.contains_key("oauth2"));
+        assert!(with_token.templates().iter().any(|t| t.contains_variable("clientId")));
+
+        let without_token = &format.requests[1];
+        let Some(Authorization::OAuth2 { token, config }) = &without_token.authorization else {
+            panic!("Expected OAuth2: {:?}", without_token.authorization);
+        };
+        assert_eq!(*token, None);
+        assert_eq!(config.client_id.as_ref().unwrap().raw, "abc");
+        assert_eq!(config.grant_type.as_ref().unwrap().raw, "client_credentials");
+        assert_eq!(config.token_url, None);
+
+        // Other schemes are kept
+        let basic = &format.requests[2];
+        assert!(matches!(basic.authorization, Some(Authorization::Basic { .. })));
+        assert!(basic.commands.contains_key("oauth2"));
+    }
+
+    #[test]
+    fn ipv6_host_test() {
+        let url = RestUrl::from_str("http://[::1]:8080/path?x=1").unwrap();
+        assert_eq!(url.url.raw, "http://[::1]:8080/path");
+        assert_eq!(url.query.get("x").unwrap().raw, "1");
+        assert_eq!(url.to_string(), "http://[::1]:8080/path?x=1");
+
+        let url = RestUrl::from_str("http://[2001:db8::1]:443/path").unwrap();
+        assert_eq!(url.url.raw, "http://[2001:db8::1]:443/path");
+        assert!(url.query.is_empty());
+
+        let raw = "GET http://[::1]:8080/path?x=1&y={{y}} HTTP/1.1\r\nHost: [::1]:8080\r\n";
+        let req = parse_raw(raw);
+        assert_eq!(req.url.raw, "http://[::1]:8080/path");
+        assert_eq!(req.query.get("x").unwrap().raw, "1");
+        assert_eq!(req.query.get("y").unwrap().raw, "{{y}}");
+        assert_eq!(req.headers.get("Host").unwrap().raw, "[::1]:8080");
+
+        let raw = "GET http://[::1]:8080 HTTP/1.1\r\n";
+        let req = parse_raw(raw);
+        assert_eq!(req.url.raw, "http://[::1]:8080");
+        assert_eq!(
+            req.to_raw_http(&RestVariables::new(), Path::new(".")).unwrap(),
+            "GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn tab_separated_request_line_test() {
+        let raw = "GET\thttps://x.com/a?b=1\t HTTP/1.1\r\nAccept:\t*/*\r\n\r\nindented\tbody";
+        let req = parse_raw(raw);
+        assert_eq!(req.method.raw, "GET");
+        assert_eq!(req.url.raw, "https://x.com/a");
+        assert_eq!(req.query.get("b").unwrap().raw, "1");
+        assert_eq!(req.headers.get("Accept").unwrap().raw, "*/*");
+        assert_eq!(req.body, Some(Body::Text(Template::new("indented\tbody"))));
+    }
+
+    #[test]
+    fn save_target_test() {
+        let mut vars = RestVariables::new();
+        vars.extend_str([("id", "7"), ("dir", "./out")]);
+
+        let body = Body::parse("{\"id\": {{id}}}\r\n\r\n>> {{dir}}/user.json", "application/json");
+        assert_eq!(body.body_text(&vars).unwrap(), "{\"id\": 7}");
+        assert_eq!(body.save_target(&vars).unwrap(), "./out/user.json");
+
+        let body = Body::parse("{\"id\": {{id}}}", "application/json");
+        assert_eq!(body.body_text(&vars).unwrap(), "{\"id\": 7}");
+        assert_eq!(body.save_target(&vars), None);
+
+        let body = Body::parse("< ./file.json", "application/json");
+        assert_eq!(body.body_text(&vars), None);
+        assert_eq!(body.save_target(&vars), None);
+    }
+
+    #[test]
+    fn parse_raw_authorization_test() {
+        let raw = "GET https://example.com HTTP/1.1\r\nAuthorization: {{authHeader}}\r\n";
+        let req = parse_raw(raw);
+        assert_eq!(req.authorization, Some(Authorization::Raw(Template::new("{{authHeader}}"))));
+        assert!(req.headers.is_empty());
+    }
+
+    #[test]
+    fn parse_last_header_without_body_test() {
+        let raw = "GET https://example.com HTTP/1.1\r\nAccept: application/json\r\nX-Last: last";
+        let req = parse_raw(raw);
+        assert_eq!(req.headers.len(), 2);
+        assert_eq!(req.headers.get("X-Last").unwrap().raw, "last");
+    }
+
+    #[test]
+    fn max_body_bytes_test() {
+        let raw = "POST https://example.com HTTP/1.1\r\n\r\n0123456789";
+        let options = ParseOptions { max_body_bytes: Some(10), ..Default::default() };
+        let req = RestRequest::from_raw_request(RequestBlock::new(raw), RestFlavor::Generic, &options);
+        assert!(req.is_ok());
+
+        let options = ParseOptions { max_body_bytes: Some(9), ..Default::default() };
+        let err = RestRequest::from_raw_request(RequestBlock::new(raw), RestFlavor::Generic, &options)
+            .unwrap_err();
+        assert!(err.to_string().contains("10 bytes"));
+    }
+
+    #[test]
+    fn max_headers_test() {
+        let headers: String = (0..80).map(|i| format!("X-Header-{i}: {i}\r\n")).collect();
+        let raw = format!("GET https://example.com HTTP/1.1\r\n{headers}");
+
+        let err = RestRequest::from_raw_request(RequestBlock::new(&raw), RestFlavor::Generic, &ParseOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("more than 64 headers"));
+
+        let options = ParseOptions { max_headers: Some(100), ..Default::default() };
+        let req = RestRequest::from_raw_request(RequestBlock::new(&raw), RestFlavor::Generic, &options).unwrap();
+        assert_eq!(req.headers.len(), 80);
+        assert_eq!(req.headers["X-Header-79"].raw, "79");
+    }
+
+    #[test]
+    fn header_command_test() {
+        let mut commands = IndexMap::new();
+        commands.insert("Content-Type".to_string(), Some(FORM_URL_ENCODED.to_string()));
+        commands.insert("no-log".to_string(), None);
+
+        let raw = "POST https://example.com HTTP/1.1\r\n\r\na=1&\r\nb=2";
+        let req = RestRequest::from_raw_request(RequestBlock { commands: commands.clone(), ..RequestBlock::new(raw) }, RestFlavor::Generic, &ParseOptions::default()).unwrap();
+        assert_eq!(req.headers.get("Content-Type").unwrap().raw, FORM_URL_ENCODED);
+        assert_eq!(req.commands.keys().collect::<Vec<_>>(), vec!["no-log"]);
+        // The promoted content type is used when parsing the body
+        assert_eq!(req.body, Some(Body::Text(Template::new("a=1&b=2"))));
+
+        // A real header wins over the command
+        let raw = "POST https://example.com HTTP/1.1\r\ncontent-type: text/plain\r\n\r\nhello";
+        let req = RestRequest::from_raw_request(RequestBlock { commands, ..RequestBlock::new(raw) }, RestFlavor::Generic, &ParseOptions::default()).unwrap();
+        assert_eq!(req.headers.len(), 1);
+        assert_eq!(req.headers.get("content-type").unwrap().raw, "text/plain");
+    }
+
+    #[test]
+    fn query_get_test() {
+        let raw = "GET https://example.com/search?q={{term}}&page=2 HTTP/1.1";
+        let req = parse_raw(raw);
+
+        let mut vars = RestVariables::new();
+        vars.insert("term".into(), Template::new("rust"));
+
+        assert_eq!(req.query_get("q", &vars), Some("rust".into()));
+        assert_eq!(req.query_get("page", &vars), Some("2".into()));
+        assert_eq!(req.query_get("missing", &vars), None);
+    }
+
+    #[test]
+    fn extend_str_test() {
+        let mut vars = RestVariables::new();
+        vars.extend_str([("HOST", "https://example.com"), ("URL", "{{HOST}}/get")]);
+        vars.extend_str(vec![("HOST", "http://localhost")]);
+
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars.get("HOST").unwrap().raw, "http://localhost");
+        assert_eq!(vars.get("URL").unwrap().variable_names(), vec!["HOST"]);
+    }
+
+    #[test]
+    fn parse_split_request_line_test() {
+        let raw = "POST\r\nhttps://example.com/post HTTP/1.1\r\nContent-Type: text/plain\r\n\r\nhello";
+        let req = parse_raw(raw);
+        assert_eq!(req.method.raw, "POST");
+        assert_eq!(req.url.raw, "https://example.com/post");
+        assert_eq!(req.headers.get("Content-Type").unwrap().raw, "text/plain");
+        assert_eq!(req.body, Some(Body::Text(Template::new("hello"))));
+
+        let raw = "GET\r\n{{HOST}}/get";
+        assert_eq!(join_split_request_line(raw), "GET {{HOST}}/get");
+
+        // A normal request is left alone
+        let raw = "GET https://example.com HTTP/1.1\r\nAccept: */*";
+        assert_eq!(join_split_request_line(raw), raw);
+    }
+
+    #[test]
+    fn rendered_preview_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("NAME".into(), Template::new("Joe"));
+
+        let body = Body::Text(Template::new(indoc! {r#"
+            {
+                "name": "{{NAME}}",
+                "description": "A very long description"
+            }"#}));
+        assert_eq!(body.rendered_preview(&vars, 100), r#"{ "name": "Joe", "description": "A very long description" }"#);
+        assert_eq!(body.rendered_preview(&vars, 16), r#"{ "name": "Jo..."#);
+        assert_eq!(body.rendered_preview(&vars, 16).chars().count(), 16);
+        assert_eq!(body.rendered_preview(&vars, 2), "..");
+
+        let body = Body::LoadFromFile {
+            process_variables: false,
+            encoding: None,
+            filepath: Template::new("./{{NAME}}.json"),
+        };
+        assert_eq!(body.rendered_preview(&vars, 50), "<file: ./Joe.json>");
+        assert_eq!(body.rendered_preview(&vars, 10), "<file: ...");
+
+        // The body sent before the response is saved
+        let body = Body::SaveToFile {
+            text: Template::new("name={{NAME}}"),
+            filepath: Template::new("./out.json"),
+        };
+        assert_eq!(body.rendered_preview(&vars, 50), "name=Joe");
+    }
+
+    #[test]
+    fn default_content_type_test() {
+        let json_body = "\n  {\"a\": 1}";
+        assert_eq!(RestFlavor::Jetbrains.default_content_type(json_body), Some("application/json"));
+        assert_eq!(RestFlavor::Vscode.default_content_type(json_body), Some("application/json"));
+        assert_eq!(RestFlavor::Generic.default_content_type(json_body), Some("application/json"));
+        assert_eq!(RestFlavor::Generic.default_content_type("[1, 2]"), Some("application/json"));
+
+        for flavor in [RestFlavor::Jetbrains, RestFlavor::Vscode, RestFlavor::Generic] {
+            assert_eq!(flavor.default_content_type("<?xml version=\"1.0\"?><a/>"), Some("application/xml"));
+            assert_eq!(flavor.default_content_type("<user>Joe</user>"), Some("application/xml"));
+        }
+
+        let text_body = "hello";
+        assert_eq!(RestFlavor::Jetbrains.default_content_type(text_body), Some("text/plain"));
+        assert_eq!(RestFlavor::Vscode.default_content_type(text_body), None);
+        assert_eq!(RestFlavor::Generic.default_content_type(text_body), None);
+    }
+
+    #[test]
+    fn rendered_url_test() {
+        let raw = "GET {{HOST}}/search?q={{search}}&filter=a%26b&tags[]=x#{{section}} HTTP/1.1";
+        let req = parse_raw(raw);
+        let mut vars = RestVariables::new();
+        vars.insert("HOST".into(), Template::new("https://example.com"));
+        vars.insert("search".into(), Template::new("hello world & more=1"));
+        vars.insert("section".into(), Template::new("results"));
+
+        assert_eq!(
+            req.rendered_url(&vars),
+            "https://example.com/search?q=hello+world+%26+more%3D1&filter=a%26b&tags%5B%5D=x#results",
+        );
+        // The rendered URL parses back into the same values
+        let url = Url::parse(&req.rendered_url(&vars)).unwrap();
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert_eq!(pairs[0], ("q".into(), "hello world & more=1".into()));
+        assert_eq!(pairs[1], ("filter".into(), "a&b".into()));
+
+        let raw = "GET https://example.com/plain HTTP/1.1";
+        let req = parse_raw(raw);
+        assert_eq!(req.rendered_url(&vars), "https://example.com/plain");
+    }
+
+    #[test]
+    fn merge_headers_test() {
+        let raw = "GET https://example.com HTTP/1.1\r\naccept: text/plain\r\nX-Trace: 1";
+        let req = parse_raw(raw);
+        let mut extra = TemplateMap::new();
+        extra.insert("Accept".into(), Template::new("application/json"));
+        extra.insert("X-Api-Key".into(), Template::new("{{api_key}}"));
+        extra.insert("User-Agent".into(), Template::new("rest_parser"));
+
+        let headers = |req: &RestRequest| -> Vec<(String, String)> {
+            req.headers.iter().map(|(k, v)| (k.clone(), v.raw.clone())).collect()
+        };
+
+        let mut overwritten = req.clone();
+        overwritten.merge_headers(&extra, true);
+        assert_eq!(headers(&overwritten), vec![
+            ("Accept".into(), "application/json".into()),
+            ("X-Trace".into(), "1".into()),
+            ("X-Api-Key".into(), "{{api_key}}".into()),
+            ("User-Agent".into(), "rest_parser".into()),
+        ]);
+
+        let mut kept = req.clone();
+        kept.merge_headers(&extra, false);
+        assert_eq!(headers(&kept), vec![
+            ("accept".into(), "text/plain".into()),
+            ("X-Trace".into(), "1".into()),
+            ("X-Api-Key".into(), "{{api_key}}".into()),
+            ("User-Agent".into(), "rest_parser".into()),
+        ]);
+    }
+
+    #[test]
+    fn set_and_remove_header_test() {
+        let raw = "POST https://example.com HTTP/1.1\r\ncontent-type: text/plain\r\nAccept: */*\r\nX-Remove: me";
+        let mut req = parse_raw(raw);
+
+        req.set_header("Content-Type", Template::new("application/json"));
+        req.set_header("X-Token", "{{token}}".to_string());
+        let headers: Vec<(&str, &str)> = req.headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.raw.as_str()))
+            .collect();
+        assert_eq!(headers, vec![
+            ("Content-Type", "application/json"),
+            ("Accept", "*/*"),
+            ("X-Remove", "me"),
+            ("X-Token", "{{token}}"),
+        ]);
+
+        assert_eq!(req.remove_header("x-remove"), Some(Template::new("me")));
+        assert_eq!(req.remove_header("x-remove"), None);
+        assert_eq!(req.headers.len(), 3);
+        assert_eq!(req.headers.get_index(1).unwrap().0, "Accept");
+    }
+
+    #[test]
+    fn bodiless_method_with_body_test() {
+        for method in ["HEAD", "OPTIONS", "TRACE"] {
+            let raw = format!("{method} https://example.com HTTP/1.1\r\n\r\nunexpected body");
+            let req = parse_raw(&raw);
+            assert_eq!(req.body, Some(Body::Text(Template::new("unexpected body"))));
+            assert_eq!(req.validate(), vec![format!("{method} requests should not have a body")]);
+        }
+
+        let raw = "POST https://example.com HTTP/1.1\r\n\r\nexpected body";
+        let req = parse_raw(raw);
+        assert!(req.validate().is_empty());
+    }
+
+    #[test]
+    fn parse_script_variables_test() {
+        let script = indoc! {r#"
+            const id = Math.random();
+            request.variables.set("firstname", "John");
+            request.variables.set( 'url' , '{{HOST}}/users' );
+            request.variables.set("computed", id);
+        "#};
+        let variables = parse_script_variables(script);
+        assert_eq!(variables.len(), 2);
+        assert_eq!(variables.get("firstname").unwrap().raw, "John");
+        assert_eq!(variables.get("url").unwrap().variable_names(), vec!["HOST"]);
+
+        let block = RequestBlock {
+            pre_request_script: Some(script.into()),
+            ..RequestBlock::new("GET https://example.com HTTP/1.1")
+        };
+        let req = RestRequest::from_raw_request(block, RestFlavor::Jetbrains, &ParseOptions::default()).unwrap();
+        assert_eq!(req.script_variables.keys().collect::<Vec<_>>(), vec!["firstname", "url"]);
+    }
+
+    #[test]
+    fn resolve_all_test() {
+        let mut vars = RestVariables::new();
+        vars.extend_str([
+            ("URL", "{{HOST}}/api"),
+            ("HOST", "https://{{DOMAIN}}"),
+            ("DOMAIN", "example.com"),
+            ("MISSING", "{{nope}}!"),
+            ("DEFAULTED", "{{nope:fallback}}/{{DOMAIN:unused}}"),
+        ]);
+        let resolved = vars.resolve_all().unwrap();
+        assert_eq!(resolved.keys().collect::<Vec<_>>(), vec!["URL", "HOST", "DOMAIN", "MISSING", "DEFAULTED"]);
+        assert_eq!(resolved.get("DEFAULTED").unwrap(), "fallback/example.com");
+        assert_eq!(resolved.get("URL").unwrap(), "https://example.com/api");
+        assert_eq!(resolved.get("HOST").unwrap(), "https://example.com");
+        assert_eq!(resolved.get("MISSING").unwrap(), "!");
+
+        let mut cyclic = RestVariables::new();
+        cyclic.extend_str([("A", "{{B}}"), ("B", "x{{C}}"), ("C", "{{A}}")]);
+        let err = cyclic.resolve_all().unwrap_err();
+        assert_eq!(err.to_string(), "Cyclic variable reference: A -> B -> C -> A");
+
+        let mut own = RestVariables::new();
+        own.extend_str([("A", "{{A}}")]);
+        assert!(own.resolve_all().is_err());
+
+        // `.env` values resolve the same way as when rendering
+        let mut dotenv = RestVariables::new();
+        dotenv.extend_str([("DOMAIN", "example.com"), ("AUTH", "Bearer {{$dotenv API_KEY}}"), ("OTHER", "{{$dotenv NOPE}}")]);
+        dotenv.insert(crate::system::dotenv_variable_name("API_KEY"), Template::new("key-for-{{DOMAIN}}"));
+        let resolved = dotenv.resolve_all().unwrap();
+        assert_eq!(resolved["AUTH"], "Bearer key-for-example.com");
+        assert_eq!(resolved["AUTH"], dotenv["AUTH"].render(&dotenv));
+        assert_eq!(resolved["OTHER"], "");
+    }
+
+    #[test]
+    fn language_hint_test() {
+        let text = indoc! {r#"
+            # @language form
+            POST https://example.com HTTP/1.1
+
+            a=1
+            &b=2
+
+            ###
+            # @language GraphQL
+            POST https://example.com/graphql HTTP/1.1
+            Content-Type: application/json
+
+            query { users { id } }
+
+            ###
+            POST https://example.com HTTP/1.1
+
+            a=1
+            &b=2
+        "#};
+        let format = crate::RestFormat::parse(text, RestFlavor::Generic).unwrap();
+
+        // Parsed as a form body without a `Content-Type` header
+        let form = &format.requests[0];
+        assert_eq!(form.body_language.as_deref(), Some("form"));
+        assert!(!form.commands.contains_key("language"));
+        assert_eq!(form.body.as_ref().unwrap().templates()[0].raw, "a=1&b=2");
+
+        let graphql = &format.requests[1];
+        assert_eq!(graphql.body_language.as_deref(), Some("graphql"));
+        assert_eq!(language_content_type("graphql"), "application/graphql");
+        // A query without a variables block stays text
+        assert_eq!(graphql.body.as_ref().unwrap().kind(), BodyKind::Text);
+
+        let no_hint = &format.requests[2];
+        assert_eq!(no_hint.body_language, None);
+        assert_eq!(no_hint.body.as_ref().unwrap().templates()[0].raw, "a=1\r\n&b=2");
+    }
+
+    #[test]
+    fn header_escapes_test() {
+        let raw = "GET https://example.com HTTP/1.1\r\nX-Message: line one\\nline two\\tend \\d\r\n";
+
+        // Kept verbatim by default
+        let req = parse_raw(raw);
+        assert_eq!(req.headers["X-Message"].raw, "line one\\nline two\\tend \\d");
+
+        let options = ParseOptions { decode_header_escapes: true, ..Default::default() };
+        let req = RestRequest::from_raw_request(RequestBlock::new(raw), RestFlavor::Generic, &options).unwrap();
+        assert_eq!(req.headers["X-Message"].raw, "line one\nline two\tend \\d");
+
+        // Written back escaped so the header stays on one line
+        assert!(req.to_string().contains("X-Message: line one\\nline two\tend \\d\n"));
+    }
+
+    #[test]
+    fn folded_authorization_test() {
+        let raw = indoc! {r#"
+            GET https://example.com HTTP/1.1
+            Authorization: Bearer
+                eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiIxMjM0In0
+            Accept: application/json
+        "#}.replace("\n", "\r\n");
+        let req = parse_raw(&raw);
+        assert_eq!(
+            req.authorization,
+            Some(Authorization::Bearer("eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiIxMjM0In0".into()))
+        );
+        assert_eq!(req.headers.get("Accept").unwrap().raw, "application/json");
+
+        let raw = "GET https://example.com HTTP/1.1\r\nAuthorization: Basic\r\n\tZm9vOmJhcg==\r\n";
+        let req = parse_raw(raw);
+        assert_eq!(
+            req.authorization,
+            Some(Authorization::Basic { username: "foo".into(), password: Some("bar".into()) })
+        );
+
+        // Folded headers in a file are joined too, indented body lines are still trimmed
+        let text = indoc! {r#"
+            GET https://example.com HTTP/1.1
+            Authorization: Bearer
+              abc.def
+
+            {
+              "a": 1
+            }
+        "#};
+        let format = crate::RestFormat::parse(text, RestFlavor::Generic).unwrap();
+        assert_eq!(format.requests[0].authorization, Some(Authorization::Bearer("abc.def".into())));
+        assert_eq!(format.requests[0].body, Some(Body::Text(Template::new("{\r\n\"a\": 1\r\n}"))));
+    }
+
+    #[test]
+    fn to_raw_http_test() {
+        let raw = indoc! {r#"
+            POST {{HOST}}/post?q={{term}} HTTP/1.1
+            Authorization: Bearer {{token}}
+            Content-Type: application/json
+
+            {
+                "name": "{{name}}"
+            }
+        "#}.replace("\n", "\r\n");
+        let req = parse_raw(&raw);
+
+        let mut vars = RestVariables::new();
+        vars.insert("HOST".into(), Template::new("https://example.com:8443"));
+        vars.insert("term".into(), Template::new("rust & c"));
+        vars.insert("token".into(), Template::new("abc"));
+        vars.insert("name".into(), Template::new("Joe"));
+
+        let expected = [
+            "POST /post?q=rust+%26+c HTTP/1.1",
+            "Host: example.com:8443",
+            "Content-Type: application/json",
+            "Authorization: Bearer abc",
+            "Content-Length: 23",
+            "",
+            "{",
+            r#"    "name": "Joe""#,
+            "}",
+        ].join("\r\n");
+        assert_eq!(req.to_raw_http(&vars, Path::new(".")).unwrap(), expected);
+
+        // Basic credentials are rendered before they are encoded
+        let raw = "GET https://example.com/a#top HTTP/1.1\r\n";
+        let mut req = parse_raw(raw);
+        req.authorization = Some(Authorization::Basic { username: "{{user}}".into(), password: Some("{{password}}".into()) });
+        let vars = RestVariables::from_iter([
+            ("user".to_string(), Template::new("foo")),
+            ("password".to_string(), Template::new("bar")),
+        ]);
+        assert_eq!(
+            req.to_raw_http(&vars, Path::new(".")).unwrap(),
+            "GET /a HTTP/1.1\r\nHost: example.com\r\nAuthorization: Basic Zm9vOmJhcg==\r\n\r\n",
+        );
+
+        // A body loaded from a file is read relative to the base directory
+        let raw = "POST https://example.com/pets HTTP/1.1\r\nContent-Type: application/json\r\n\r\n< ./pets.json";
+        let req = parse_raw(raw);
+        let contents = std::fs::read_to_string("test_data/pets.json").unwrap();
+        let http = req.to_raw_http(&vars, Path::new("test_data")).unwrap();
+        assert!(http.contains(&format!("Content-Length: {}\r\n", contents.len())));
+        assert!(http.ends_with(&contents));
+
+        assert!(req.to_raw_http(&vars, Path::new(".")).is_err());
     }
 
     #[test]
@@ -426,7 +2982,7 @@ mod test {
             GET https://httpbin.org/get HTTP/1.1
         "#};
 
-        let req = RestRequest::from_raw_request(None, IndexMap::new(), get_request);
+        let req = RestRequest::from_raw_request(RequestBlock::new(get_request), RestFlavor::Generic, &ParseOptions::default());
         match req {
             Ok(RestRequest { url, method, .. }) => {
                 assert_eq!(url.to_string(), "https://httpbin.org/get");
@@ -440,7 +2996,7 @@ mod test {
             GET {{HOST}}/get HTTP/1.1
         "#};
 
-        let req = RestRequest::from_raw_request(None, IndexMap::new(), get_request);
+        let req = RestRequest::from_raw_request(RequestBlock::new(get_request), RestFlavor::Generic, &ParseOptions::default());
         match req {
             Ok(RestRequest { url, method, .. }) => {
                 assert_eq!(url.parts.first(), Some(&TemplatePart::var("HOST")));
@@ -454,7 +3010,7 @@ mod test {
             GET {{ HOST }}/get HTTP/1.1
         "#};
 
-        let req = RestRequest::from_raw_request(None, IndexMap::new(), get_request);
+        let req = RestRequest::from_raw_request(RequestBlock::new(get_request), RestFlavor::Generic, &ParseOptions::default());
         match req {
             Ok(RestRequest { url, method, .. }) => {
                 assert_eq!(url.parts.first(), Some(&TemplatePart::var("HOST")));
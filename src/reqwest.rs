@@ -0,0 +1,192 @@
+//! Build `reqwest` requests from parsed requests so they can be sent
+//! The response target of `Body::SaveToFile` isn't part of the request,
+//! use `Body::save_target` to get it
+
+use std::fs;
+
+use anyhow::{anyhow, Context};
+use reqwest::{Client, Method, Request, RequestBuilder};
+use url::Url;
+
+use crate::headers::{Authorization, AUTHORIZATION_HEADER};
+use crate::parser::{MultipartContent, MultipartPart};
+use crate::template::Template;
+use crate::{Body, RestRequest, RestVariables};
+
+const CONTENT_LENGTH: &str = "Content-Length";
+
+impl RestRequest {
+    /// Render the request into a `reqwest::Request`
+    pub fn to_reqwest(&self, vars: &RestVariables) -> anyhow::Result<Request> {
+        self.to_reqwest_builder(&Client::new(), vars)?
+            .build()
+            .context("Failed to build the request")
+    }
+
+    /// Render the request into a builder for an existing client
+    /// The method, url, query, headers, authorization and body are rendered with `vars`
+    /// A body loaded from a file is read when the builder is created
+    pub fn to_reqwest_builder(
+        &self,
+        client: &Client,
+        vars: &RestVariables,
+    ) -> anyhow::Result<RequestBuilder> {
+        let method = self.method.render(vars);
+        let method = Method::from_bytes(method.as_bytes())
+            .map_err(|_| anyhow!("Invalid method: {method}"))?;
+
+        let url = self.url.render(vars);
+        let mut url = Url::parse(&url).context(format!("Invalid URL: {url}"))?;
+        if !self.query.is_empty() {
+            let mut pairs = url.query_pairs_mut();
+            for (key, value) in self.query.iter() {
+                pairs.append_pair(key, &value.render(vars));
+            }
+        }
+
+        let mut builder = client.request(method, url);
+        for (name, value) in &self.headers {
+            // reqwest sets the length from the body
+            if !name.eq_ignore_ascii_case(CONTENT_LENGTH) {
+                builder = builder.header(name, value.render(vars));
+            }
+        }
+
+        let render = |value: &str| Template::new(value).render(vars);
+        builder = match &self.authorization {
+            Some(Authorization::Bearer(token)) => builder.bearer_auth(render(token)),
+            Some(Authorization::Basic { username, password }) => {
+                builder.basic_auth(render(username), password.as_deref().map(render))
+            }
+            Some(Authorization::Raw(value)) => {
+                builder.header(AUTHORIZATION_HEADER, value.render(vars))
+            }
+            Some(auth @ (Authorization::Digest { .. } | Authorization::Other { .. } | Authorization::Multiple(_))) => {
+                builder.header(AUTHORIZATION_HEADER, render(&auth.to_header()))
+            }
+            Some(Authorization::OAuth2 { token: Some(token), .. }) => {
+                builder.bearer_auth(token.render(vars))
+            }
+            Some(Authorization::OAuth2 { token: None, .. }) | None => builder,
+        };
+
+        let body = match &self.body {
+            Some(body @ (Body::Text(_) | Body::Ndjson(_) | Body::GraphQL { .. } | Body::SaveToFile { .. })) => {
+                body.body_bytes(vars)
+            }
+            Some(Body::Multipart { boundary, parts }) => {
+                Some(multipart_bytes(boundary, parts, vars)?)
+            }
+            Some(Body::LoadFromFile { process_variables, filepath, .. }) => {
+                Some(read_body_file(*process_variables, filepath, vars)?)
+            }
+            None => None,
+        };
+
+        Ok(match body {
+            Some(body) if !body.is_empty() => builder.body(body),
+            _ => builder,
+        })
+    }
+}
+
+/// Read a body file, rendering the variables in it for `<@`
+fn read_body_file(process_variables: bool, filepath: &Template, vars: &RestVariables) -> anyhow::Result<Vec<u8>> {
+    let path = filepath.render(vars);
+    let contents = fs::read(&path)
+        .context(format!("Error reading request body file {path:?}"))?;
+    match process_variables {
+        true => {
+            let text = String::from_utf8(contents)
+                .context(format!("Request body file {path:?} is not valid UTF-8"))?;
+            Ok(Template::new(&text).render(vars).into_bytes())
+        }
+        false => Ok(contents),
+    }
+}
+
+/// Build a multipart body with the request's own boundary so it matches the `Content-Type`
+fn multipart_bytes(boundary: &str, parts: &[MultipartPart], vars: &RestVariables) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for part in parts {
+        bytes.extend(format!("--{boundary}\r\n").into_bytes());
+        for (name, value) in &part.headers {
+            bytes.extend(format!("{name}: {}\r\n", value.render(vars)).into_bytes());
+        }
+        bytes.extend(b"\r\n");
+        match &part.content {
+            MultipartContent::Text(text) => bytes.extend(text.render(vars).into_bytes()),
+            MultipartContent::LoadFromFile { process_variables, filepath, .. } => {
+                bytes.extend(read_body_file(*process_variables, filepath, vars)?)
+            }
+        }
+        bytes.extend(b"\r\n");
+    }
+    bytes.extend(format!("--{boundary}--\r\n").into_bytes());
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RestFlavor, RestFormat};
+    use indoc::indoc;
+
+    #[test]
+    fn to_reqwest_test() {
+        let text = indoc! {r#"
+            @HOST = https://example.com
+            @token = abc123
+
+            ### Create
+            POST {{HOST}}/users?tags[]=a&tags[]=b&page={{page:1}}#ignored HTTP/1.1
+            Content-Type: application/json
+            Content-Length: 999
+            Authorization: Bearer {{token}}
+
+            {"name": "{{name}}"}
+
+            >> ./created.json
+
+            ### Basic
+            GET {{HOST}}/basic HTTP/1.1
+            Authorization: Basic dXNlcjpwYXNz
+        "#};
+        let format = RestFormat::parse(text, RestFlavor::Jetbrains).unwrap();
+        let mut vars = format.variables.clone();
+        vars.insert("name".into(), Template::new("Ben"));
+
+        let create = &format.requests[0];
+        let request = create.to_reqwest(&vars).unwrap();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(
+            request.url().as_str(),
+            "https://example.com/users?tags%5B%5D=a&tags%5B%5D=b&page=1",
+        );
+        assert_eq!(request.headers()["content-type"], "application/json");
+        assert_eq!(request.headers()["authorization"], "Bearer abc123");
+        assert!(request.headers().get("content-length").is_none());
+        let body = request.body().unwrap().as_bytes().unwrap();
+        assert_eq!(body, br#"{"name": "Ben"}"#);
+        // The response target isn't part of the request
+        assert_eq!(create.body.as_ref().unwrap().save_target(&vars).unwrap(), "./created.json");
+
+        let request = format.requests[1].to_reqwest(&vars).unwrap();
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(request.headers()["authorization"], "Basic dXNlcjpwYXNz");
+        assert!(request.body().is_none());
+
+        let path = std::env::temp_dir().join(format!("rest_parser_reqwest_{}.json", std::process::id()));
+        fs::write(&path, r#"{"token": "{{token}}"}"#).unwrap();
+        let text = format!("POST https://example.com/upload HTTP/1.1\n\n<@ {}", path.display());
+        let upload = RestFormat::parse(&text, RestFlavor::Jetbrains).unwrap();
+        let request = upload.requests[0].to_reqwest(&vars).unwrap();
+        assert_eq!(request.body().unwrap().as_bytes().unwrap(), br#"{"token": "abc123"}"#);
+        fs::remove_file(&path).unwrap();
+        assert!(upload.requests[0].to_reqwest(&vars).is_err());
+
+        let mut missing = format.requests[1].clone();
+        missing.url = Template::new("{{nope}}/path");
+        assert!(missing.to_reqwest(&vars).is_err());
+    }
+}
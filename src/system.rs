@@ -0,0 +1,221 @@
+//! Dynamic system variables like `{{$guid}}` and `{{$randomInt 1 10}}`
+//! They are resolved every time a template is rendered
+//! The generated ones (`$guid`, `$timestamp`, `$randomInt`, `$datetime`) need the `system-vars` feature
+//! `{{$processEnv HOME}}` reads an environment variable of the process
+//! `{{$dotenv KEY}}` reads the `.env` file next to the request file, see `RestFormat::dotenv_variables`
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Context;
+#[cfg(feature = "system-vars")]
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta, TimeZone, Utc};
+use indexmap::IndexMap;
+#[cfg(feature = "system-vars")]
+use rand::Rng;
+
+use crate::template::Template;
+use crate::RestVariables;
+
+/// Reads an environment variable: `{{$processEnv HOME}}`
+pub(crate) const PROCESS_ENV: &str = "processEnv";
+
+/// Reads a key of the `.env` file: `{{$dotenv API_KEY}}`
+pub(crate) const DOTENV: &str = "dotenv";
+
+/// The file `$dotenv` variables are read from, in the directory of the request file
+pub const DOTENV_FILE_NAME: &str = ".env";
+
+/// The name a `.env` value has in the variables while rendering: `$dotenv API_KEY`
+/// `@` variables can't start with `$` so they never clash
+pub(crate) fn dotenv_variable_name(key: &str) -> String {
+    format!("${DOTENV} {key}")
+}
+
+/// The `.env` value for the arguments of `{{$dotenv KEY}}` with its name in the variables
+/// Both `Template::render` and `RestVariablesExt::resolve_all` look it up here
+pub(crate) fn dotenv_entry<'a>(variables: &'a RestVariables, args: &[String]) -> Option<(&'a String, &'a Template)> {
+    variables.get_key_value(&dotenv_variable_name(args.first()?))
+}
+
+/// Resolve a system variable (without the leading `$`) with its arguments
+/// Returns `None` for unknown variables or invalid arguments
+/// and for the generated ones without the `system-vars` feature
+pub fn resolve_system_variable(name: &str, args: &[String]) -> Option<String> {
+    match name {
+        #[cfg(feature = "system-vars")]
+        "guid" | "uuid" => Some(uuid::Uuid::new_v4().to_string()),
+        #[cfg(feature = "system-vars")]
+        "timestamp" => {
+            let now = Utc::now().checked_add_signed(parse_offset(args)?)?;
+            Some(now.timestamp().to_string())
+        }
+        #[cfg(feature = "system-vars")]
+        "randomInt" => {
+            let min: i64 = args.first()?.parse().ok()?;
+            let max: i64 = args.get(1)?.parse().ok()?;
+            if min >= max {
+                return None;
+            }
+            Some(rand::thread_rng().gen_range(min..max).to_string())
+        }
+        #[cfg(feature = "system-vars")]
+        "datetime" => {
+            let (format, offset) = args.split_first()?;
+            let now = Utc::now().checked_add_signed(parse_offset(offset)?)?;
+            format_datetime(&now, format)
+        }
+        #[cfg(feature = "system-vars")]
+        "localDatetime" => {
+            let (format, offset) = args.split_first()?;
+            let now = Local::now().checked_add_signed(parse_offset(offset)?)?;
+            format_datetime(&now, format)
+        }
+        // VSCode writes the name as `%NAME`
+        PROCESS_ENV => {
+            let [name] = args else { return None };
+            std::env::var(name.trim_start_matches('%')).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Parse the `KEY=value` lines of a `.env` file
+/// Blank lines, `#` comments and an `export ` prefix are skipped, quotes around a value are removed
+pub fn parse_dotenv(text: &str) -> IndexMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let value = value.trim();
+            let unquoted = ['"', '\'']
+                .iter()
+                .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+                .unwrap_or(value);
+            Some((key.trim().to_string(), unquoted.to_string()))
+        })
+        .collect()
+}
+
+/// Load the `.env` file in `dir`
+/// A missing file is not an error and returns `None`
+pub fn load_dotenv(dir: impl AsRef<Path>) -> anyhow::Result<Option<IndexMap<String, String>>> {
+    let path = dir.as_ref().join(DOTENV_FILE_NAME);
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(Some(parse_dotenv(&text))),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(format!("Error reading {path:?}")),
+    }
+}
+
+#[cfg(feature = "system-vars")]
+fn format_datetime<Tz: TimeZone>(datetime: &DateTime<Tz>, format: &str) -> Option<String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    match format {
+        "rfc1123" => Some(
+            datetime
+                .with_timezone(&Utc)
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        ),
+        "iso8601" => Some(datetime.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        _ => None,
+    }
+}
+
+/// An optional offset from now: `1 d`, `-2 h`
+/// Units are `ms`, `s`, `m`, `h`, `d` and `w`, an offset out of range is `None`
+#[cfg(feature = "system-vars")]
+fn parse_offset(args: &[String]) -> Option<TimeDelta> {
+    let [amount, unit] = args else {
+        return args.is_empty().then_some(TimeDelta::zero());
+    };
+    let amount: i64 = amount.parse().ok()?;
+    match unit.as_str() {
+        "ms" => TimeDelta::try_milliseconds(amount),
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "system-vars")]
+    fn resolve_system_variable_test() {
+        let guid = resolve_system_variable("guid", &[]).unwrap();
+        assert!(uuid::Uuid::parse_str(&guid).is_ok());
+        assert_ne!(guid, resolve_system_variable("guid", &[]).unwrap());
+
+        let timestamp: i64 = resolve_system_variable("timestamp", &[]).unwrap().parse().unwrap();
+        assert!((timestamp - Utc::now().timestamp()).abs() < 5);
+        let tomorrow: i64 = resolve_system_variable("timestamp", &args(&["1", "d"])).unwrap().parse().unwrap();
+        assert!((tomorrow - timestamp - 86400).abs() < 5);
+
+        for _ in 0..20 {
+            let value: i64 = resolve_system_variable("randomInt", &args(&["1", "10"])).unwrap().parse().unwrap();
+            assert!((1..10).contains(&value));
+        }
+        assert_eq!(resolve_system_variable("randomInt", &args(&["10", "1"])), None);
+        assert_eq!(resolve_system_variable("randomInt", &[]), None);
+
+        let rfc1123 = resolve_system_variable("datetime", &args(&["rfc1123"])).unwrap();
+        assert!(rfc1123.ends_with(" GMT"));
+        assert!(DateTime::parse_from_rfc2822(&rfc1123.replace("GMT", "+0000")).is_ok());
+        let iso8601 = resolve_system_variable("datetime", &args(&["iso8601", "-1", "h"])).unwrap();
+        assert!(DateTime::parse_from_rfc3339(&iso8601).is_ok());
+        assert_eq!(resolve_system_variable("datetime", &args(&["nope"])), None);
+
+        // Offsets too large for a date are invalid instead of panicking
+        assert_eq!(resolve_system_variable("timestamp", &args(&["99999999999999", "d"])), None);
+        assert_eq!(resolve_system_variable("timestamp", &args(&["-9999999999", "w"])), None);
+        assert_eq!(resolve_system_variable("timestamp", &args(&["100000000", "d"])), None);
+        assert_eq!(resolve_system_variable("datetime", &args(&["iso8601", "9999999999", "d"])), None);
+        assert_eq!(resolve_system_variable("localDatetime", &args(&["rfc1123", "9999999999", "d"])), None);
+
+        assert_eq!(resolve_system_variable("unknown", &[]), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "system-vars"))]
+    fn generated_system_variable_without_feature_test() {
+        assert_eq!(resolve_system_variable("guid", &[]), None);
+        assert_eq!(resolve_system_variable("randomInt", &args(&["1", "10"])), None);
+    }
+
+    #[test]
+    fn parse_dotenv_test() {
+        let text = "# Secrets\nAPI_KEY=abc123\n\nexport HOST = https://example.com\nQUOTED=\"a b\"\nSINGLE='c=d'\nnot a pair\n";
+        let values = parse_dotenv(text);
+        assert_eq!(values.len(), 4);
+        assert_eq!(values["API_KEY"], "abc123");
+        assert_eq!(values["HOST"], "https://example.com");
+        assert_eq!(values["QUOTED"], "a b");
+        assert_eq!(values["SINGLE"], "c=d");
+    }
+
+    #[test]
+    fn process_env_test() {
+        // Read a variable Cargo already set, `set_var` isn't safe while tests run in parallel
+        let name = env!("CARGO_PKG_NAME");
+        assert_eq!(resolve_system_variable("processEnv", &args(&["CARGO_PKG_NAME"])).as_deref(), Some(name));
+        assert_eq!(resolve_system_variable("processEnv", &args(&["%CARGO_PKG_NAME"])).as_deref(), Some(name));
+        assert_eq!(resolve_system_variable("processEnv", &args(&["REST_PARSER_PROCESS_ENV_UNSET"])), None);
+        assert_eq!(resolve_system_variable("processEnv", &[]), None);
+    }
+}
@@ -1,19 +1,74 @@
 use std::str::FromStr;
 use anyhow::{Error, anyhow};
 use nom::{
-    bytes::{complete::tag, streaming::take_until}, character::complete::space0, IResult
+    bytes::{complete::tag, streaming::take_until}, character::complete::space0,
+    combinator::{map_opt, opt}, sequence::preceded, IResult
 };
 use crate::RestVariables;
 
-use super::lexer::parse_variable_identifier;
+use super::lexer::{parse_system_variable_identifier, parse_variable_identifier};
+use super::system::{dotenv_entry, resolve_system_variable, DOTENV, PROCESS_ENV};
 use std::fmt;
 
 pub type TemplateMap = indexmap::IndexMap<String, Template>;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TemplatePart {
     Text(String),
-    Variable(String),
+    /// `{{name}}` or with a fallback for when it's undefined: `{{name:default}}`
+    Variable {
+        name: String,
+        default: Option<String>,
+        /// The text between the braces as written so the spacing is kept: ` name ` for `{{ name }}`
+        /// Empty when it's unknown, for example in JSON from before it was added
+        #[cfg_attr(feature = "serde", serde(default))]
+        raw_inner: String,
+    },
+    /// A dynamic system variable resolved on render: `{{$randomInt 1 10}}`
+    /// The name doesn't include the `$`
+    System {
+        name: String,
+        args: Vec<String>,
+    },
+    /// A value from the response of an earlier named request: `{{login.response.body.$.token}}`
+    /// It can't be resolved while parsing, it's left for the consumer executing the requests
+    Response {
+        request: String,
+        source: ResponseSource,
+        /// A JSONPath or XPath for the body, the header name for headers
+        /// Empty when the whole body or all headers are referenced
+        path: String,
+    },
+}
+
+/// Which part of a response a `TemplatePart::Response` references
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResponseSource {
+    Body,
+    Headers,
+}
+
+impl ResponseSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseSource::Body => "body",
+            ResponseSource::Headers => "headers",
+        }
+    }
+}
+
+impl FromStr for ResponseSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "body" => Ok(ResponseSource::Body),
+            "headers" => Ok(ResponseSource::Headers),
+            _ => Err(anyhow!("Unknown response source: {s}")),
+        }
+    }
 }
 
 impl TemplatePart {
@@ -22,14 +77,89 @@ impl TemplatePart {
     }
 
     pub fn var(value: &str) -> Self {
-        TemplatePart::Variable(value.to_string())
+        TemplatePart::Variable {
+            name: value.to_string(),
+            default: None,
+            raw_inner: value.to_string(),
+        }
     }    
+
+    pub fn var_with_default(value: &str, default: &str) -> Self {
+        TemplatePart::Variable {
+            name: value.to_string(),
+            default: Some(default.to_string()),
+            raw_inner: format!("{value}{DEFAULT_SEPERATOR}{default}"),
+        }
+    }
+}
+
+/// A variable's spacing isn't part of its meaning, `{{ name }}` equals `{{name}}`
+impl PartialEq for TemplatePart {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TemplatePart::Text(text), TemplatePart::Text(other)) => text == other,
+            (
+                TemplatePart::Variable { name, default, .. },
+                TemplatePart::Variable { name: other_name, default: other_default, .. },
+            ) => name == other_name && default == other_default,
+            (
+                TemplatePart::System { name, args },
+                TemplatePart::System { name: other_name, args: other_args },
+            ) => name == other_name && args == other_args,
+            (
+                TemplatePart::Response { request, source, path },
+                TemplatePart::Response { request: other_request, source: other_source, path: other_path },
+            ) => request == other_request && source == other_source && path == other_path,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TemplatePart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // An unescaped `{{` would start a variable when parsed again
+            TemplatePart::Text(text) => write!(f, "{}", text.replace(VARIABLE_START, ESCAPED_VARIABLE_START)),
+            TemplatePart::Variable { name, default, raw_inner } if raw_inner.is_empty() => match default {
+                Some(default) => write!(f, "{VARIABLE_START}{name}{DEFAULT_SEPERATOR}{default}{VARIABLE_END}"),
+                None => write!(f, "{VARIABLE_START}{name}{VARIABLE_END}"),
+            },
+            TemplatePart::Variable { raw_inner, .. } => {
+                write!(f, "{VARIABLE_START}{raw_inner}{VARIABLE_END}")
+            }
+            TemplatePart::System { name, args } => {
+                write!(f, "{VARIABLE_START}{SYSTEM_SYMBOL}{name}")?;
+                for arg in args {
+                    match arg.contains(char::is_whitespace) {
+                        true => write!(f, " \"{arg}\"")?,
+                        false => write!(f, " {arg}")?,
+                    }
+                }
+                write!(f, "{VARIABLE_END}")
+            }
+            TemplatePart::Response { request, source, path } => {
+                let source = source.as_str();
+                write!(f, "{VARIABLE_START}{request}{RESPONSE_SEPERATOR}{source}")?;
+                if !path.is_empty() {
+                    write!(f, ".{path}")?;
+                }
+                write!(f, "{VARIABLE_END}")
+            }
+        }
+    }
 }
 
 const VARIABLE_START: &str = "{{";
 const VARIABLE_END: &str = "}}";
+/// `\{{` and `\}}` are literal braces instead of a variable
+const ESCAPED_VARIABLE_START: &str = "\\{{";
+const ESCAPED_VARIABLE_END: &str = "\\}}";
+const DEFAULT_SEPERATOR: &str = ":";
+const SYSTEM_SYMBOL: &str = "$";
+const RESPONSE_SEPERATOR: &str = ".response.";
 
 #[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Template {
     pub parts: Vec<TemplatePart>,
     pub raw: String,
@@ -48,36 +178,298 @@ impl Template {
 
     /// Takes a variable context and renders a template
     /// Useful if your application doesn't require variables and you want them rendered now
+    /// Undefined variables use their default or render as empty
+    /// System variables like `{{$guid}}` are resolved, unknown ones render as empty
+    /// Variables referencing other variables are expanded recursively,
+    /// a cyclic reference renders as empty instead of looping forever
+    /// Response references can't be resolved here and render as empty
     pub fn render(&self, variables: &RestVariables) -> String {
+        self.render_with_stack(variables, &mut vec![], &mut vec![])
+    }
+
+    /// Render like `render` but fail with the names of every undefined variable
+    /// that has no default, including ones referenced by other variables
+    /// An unset environment variable is missing as `$processEnv NAME`
+    pub fn try_render(&self, variables: &RestVariables) -> Result<String, Vec<String>> {
+        let mut missing = vec![];
+        let rendered = self.render_with_stack(variables, &mut vec![], &mut missing);
+        match missing.is_empty() {
+            true => Ok(rendered),
+            false => Err(missing),
+        }
+    }
+
+    /// Render like `render` but error with every undefined variable instead of rendering them empty
+    /// System variables like `{{$guid}}` and variables with a default count as defined,
+    /// `{{$processEnv NAME}}` only when the environment variable is set
+    pub fn render_strict(&self, variables: &RestVariables) -> anyhow::Result<String> {
+        self.try_render(variables)
+            .map_err(|missing| anyhow!("Undefined variables: {}", missing.join(", ")))
+    }
+
+    /// `stack` holds the names of the variables currently being expanded
+    /// `missing` collects undefined variables without a default
+    fn render_with_stack<'a>(
+        &self,
+        variables: &'a RestVariables,
+        stack: &mut Vec<&'a str>,
+        missing: &mut Vec<String>,
+    ) -> String {
         let mut built = "".to_string(); 
         for part in &self.parts {
-            built += match part {
-                TemplatePart::Variable(name) => match variables.get(name) {
-                    Some(value) => value.raw.as_str(),
-                    None => "",
+            match part {
+                TemplatePart::Variable { name, default, .. } => match variables.get_key_value(name) {
+                    Some((name, _)) if stack.contains(&name.as_str()) => {}
+                    Some((name, value)) => {
+                        stack.push(name);
+                        built += &value.render_with_stack(variables, stack, missing);
+                        stack.pop();
+                    }
+                    None => match default {
+                        Some(default) => built += default,
+                        None if !missing.contains(name) => missing.push(name.clone()),
+                        None => {}
+                    },
+                },
+                // `.env` values are in the context, see `RestFormat::dotenv_variables`
+                TemplatePart::System { name, args } if name == DOTENV => {
+                    if let Some((_, value)) = dotenv_entry(variables, args) {
+                        built += &value.render_with_stack(variables, stack, missing);
+                    }
+                }
+                TemplatePart::System { name, args } => match resolve_system_variable(name, args) {
+                    Some(value) => built += &value,
+                    // An unset environment variable is missing like an undefined variable
+                    None if name == PROCESS_ENV => {
+                        let missing_name = format!("{SYSTEM_SYMBOL}{name} {}", args.join(" ")).trim_end().to_string();
+                        if !missing.contains(&missing_name) {
+                            missing.push(missing_name);
+                        }
+                    }
+                    None => {}
                 },
-                TemplatePart::Text(text) => text.as_str(),
+                TemplatePart::Response { .. } => {}
+                TemplatePart::Text(text) => built += text,
             };
         }
         built
     }
+
+    /// Render the variables that are defined and keep the rest as variables
+    /// `{{HOST}}/{{id}}` with `HOST` defined -> `https://example.com/{{id}}`
+    pub fn render_partial(&self, variables: &RestVariables) -> Template {
+        let mut built = "".to_string();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Variable { name, .. } => match variables.get(name) {
+                    Some(value) => built += &value.raw,
+                    None => built += &part.to_string(),
+                },
+                TemplatePart::System { .. } | TemplatePart::Response { .. } | TemplatePart::Text(_) => {
+                    built += &part.to_string()
+                }
+            }
+        }
+        Template::new(&built)
+    }
+
+    /// Resolve the system variables and keep everything else
+    /// `{{$uuid}}-{{name}}` -> `3f1c...-{{name}}`, unknown system variables are kept
+    #[cfg(feature = "system-vars")]
+    pub fn resolve_system_variables(&self) -> Template {
+        let mut built = "".to_string();
+        for part in &self.parts {
+            match part {
+                TemplatePart::System { name, args } => match resolve_system_variable(name, args) {
+                    Some(value) => built += &value,
+                    None => built += &part.to_string(),
+                },
+                TemplatePart::Text(_) | TemplatePart::Variable { .. } | TemplatePart::Response { .. } => {
+                    built += &part.to_string()
+                }
+            }
+        }
+        Template::new(&built)
+    }
+
+    /// Trim the whitespace around the template and keep the variables
+    /// `  {{HOST}}/x  ` -> `{{HOST}}/x`, whitespace next to a leading or trailing variable is kept
+    pub fn trim(&self) -> Template {
+        let mut parts = self.parts.clone();
+        if let Some(TemplatePart::Text(text)) = parts.first_mut() {
+            *text = text.trim_start().to_string();
+        }
+        if let Some(TemplatePart::Text(text)) = parts.last_mut() {
+            *text = text.trim_end().to_string();
+        }
+        parts.retain(|part| !matches!(part, TemplatePart::Text(text) if text.is_empty()));
+        let raw = parts.iter().map(TemplatePart::to_string).collect();
+        Template { parts, raw }
+    }
+
+    /// The template uses a `.env` value: `{{$dotenv API_KEY}}`
+    pub(crate) fn uses_dotenv(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, TemplatePart::System { name, .. } if name == DOTENV))
+    }
+
+    /// The names of every variable referenced in the template, in order
+    pub fn variable_names(&self) -> Vec<&str> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                TemplatePart::Variable { name, .. } => Some(name.as_str()),
+                TemplatePart::Text(_)
+                | TemplatePart::System { .. }
+                | TemplatePart::Response { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Every variable referenced in the template with its default literal, in order
+    /// Variables without a default are required
+    pub fn variables_with_defaults(&self) -> Vec<(String, Option<String>)> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                TemplatePart::Variable { name, default, .. } => Some((name.clone(), default.clone())),
+                TemplatePart::Text(_)
+                | TemplatePart::System { .. }
+                | TemplatePart::Response { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Split the parts at the first variable (or system variable)
+    /// Returns the static prefix and the remaining parts starting with the variable
+    /// `/a/{{id}}/b` -> (`/a/`, `{{id}}/b`)
+    pub fn split_at_variable(&self) -> (&[TemplatePart], &[TemplatePart]) {
+        let index = self.parts
+            .iter()
+            .position(|part| !matches!(part, TemplatePart::Text(_)))
+            .unwrap_or(self.parts.len());
+        self.parts.split_at(index)
+    }
+
+    /// Check if a specific variable is referenced in the template
+    pub fn contains_variable(&self, name: &str) -> bool {
+        self.variable_names().contains(&name)
+    }
+
+    /// Compare only the parsed parts, ignoring how `raw` was written
+    /// `{{ name }}` and `{{name}}` are semantically equal
+    pub fn semantic_eq(&self, other: &Template) -> bool {
+        self.parts == other.parts
+    }
+
+    /// Borrow the value if the template has no variables
+    /// Avoids allocating a rendered `String` for static values
+    pub fn as_static_str(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [] => Some(""),
+            [TemplatePart::Text(text)] => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// Render every template in the map like `Template::try_render`
+/// Fails with the undefined variables across the whole map, each name once
+pub fn try_render_map(
+    map: &TemplateMap,
+    variables: &RestVariables,
+) -> Result<indexmap::IndexMap<String, String>, Vec<String>> {
+    let mut rendered = indexmap::IndexMap::new();
+    let mut missing: Vec<String> = vec![];
+    for (key, template) in map {
+        match template.try_render(variables) {
+            Ok(value) => {
+                rendered.insert(key.clone(), value);
+            }
+            Err(names) => {
+                for name in names {
+                    if !missing.contains(&name) {
+                        missing.push(name);
+                    }
+                }
+            }
+        }
+    }
+    match missing.is_empty() {
+        true => Ok(rendered),
+        false => Err(missing),
+    }
 }
 
 impl FromStr for Template {
     type Err = Error; 
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        fn parse_variable(inp: &str) -> IResult<&str, &str> {
+        fn parse_system_variable(inp: &str) -> IResult<&str, TemplatePart> {
             let (inp, _) = tag(VARIABLE_START)(inp)?;
             let (inp, _) = space0(inp)?;
+            let (inp, var) = parse_system_variable_identifier(inp)?;
+            let (inp, args) = take_until(VARIABLE_END)(inp)?;
+            let (inp, _) = tag(VARIABLE_END)(inp)?;
+
+            let part = TemplatePart::System {
+                name: var.trim_start_matches(SYSTEM_SYMBOL).to_string(),
+                args: split_args(args),
+            };
+            Ok((inp, part))
+        }
+
+        fn parse_response_variable(inp: &str) -> IResult<&str, TemplatePart> {
+            let (inp, _) = tag(VARIABLE_START)(inp)?;
+            let (inp, reference) = map_opt(
+                take_until(VARIABLE_END),
+                |reference: &str| parse_response_reference(reference.trim()),
+            )(inp)?;
+            let (inp, _) = tag(VARIABLE_END)(inp)?;
+            Ok((inp, reference))
+        }
+
+        fn parse_variable(inp: &str) -> IResult<&str, TemplatePart> {
+            let (inp, _) = tag(VARIABLE_START)(inp)?;
+            let inner_start = inp;
+            let (inp, _) = space0(inp)?;
             let (inp, var) = parse_variable_identifier(inp)?;
             let (inp, _) = space0(inp)?;
+            // Everything up to the end is the default, it can contain colons
+            let (inp, default) = opt(preceded(
+                tag(DEFAULT_SEPERATOR),
+                take_until(VARIABLE_END),
+            ))(inp)?;
+            let raw_inner = &inner_start[..inner_start.len() - inp.len()];
             let (inp, _) = tag(VARIABLE_END)(inp)?;
-            Ok((inp, var))
+
+            let part = TemplatePart::Variable {
+                name: var.to_string(),
+                default: default.map(|default| default.trim().to_string()),
+                raw_inner: raw_inner.to_string(),
+            };
+            Ok((inp, part))
         }
 
+        /// Text runs until a variable or an escaped brace
         fn parse_text(inp: &str) -> IResult<&str, &str> {
-            take_until(VARIABLE_START)(inp)
+            let end = [VARIABLE_START, ESCAPED_VARIABLE_START, ESCAPED_VARIABLE_END]
+                .iter()
+                .filter_map(|tag| inp.find(tag))
+                .min();
+            match end {
+                Some(end) => Ok((&inp[end..], &inp[..end])),
+                None => take_until(VARIABLE_START)(inp),
+            }
+        }
+
+        /// Escaped braces and the text around them are joined into one part
+        fn push_text(parts: &mut Vec<TemplatePart>, text: &str) {
+            match parts.last_mut() {
+                Some(TemplatePart::Text(last)) => last.push_str(text),
+                _ => parts.push(TemplatePart::text(text)),
+            }
         }
 
         let mut parts: Vec<TemplatePart> = vec![];
@@ -85,9 +477,21 @@ impl FromStr for Template {
 
         while !value.is_empty() {
             let test_val = &value.clone();
-            if let Ok((new_val, var)) = parse_variable(test_val) {
+            let escaped = [(ESCAPED_VARIABLE_START, VARIABLE_START), (ESCAPED_VARIABLE_END, VARIABLE_END)]
+                .into_iter()
+                .find_map(|(escape, brace)| Some((test_val.strip_prefix(escape)?, brace)));
+            if let Some((new_val, brace)) = escaped {
                 value = new_val.to_string();
-                parts.push(TemplatePart::var(var));
+                push_text(&mut parts, brace);
+                continue;
+            }
+
+            let part = parse_response_variable(test_val)
+                .or_else(|_| parse_variable(test_val))
+                .or_else(|_| parse_system_variable(test_val));
+            if let Ok((new_val, part)) = part {
+                value = new_val.to_string();
+                parts.push(part);
                 continue;
             } 
 
@@ -97,11 +501,11 @@ impl FromStr for Template {
                 } 
 
                 value = new_val.to_string();
-                parts.push(TemplatePart::text(text));
+                push_text(&mut parts, text);
                 continue;
             }
            
-            parts.push(TemplatePart::text(&value));
+            push_text(&mut parts, &value);
             break; 
         }
 
@@ -113,6 +517,39 @@ impl FromStr for Template {
     }
 }
 
+/// `login.response.body.$.token` or `login.response.headers.X-Auth`
+/// The request name can't be mistaken for a variable since `$` and `*` paths aren't identifiers
+fn parse_response_reference(reference: &str) -> Option<TemplatePart> {
+    let (request, rest) = reference.split_once(RESPONSE_SEPERATOR)?;
+    let (source, path) = rest.split_once('.').unwrap_or((rest, ""));
+
+    let is_identifier = matches!(parse_variable_identifier(request), Ok(("", _)));
+    if !is_identifier {
+        return None;
+    }
+
+    Some(TemplatePart::Response {
+        request: request.to_string(),
+        source: source.parse().ok()?,
+        path: path.to_string(),
+    })
+}
+
+/// Split whitespace seperated arguments, double quotes group words: `iso8601 "a b"`
+fn split_args(input: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut rest = input.trim();
+    while !rest.is_empty() {
+        let (arg, remaining) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        args.push(arg.to_string());
+        rest = remaining.trim_start();
+    }
+    args
+}
+
 impl From<String> for Template {
     fn from(value: String) -> Self {
         Template::new(&value)
@@ -133,7 +570,7 @@ mod tests {
     fn test_parse_template() {
         use indexmap::IndexMap; 
         fn var(t: &str) -> TemplatePart {
-            TemplatePart::Variable(t.into())
+            TemplatePart::var(t)
         } 
 
         fn text(t: &str) -> TemplatePart {
@@ -172,6 +609,295 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn render_partial_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("HOST".into(), Template::new("https://example.com"));
+
+        let template = Template::new("{{HOST}}/users/{{ id }}");
+        let rendered = template.render_partial(&vars);
+        // The variable keeps its spacing
+        assert_eq!(rendered.raw, "https://example.com/users/{{ id }}");
+        assert_eq!(rendered.variable_names(), vec!["id"]);
+    }
+
+    #[test]
+    fn variable_names_test() {
+        let template = Template::new("{{HOST}}/users/{{ id }}?q={{HOST}}");
+        assert_eq!(template.variable_names(), vec!["HOST", "id", "HOST"]);
+
+        assert!(template.contains_variable("HOST"));
+        assert!(template.contains_variable("id"));
+        assert!(!template.contains_variable("users"));
+        assert!(!template.contains_variable("ho"));
+
+        let template = Template::new("https://example.com");
+        assert!(template.variable_names().is_empty());
+        assert!(!template.contains_variable("HOST"));
+    }
+
+    #[test]
+    fn default_value_test() {
+        let template = Template::new("{{host:http://localhost:8080}}/users/{{ id : 1 }}?q={{q}}");
+        assert_eq!(template.parts, vec![
+            TemplatePart::var_with_default("host", "http://localhost:8080"),
+            TemplatePart::text("/users/"),
+            TemplatePart::var_with_default("id", "1"),
+            TemplatePart::text("?q="),
+            TemplatePart::var("q"),
+        ]);
+        assert_eq!(template.render(&RestVariables::new()), "http://localhost:8080/users/1?q=");
+
+        let mut vars = RestVariables::new();
+        vars.insert("host".into(), Template::new("https://example.com"));
+        assert_eq!(template.render(&vars), "https://example.com/users/1?q=");
+        assert_eq!(template.render_partial(&vars).raw, "https://example.com/users/{{ id : 1 }}?q={{q}}");
+
+        // An empty default is still a default
+        let template = Template::new("a{{b:}}c");
+        assert_eq!(template.parts[1], TemplatePart::var_with_default("b", ""));
+        assert_eq!(template.to_string(), "a{{b:}}c");
+    }
+
+    #[test]
+    fn recursive_render_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("scheme".into(), Template::new("https"));
+        vars.insert("base".into(), Template::new("{{scheme}}://api.example.com"));
+        vars.insert("users".into(), Template::new("{{base}}/users"));
+        assert_eq!(Template::new("{{users}}/1").render(&vars), "https://api.example.com/users/1");
+
+        // The same variable can be used twice without being a cycle
+        vars.insert("both".into(), Template::new("{{scheme}}-{{scheme}}"));
+        assert_eq!(Template::new("{{both}}").render(&vars), "https-https");
+
+        vars.insert("a".into(), Template::new("a{{b}}"));
+        vars.insert("b".into(), Template::new("b{{a}}"));
+        vars.insert("me".into(), Template::new("[{{me}}]"));
+        assert_eq!(Template::new("{{a}}").render(&vars), "ab");
+        assert_eq!(Template::new("{{me}}").render(&vars), "[]");
+    }
+
+    #[test]
+    fn system_variable_test() {
+        let template = Template::new("{{$guid}}/{{ $randomInt 1 10 }}/{{$datetime \"iso8601\" 1 d}}/{{$nope}}");
+        assert_eq!(template.parts[0], TemplatePart::System { name: "guid".into(), args: vec![] });
+        assert_eq!(template.parts[2], TemplatePart::System {
+            name: "randomInt".into(),
+            args: vec!["1".into(), "10".into()],
+        });
+        assert_eq!(template.parts[4], TemplatePart::System {
+            name: "datetime".into(),
+            args: vec!["iso8601".into(), "1".into(), "d".into()],
+        });
+        assert!(template.variable_names().is_empty());
+
+        let rendered = template.render(&RestVariables::new());
+        let segments: Vec<&str> = rendered.split('/').collect();
+        if cfg!(feature = "system-vars") {
+            assert_eq!(segments[0].len(), 36);
+            let random: i64 = segments[1].parse().unwrap();
+            assert!((1..10).contains(&random));
+            assert!(segments[2].ends_with('Z'));
+        } else {
+            assert_eq!(rendered, "///");
+        }
+        // Unknown system variables render as empty
+        assert_eq!(segments[3], "");
+
+        // Partial rendering keeps them dynamic
+        assert_eq!(template.render_partial(&RestVariables::new()).raw, "{{$guid}}/{{$randomInt 1 10}}/{{$datetime iso8601 1 d}}/{{$nope}}");
+    }
+
+    #[test]
+    fn response_variable_test() {
+        let template = Template::new("Bearer {{login.response.body.$.token}}");
+        assert_eq!(template.parts[1], TemplatePart::Response {
+            request: "login".into(),
+            source: ResponseSource::Body,
+            path: "$.token".into(),
+        });
+
+        let template = Template::new("{{ login.response.headers.X-Auth }}|{{login.response.body}}|{{id}}");
+        assert_eq!(template.parts[0], TemplatePart::Response {
+            request: "login".into(),
+            source: ResponseSource::Headers,
+            path: "X-Auth".into(),
+        });
+        assert_eq!(template.parts[2], TemplatePart::Response {
+            request: "login".into(),
+            source: ResponseSource::Body,
+            path: "".into(),
+        });
+        assert_eq!(template.variable_names(), vec!["id"]);
+
+        // Without response data they render as empty and stay unresolved when partially rendering
+        let variables = RestVariables::from_iter([("id".to_string(), Template::new("1"))]);
+        assert_eq!(template.render(&variables), "||1");
+        assert_eq!(
+            template.render_partial(&variables).raw,
+            "{{login.response.headers.X-Auth}}|{{login.response.body}}|1",
+        );
+
+        // Not a response source, so it's a regular dotted variable
+        let template = Template::new("{{config.response.timeout}}");
+        assert_eq!(template.parts[0], TemplatePart::var("config.response.timeout"));
+    }
+
+    #[test]
+    fn variables_with_defaults_test() {
+        let template = Template::new("{{HOST}}/users/{{ id }}");
+        assert_eq!(template.variables_with_defaults(), vec![
+            ("HOST".to_string(), None),
+            ("id".to_string(), None),
+        ]);
+
+        let template = Template::new("{{host:localhost}}:{{port:8080}}/{{path}}");
+        assert_eq!(template.variables_with_defaults(), vec![
+            ("host".to_string(), Some("localhost".to_string())),
+            ("port".to_string(), Some("8080".to_string())),
+            ("path".to_string(), None),
+        ]);
+        assert!(Template::new("/users").variables_with_defaults().is_empty());
+    }
+
+    #[test]
+    fn split_at_variable_test() {
+        let template = Template::new("{{HOST}}/a/b");
+        let (prefix, rest) = template.split_at_variable();
+        assert!(prefix.is_empty());
+        assert_eq!(rest, &[TemplatePart::var("HOST"), TemplatePart::text("/a/b")]);
+
+        let template = Template::new("/a/{{id}}/b");
+        let (prefix, rest) = template.split_at_variable();
+        assert_eq!(prefix, &[TemplatePart::text("/a/")]);
+        assert_eq!(rest, &[TemplatePart::var("id"), TemplatePart::text("/b")]);
+
+        let template = Template::new("/a/b");
+        let (prefix, rest) = template.split_at_variable();
+        assert_eq!(prefix, &[TemplatePart::text("/a/b")]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn variable_spacing_round_trip_test() {
+        for raw in ["{{name}}", "{{ name }}", "{{  name}}", "{{name:default}}", "{{ port : 8080 }}"] {
+            let template = Template::new(raw);
+            let rebuilt: String = template.parts.iter().map(TemplatePart::to_string).collect();
+            assert_eq!(rebuilt, raw);
+            assert_eq!(template.render_partial(&RestVariables::new()).raw, raw);
+        }
+
+        let spaced = Template::new("{{ name }}");
+        assert_eq!(spaced.parts[0], TemplatePart::var("name"));
+        assert!(matches!(&spaced.parts[0], TemplatePart::Variable { raw_inner, .. } if raw_inner == " name "));
+        assert_eq!(TemplatePart::var_with_default("port", "8080").to_string(), "{{port:8080}}");
+    }
+
+    #[test]
+    fn semantic_eq_test() {
+        let spaced = Template::new("{{ HOST }}/users/{{id }}");
+        let unspaced = Template::new("{{HOST}}/users/{{id}}");
+        assert_ne!(spaced, unspaced);
+        assert!(spaced.semantic_eq(&unspaced));
+        assert!(unspaced.semantic_eq(&spaced));
+
+        let different = Template::new("{{HOST}}/user/{{id}}");
+        assert!(!spaced.semantic_eq(&different));
+
+        // Whitespace outside of braces still matters
+        assert!(!Template::new("a {{b}}").semantic_eq(&Template::new("a{{b}}")));
+    }
+
+    #[test]
+    fn as_static_str_test() {
+        let static_template = Template::new("application/json");
+        assert_eq!(static_template.as_static_str(), Some("application/json"));
+        assert_eq!(Template::new("").as_static_str(), Some(""));
+
+        assert_eq!(Template::new("{{HOST}}/users").as_static_str(), None);
+        assert_eq!(Template::new("Bearer {{$guid}}").as_static_str(), None);
+    }
+
+    #[test]
+    fn trim_test() {
+        let trimmed = Template::new("  {{HOST}}/x  ").trim();
+        assert_eq!(trimmed.raw, "{{HOST}}/x");
+        assert_eq!(trimmed.parts, vec![TemplatePart::var("HOST"), TemplatePart::text("/x")]);
+
+        assert_eq!(Template::new(" Bearer {{token}} ").trim().raw, "Bearer {{token}}");
+        assert_eq!(Template::new("{{a}} {{b}}").trim().raw, "{{a}} {{b}}");
+        assert_eq!(Template::new("\t https://example.com \r\n").trim(), Template::new("https://example.com"));
+
+        let blank = Template::new("   ").trim();
+        assert_eq!(blank.as_static_str(), Some(""));
+    }
+
+    #[test]
+    fn escaped_braces_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("name".into(), Template::new("Ben"));
+
+        let template = Template::new(r"\{{literal\}} {{name}} \{{");
+        assert_eq!(template.parts, vec![
+            TemplatePart::text("{{literal}} "),
+            TemplatePart::var("name"),
+            TemplatePart::text(" {{"),
+        ]);
+        assert_eq!(template.raw, r"\{{literal\}} {{name}} \{{");
+        assert_eq!(template.render(&vars), "{{literal}} Ben {{");
+
+        // Rebuilt templates keep the escape so the braces stay literal
+        let partial = template.render_partial(&vars);
+        assert_eq!(partial.raw, r"\{{literal}} Ben \{{");
+        assert_eq!(partial.render(&vars), "{{literal}} Ben {{");
+        assert_eq!(Template::new(r"  \{{x}}  ").trim().raw, r"\{{x}}");
+    }
+
+    #[test]
+    fn try_render_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("HOST".into(), Template::new("https://{{domain}}"));
+        vars.insert("token".into(), Template::new("abc"));
+
+        assert_eq!(Template::new("Bearer {{token}}").try_render(&vars), Ok("Bearer abc".into()));
+        // Defaults and system variables are defined
+        assert!(Template::new("{{page:1}} {{$guid}}").try_render(&vars).unwrap().starts_with("1 "));
+        // Missing variables of referenced variables are included once
+        assert_eq!(
+            Template::new("{{HOST}}/{{id}}/{{domain}}").try_render(&vars),
+            Err(vec!["domain".to_string(), "id".to_string()]),
+        );
+
+        let mut map = TemplateMap::new();
+        map.insert("Authorization".into(), Template::new("Bearer {{token}}"));
+        map.insert("Accept".into(), Template::new("application/json"));
+        let rendered = try_render_map(&map, &vars).unwrap();
+        assert_eq!(rendered["Authorization"], "Bearer abc");
+        assert_eq!(rendered["Accept"], "application/json");
+
+        map.insert("X-Api-Key".into(), Template::new("{{api_key}}"));
+        assert_eq!(try_render_map(&map, &vars), Err(vec!["api_key".to_string()]));
+    }
+
+    #[test]
+    fn render_strict_test() {
+        let mut vars = RestVariables::new();
+        vars.insert("HOST".into(), Template::new("https://{{domain}}"));
+
+        let template = Template::new("{{$guid}} {{page:1}}");
+        assert!(template.render_strict(&vars).is_ok());
+
+        let err = Template::new("{{HOST}}/{{id}}").render_strict(&vars).unwrap_err();
+        assert_eq!(err.to_string(), "Undefined variables: domain, id");
+
+        // Cargo sets the package name for the tests, setting a variable here would race other tests
+        let template = Template::new("{{$processEnv CARGO_PKG_NAME}}/{{$processEnv REST_PARSER_RENDER_STRICT_UNSET}}");
+        assert_eq!(template.render(&vars), format!("{}/", env!("CARGO_PKG_NAME")));
+        let err = template.render_strict(&vars).unwrap_err();
+        assert_eq!(err.to_string(), "Undefined variables: $processEnv REST_PARSER_RENDER_STRICT_UNSET");
+    }
+
     #[test]
     fn can_parse_error() {
         // This should unclosed template error